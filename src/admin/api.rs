@@ -0,0 +1,462 @@
+use crate::audit;
+use crate::bans::{self, BanTarget, BANS};
+use crate::bot;
+use crate::config::{check_chat_message, Config};
+use crate::database::SHUTDOWN_TOKEN;
+use crate::database::{Database, DATABASE};
+use crate::metrics::METRICS;
+use crate::nicknames::{self, NICKNAMES};
+use crate::rooms::{PermanentRoom, PERMANENT_ROOMS};
+use crate::server::Server;
+use axum::extract::{Path, Request};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use eyre::{Result, WrapErr};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+
+// A small JSON API to look into and manage a running server. Every request needs the configured
+// bearer token.
+
+#[derive(Serialize)]
+struct UserInfo {
+    id: u32,
+    name: String,
+    nation: String,
+    /// None while the user isn't in a room
+    room_id: Option<u32>,
+    ip: IpAddr,
+    /// Unix timestamp in seconds
+    connected_at: u64,
+}
+
+#[derive(Serialize)]
+struct RoomInfo {
+    id: u32,
+    name: String,
+    nation: String,
+    users: Vec<u32>,
+    games: Vec<u32>,
+    /// Kept open while empty
+    permanent: bool,
+    protected: bool,
+    /// 0 for `rooms.max_users`
+    capacity: u32,
+    /// None for permanent rooms and ones everybody left
+    owner: Option<u32>,
+    topic: String,
+}
+
+#[derive(Serialize)]
+struct GameInfo {
+    id: u32,
+    name: String,
+    nation: String,
+    room_id: u32,
+    host_ip: IpAddr,
+    access: String,
+}
+
+#[derive(Serialize)]
+struct BanInfo {
+    /// An address, a CIDR range or a name pattern
+    target: String,
+    reason: String,
+    /// Unix timestamps in seconds
+    created: u64,
+    expires: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct BanRequest {
+    target: String,
+    #[serde(default)]
+    reason: String,
+    /// Lasts forever if left out
+    duration_secs: Option<u64>,
+    /// With a name, ban the address of whoever's online using it too
+    #[serde(default)]
+    ban_address: bool,
+}
+
+#[derive(Deserialize)]
+struct UnbanRequest {
+    target: String,
+}
+
+#[derive(Serialize)]
+struct NicknameInfo {
+    name: String,
+    /// Unix timestamp in seconds
+    registered: u64,
+}
+
+#[derive(Deserialize)]
+struct NicknameRequest {
+    name: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+struct UnregisterRequest {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct BroadcastRequest {
+    message: String,
+    /// Only send to this room, everyone gets it if left out
+    room_id: Option<u32>,
+}
+
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+impl From<eyre::Report> for ApiError {
+    fn from(e: eyre::Report) -> Self {
+        error!("Admin API request failed: {:?}", e);
+        ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    }
+}
+
+/// Binds the admin API and serves it until the server shuts down.
+pub async fn start(address: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(address)
+        .await
+        .wrap_err_with(|| format!("Unable to bind the admin API to {address}"))?;
+    info!("Admin API listening at {}", listener.local_addr()?);
+
+    tokio::spawn(async move {
+        let shutdown = async { SHUTDOWN_TOKEN.cancelled().await };
+        if let Err(e) = axum::serve(listener, router())
+            .with_graceful_shutdown(shutdown)
+            .await
+        {
+            error!("Admin API stopped: {}", e);
+        }
+    });
+
+    Ok(())
+}
+
+fn router() -> Router {
+    Router::new()
+        .route("/users", get(list_users))
+        .route("/users/{id}/kick", post(kick_user))
+        .route("/rooms", get(list_rooms))
+        .route("/rooms/{id}/close", post(close_room))
+        .route("/rooms/{id}/release", post(release_room))
+        .route(
+            "/rooms/permanent",
+            get(list_permanent_rooms).post(keep_room),
+        )
+        .route("/games", get(list_games))
+        .route("/games/{id}/close", post(close_game))
+        .route("/bans", get(list_bans).post(add_ban))
+        .route("/bans/remove", post(remove_ban))
+        .route("/nicknames", get(list_nicknames).post(set_nickname))
+        .route("/nicknames/remove", post(remove_nickname))
+        .route("/broadcast", post(broadcast))
+        .route("/metrics", get(metrics))
+        .layer(middleware::from_fn(authorize))
+}
+
+async fn authorize(request: Request, next: Next) -> Response {
+    let provided = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    // Read on every request so a reload can change the token
+    let token = &Config::current().admin_api.token;
+    match provided {
+        Some(provided) if !token.is_empty() && constant_time_eq(provided, token) => {
+            next.run(request).await
+        }
+        _ => ApiError(
+            StatusCode::UNAUTHORIZED,
+            "Missing or wrong token".to_string(),
+        )
+        .into_response(),
+    }
+}
+
+/// Compares without returning early, so the time taken doesn't give away how much matched.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+async fn list_users() -> Json<Vec<UserInfo>> {
+    let users = DATABASE
+        .users
+        .iter()
+        .filter(|user| !bot::is_bot(user.id))
+        .map(|user| UserInfo {
+            id: user.id,
+            name: user.name.clone(),
+            nation: format!("{:?}", user.session.nation),
+            room_id: (user.room_id != 0).then_some(user.room_id),
+            ip: user.address.ip(),
+            connected_at: unix_seconds(user.connected_at),
+        })
+        .collect();
+
+    Json(users)
+}
+
+async fn list_rooms() -> Json<Vec<RoomInfo>> {
+    let rooms = DATABASE
+        .rooms
+        .iter()
+        .map(|room| RoomInfo {
+            id: room.id,
+            name: room.name.clone(),
+            nation: format!("{:?}", room.session.nation),
+            users: DATABASE
+                .users
+                .iter()
+                .filter(|u| u.room_id == room.id)
+                .map(|u| u.id)
+                .collect(),
+            games: DATABASE
+                .games
+                .iter()
+                .filter(|g| g.room_id == room.id)
+                .map(|g| g.id)
+                .collect(),
+            permanent: room.permanent,
+            protected: room.is_protected(),
+            capacity: room.capacity,
+            owner: (room.owner != 0).then_some(room.owner),
+            topic: room.topic.clone(),
+        })
+        .collect();
+
+    Json(rooms)
+}
+
+async fn list_games() -> Json<Vec<GameInfo>> {
+    let games = DATABASE
+        .games
+        .iter()
+        .map(|game| GameInfo {
+            id: game.id,
+            name: game.name.clone(),
+            nation: format!("{:?}", game.session.nation),
+            room_id: game.room_id,
+            host_ip: game.ip,
+            access: format!("{:?}", game.session.access),
+        })
+        .collect();
+
+    Json(games)
+}
+
+async fn kick_user(Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+    let name = DATABASE.users.get(&id).map(|user| user.name.clone());
+    found(Server::kick_user(id).await?, "user", id)?;
+    audit::operator(
+        "admin api",
+        format!("kick {} ({id})", name.unwrap_or_default()),
+    );
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn close_room(Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+    found(Server::close_room(id).await?, "room", id)?;
+    audit::operator("admin api", format!("close room {id}"));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_permanent_rooms() -> Json<Vec<PermanentRoom>> {
+    Json(PERMANENT_ROOMS.list())
+}
+
+/// Makes a room permanent, or changes one that is.
+async fn keep_room(Json(settings): Json<PermanentRoom>) -> Result<StatusCode, ApiError> {
+    let created = PERMANENT_ROOMS
+        .keep(settings)
+        .await
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    Ok(if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::NO_CONTENT
+    })
+}
+
+async fn release_room(Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+    if PERMANENT_ROOMS.release(id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("No permanent room with id {id}"),
+        ))
+    }
+}
+
+async fn close_game(Path(id): Path<u32>) -> Result<StatusCode, ApiError> {
+    found(Server::close_game(id).await?, "game", id)?;
+    audit::operator("admin api", format!("close game {id}"));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_bans() -> Json<Vec<BanInfo>> {
+    let bans = BANS
+        .list()
+        .into_iter()
+        .map(|ban| BanInfo {
+            target: ban.target.to_string(),
+            reason: ban.reason,
+            created: unix_seconds(ban.created),
+            expires: ban.expires.map(unix_seconds),
+        })
+        .collect();
+
+    Json(bans)
+}
+
+async fn add_ban(
+    Json(request): Json<BanRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    let target: BanTarget = request
+        .target
+        .parse()
+        .map_err(|e: eyre::Report| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let expires = request
+        .duration_secs
+        .map(|secs| SystemTime::now() + Duration::from_secs(secs));
+
+    let outcome = bans::ban(target, &request.reason, expires, request.ban_address).await?;
+    for target in &outcome.banned {
+        let action = format!("ban {target} {}", request.reason);
+        audit::operator("admin api", action.trim_end().to_string());
+    }
+    let banned: Vec<String> = outcome.banned.iter().map(ToString::to_string).collect();
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "banned": banned,
+            "kicked": outcome.kicked,
+            "notes": outcome.notes,
+        })),
+    ))
+}
+
+async fn remove_ban(Json(request): Json<UnbanRequest>) -> Result<StatusCode, ApiError> {
+    let target: BanTarget = request
+        .target
+        .parse()
+        .map_err(|e: eyre::Report| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    if BANS.remove(&target)? {
+        audit::operator("admin api", format!("unban {target}"));
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("{target} isn't banned"),
+        ))
+    }
+}
+
+async fn list_nicknames() -> Json<Vec<NicknameInfo>> {
+    let nicknames = NICKNAMES
+        .list()
+        .into_iter()
+        .map(|nickname| NicknameInfo {
+            name: nickname.name,
+            registered: unix_seconds(nickname.registered),
+        })
+        .collect();
+
+    Json(nicknames)
+}
+
+/// Registers a name, or sets a new password for one that is.
+async fn set_nickname(Json(request): Json<NicknameRequest>) -> Result<StatusCode, ApiError> {
+    if request.name.trim().is_empty() {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            "No name given".to_string(),
+        ));
+    }
+    let created = nicknames::set_password(&request.name, &request.password, true)
+        .await
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+
+    if let Some(id) = Database::find_user(&request.name) {
+        if nicknames::needs_identifying(id) {
+            nicknames::demand_identification(id).await?;
+        }
+    }
+
+    Ok(if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::NO_CONTENT
+    })
+}
+
+async fn remove_nickname(Json(request): Json<UnregisterRequest>) -> Result<StatusCode, ApiError> {
+    if NICKNAMES.remove(&request.name)? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("{} isn't registered", request.name),
+        ))
+    }
+}
+
+async fn broadcast(
+    Json(request): Json<BroadcastRequest>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    check_chat_message("message", &request.message)
+        .map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    if let Some(room_id) = request.room_id {
+        found(DATABASE.rooms.contains_key(&room_id), "room", room_id)?;
+    }
+
+    let sent_to = Server::broadcast_notice(request.room_id, &request.message).await?;
+    Ok(Json(json!({ "sent_to": sent_to })))
+}
+
+async fn metrics() -> Result<String, ApiError> {
+    Ok(METRICS.render()?)
+}
+
+fn found(found: bool, kind: &str, id: u32) -> Result<StatusCode, ApiError> {
+    if found {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("No {kind} with id {id}"),
+        ))
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,570 @@
+use crate::audit;
+use crate::bans::{self, BanTarget, BANS};
+use crate::bot;
+use crate::config::{check_chat_message, Config};
+use crate::database::{Database, DATABASE, SHUTDOWN_TOKEN};
+use crate::moderation::{format_duration, split_duration, split_flag, split_word};
+use crate::net::nation::Nation;
+use crate::nicknames::{self, NICKNAMES};
+use crate::rooms::{self, PermanentRoom, PERMANENT_ROOMS};
+use crate::server::Server;
+use eyre::{bail, eyre, Result};
+use log::{error, info};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{CompletionType, Context, Editor, Helper};
+use std::fmt::Write;
+use std::time::{Duration, SystemTime};
+use tokio::runtime::Handle;
+
+// Operator commands typed into the terminal the server runs in.
+
+const COMMANDS: [(&str, &str); 19] = [
+    ("users", "List the users logged in"),
+    ("rooms", "List the rooms"),
+    ("games", "List the games being hosted"),
+    ("kick <name>", "Disconnect a user"),
+    (
+        "ban [--address] <name|ip|range> [duration] [reason]",
+        "Keep a name or address out, --address bans an online user's address with their name",
+    ),
+    ("unban <name|ip|range>", "Lift a ban"),
+    ("bans", "List the bans in effect"),
+    (
+        "register <name> <password>",
+        "Register a name, or set a new password for one that is",
+    ),
+    (
+        "unregister <name>",
+        "Let anyone use a registered name again",
+    ),
+    ("nicknames", "List the registered names"),
+    (
+        "say <room|all> <text>",
+        "Send a notice to a room or to everyone",
+    ),
+    ("close <room|game>", "Close a room or a game, by name or id"),
+    (
+        "keep <room>",
+        "Keep a room open while it's empty, making it if there's none",
+    ),
+    ("release <room>", "Let a kept room close once it's empty"),
+    (
+        "room <room> <setting> [value]",
+        "Change a kept room's name, nation, greeting, password or capacity, no value clears it",
+    ),
+    ("stats", "Show counts and uptime"),
+    ("reload", "Reload the config file"),
+    (
+        "shutdown [seconds]",
+        "Shut down, waiting the configured grace period unless given",
+    ),
+    ("help", "Show this list"),
+];
+
+/// Reads commands on a thread of its own until stdin closes. Must be called within the runtime,
+/// commands run on it.
+pub fn start() -> Result<()> {
+    let runtime = Handle::current();
+    std::thread::Builder::new()
+        .name("console".to_string())
+        .spawn(move || {
+            if let Err(e) = run(runtime) {
+                error!("Console stopped: {:#}", e);
+            }
+        })?;
+
+    Ok(())
+}
+
+fn run(runtime: Handle) -> Result<()> {
+    let config = rustyline::Config::builder()
+        .completion_type(CompletionType::List)
+        .auto_add_history(true)
+        .build();
+    let mut editor = Editor::<ConsoleHelper, DefaultHistory>::with_config(config)?;
+    editor.set_helper(Some(ConsoleHelper));
+
+    loop {
+        match editor.readline("> ") {
+            Ok(line) => {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                match runtime.block_on(execute(line)) {
+                    Ok(output) => print!("{output}"),
+                    Err(e) => println!("{e:#}"),
+                }
+            }
+            // The terminal doesn't raise SIGINT while a line is being read
+            Err(ReadlineError::Interrupted) => Server::interrupt(),
+            Err(ReadlineError::Eof) => {
+                info!("Console closed");
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+async fn execute(line: &str) -> Result<String> {
+    let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+    let argument = argument.trim();
+    let mut output = String::new();
+
+    match command.to_lowercase().as_str() {
+        "users" => {
+            for user in DATABASE.users.iter().filter(|u| !bot::is_bot(u.id)) {
+                let room = DATABASE
+                    .rooms
+                    .get(&user.room_id)
+                    .map_or_else(|| "-".to_string(), |r| r.name.clone());
+                writeln!(
+                    output,
+                    "{:>6}  {:<17} {:<21} {:?}, room {}",
+                    user.id, user.name, user.address, user.session.nation, room
+                )?;
+            }
+            writeln!(output, "{} users", Database::player_count())?;
+        }
+        "rooms" => {
+            for room in DATABASE.rooms.iter() {
+                let users = DATABASE
+                    .users
+                    .iter()
+                    .filter(|u| u.room_id == room.id)
+                    .count();
+                let games = DATABASE
+                    .games
+                    .iter()
+                    .filter(|g| g.room_id == room.id)
+                    .count();
+                let mut notes = String::new();
+                if room.permanent {
+                    notes.push_str(", kept");
+                }
+                if room.is_protected() {
+                    notes.push_str(", password");
+                }
+                if room.capacity != 0 {
+                    write!(notes, ", at most {}", room.capacity)?;
+                }
+                writeln!(
+                    output,
+                    "{:>6}  {:<17} {:?}, {users} users, {games} games{notes}",
+                    room.id, room.name, room.session.nation
+                )?;
+            }
+            writeln!(output, "{} rooms", DATABASE.rooms.len())?;
+        }
+        "games" => {
+            for game in DATABASE.games.iter() {
+                writeln!(
+                    output,
+                    "{:>6}  {:<17} {:<15} {:?}, room {}",
+                    game.id, game.name, game.ip, game.session.access, game.room_id
+                )?;
+            }
+            writeln!(output, "{} games", DATABASE.games.len())?;
+        }
+        "kick" => {
+            let id = find_user(argument)?;
+            Server::kick_user(id).await?;
+            audit::operator("console", line.to_string());
+            writeln!(output, "Kicked {argument}")?;
+        }
+        "ban" => {
+            let (with_address, argument) = split_flag(argument, "--address");
+            let (target, rest) = split_word(argument);
+            if target.is_empty() {
+                bail!("Usage: ban [--address] <name|ip|range> [duration] [reason]");
+            }
+            let target: BanTarget = target.parse()?;
+            let (duration, reason) = split_duration(rest);
+            let expires = duration.map(|duration| SystemTime::now() + duration);
+
+            let outcome = bans::ban(target, reason, expires, with_address).await?;
+            audit::operator("console", line.to_string());
+            for target in outcome.banned {
+                writeln!(output, "Banned {target}")?;
+            }
+            for note in outcome.notes {
+                writeln!(output, "{note}")?;
+            }
+            for name in outcome.kicked {
+                writeln!(output, "Kicked {name}")?;
+            }
+        }
+        "unban" => {
+            let (target, _) = split_word(argument);
+            if !BANS.remove(&target.parse()?)? {
+                bail!("{target} isn't banned");
+            }
+            audit::operator("console", line.to_string());
+            writeln!(output, "Unbanned {target}")?;
+        }
+        "bans" => {
+            for ban in BANS.list() {
+                let expires = ban.expires.map_or_else(
+                    || "permanent".to_string(),
+                    |expires| format!("until {}", humantime::format_rfc3339_seconds(expires)),
+                );
+                writeln!(
+                    output,
+                    "{:<24} {:<30} {}",
+                    ban.target.to_string(),
+                    expires,
+                    ban.reason
+                )?;
+            }
+            writeln!(output, "{} bans", BANS.len())?;
+        }
+        "register" => {
+            let (name, password) = split_word(argument);
+            if name.is_empty() || password.is_empty() {
+                bail!("Usage: register <name> <password>");
+            }
+            let registered = nicknames::set_password(name, password, true).await?;
+            // The password stays out of the audit log
+            audit::operator("console", format!("register {name}"));
+            if registered {
+                writeln!(output, "Registered {name}")?;
+            } else {
+                writeln!(output, "Changed the password of {name}")?;
+            }
+
+            // Whoever's online with it now has to prove it's theirs as well
+            if let Some(id) = Database::find_user(name) {
+                if nicknames::needs_identifying(id) {
+                    nicknames::demand_identification(id).await?;
+                    writeln!(output, "Asked {name} to identify")?;
+                }
+            }
+        }
+        "unregister" => {
+            let (name, _) = split_word(argument);
+            if !NICKNAMES.remove(name)? {
+                bail!("{name} isn't registered");
+            }
+            audit::operator("console", format!("unregister {name}"));
+            writeln!(output, "Unregistered {name}")?;
+        }
+        "nicknames" => {
+            for nickname in NICKNAMES.list() {
+                writeln!(
+                    output,
+                    "{:<17} registered {}",
+                    nickname.name,
+                    humantime::format_rfc3339_seconds(nickname.registered)
+                )?;
+            }
+            writeln!(output, "{} registered names", NICKNAMES.len())?;
+        }
+        "say" => {
+            let (room_id, message) = split_room(argument)?;
+            check_chat_message("message", message)?;
+            let sent = Server::broadcast_notice(room_id, message).await?;
+            writeln!(output, "Sent to {sent} users")?;
+        }
+        "close" => {
+            let closed = match find_session(argument)? {
+                Session::Room(id) => Server::close_room(id).await?,
+                Session::Game(id) => Server::close_game(id).await?,
+            };
+            if !closed {
+                bail!("{argument} is already gone");
+            }
+            audit::operator("console", line.to_string());
+            writeln!(output, "Closed {argument}")?;
+        }
+        "keep" => {
+            if argument.is_empty() {
+                bail!("Usage: keep <room>");
+            }
+            let settings = find_room(argument)
+                .and_then(rooms::settings)
+                .unwrap_or_else(|| PermanentRoom {
+                    name: argument.to_string(),
+                    ..Default::default()
+                });
+            let name = settings.name.clone();
+            let created = PERMANENT_ROOMS.keep(settings).await?;
+            audit::operator("console", line.to_string());
+            if created {
+                writeln!(output, "Opened {name}, it stays open")?;
+            } else {
+                writeln!(output, "Keeping {name} open")?;
+            }
+        }
+        "release" => {
+            let id = find_room(argument).ok_or_else(|| eyre!("No room called '{argument}'"))?;
+            if !PERMANENT_ROOMS.release(id).await? {
+                bail!("{argument} isn't kept open");
+            }
+            audit::operator("console", line.to_string());
+            writeln!(output, "Released {argument}, it closes once it's empty")?;
+        }
+        "room" => {
+            let (room, rest) = split_word(argument);
+            let (setting, value) = split_word(rest);
+            if setting.is_empty() {
+                bail!("Usage: room <room> <setting> [value]");
+            }
+            let mut settings = find_room(room)
+                .filter(|id| DATABASE.rooms.get(id).is_some_and(|r| r.permanent))
+                .and_then(rooms::settings)
+                .ok_or_else(|| eyre!("No room called '{room}' is kept open, keep it first"))?;
+            match setting.to_lowercase().as_str() {
+                "name" => settings.name = value.to_string(),
+                "nation" => {
+                    settings.nation = if value.is_empty() {
+                        Nation::None
+                    } else {
+                        value.parse()?
+                    }
+                }
+                "greeting" => settings.greeting = value.replace("\\n", "\n"),
+                "password" => settings.password = value.to_string(),
+                "capacity" => {
+                    settings.capacity = match value {
+                        "" => 0,
+                        value => value
+                            .parse()
+                            .map_err(|_| eyre!("The capacity is a number of users"))?,
+                    }
+                }
+                _ => bail!(
+                    "Unknown setting '{setting}', use name, nation, greeting, password or capacity"
+                ),
+            }
+            PERMANENT_ROOMS.keep(settings).await?;
+            let action = if setting.eq_ignore_ascii_case("password") && !value.is_empty() {
+                format!("room {room} {setting} (hidden)")
+            } else {
+                line.to_string()
+            };
+            audit::operator("console", action);
+            writeln!(output, "Changed the {setting} of {room}")?;
+        }
+        "stats" => {
+            writeln!(
+                output,
+                "{} users, {} rooms, {} games, {} bans, {} registered names",
+                Database::player_count(),
+                DATABASE.rooms.len(),
+                DATABASE.games.len(),
+                BANS.len(),
+                NICKNAMES.len()
+            )?;
+            writeln!(output, "Up for {}", format_duration(Server::uptime()))?;
+        }
+        "reload" => {
+            let report = Config::reload()?;
+            if let Some(count) = BANS.reload()? {
+                writeln!(output, "{count} bans in effect")?;
+            }
+            if let Some(count) = NICKNAMES.reload()? {
+                writeln!(output, "{count} registered names")?;
+            }
+            if let Some(count) = PERMANENT_ROOMS.reload().await? {
+                writeln!(output, "{count} permanent rooms")?;
+            }
+            if report.applied.is_empty() {
+                writeln!(output, "Nothing changed")?;
+            } else {
+                writeln!(output, "Applied: {}", report.applied.join(", "))?;
+            }
+            if !report.needs_restart.is_empty() {
+                writeln!(
+                    output,
+                    "Restart the server to apply: {}",
+                    report.needs_restart.join(", ")
+                )?;
+            }
+        }
+        "shutdown" => {
+            if SHUTDOWN_TOKEN.is_cancelled() {
+                bail!("Already shutting down");
+            }
+            let grace = match argument {
+                "" => None,
+                seconds => Some(Duration::from_secs(
+                    seconds
+                        .parse()
+                        .map_err(|_| eyre!("Usage: shutdown [seconds]"))?,
+                )),
+            };
+            Server::begin_shutdown(grace);
+        }
+        "help" => {
+            for (usage, description) in COMMANDS {
+                writeln!(output, "{usage:<42}{description}")?;
+            }
+        }
+        _ => bail!("Unknown command '{command}', try help"),
+    }
+
+    Ok(output)
+}
+
+fn find_user(name: &str) -> Result<u32> {
+    Database::find_user(name).ok_or_else(|| eyre!("No user called '{name}'"))
+}
+
+/// A room by id or by name.
+fn find_room(name: &str) -> Option<u32> {
+    name.parse::<u32>()
+        .ok()
+        .filter(|id| DATABASE.rooms.contains_key(id))
+        .or_else(|| rooms::find_room(name))
+}
+
+/// Splits `say`'s argument into the room and the message. Room names can have spaces in them so
+/// the longest one that fits is used.
+fn split_room(argument: &str) -> Result<(Option<u32>, &str)> {
+    let usage = || eyre!("Usage: say <room|all> <text>");
+    let (first, rest) = argument.split_once(' ').ok_or_else(usage)?;
+    if first.eq_ignore_ascii_case("all") {
+        return Ok((None, rest.trim()));
+    }
+    if let Some(room) = first
+        .parse::<u32>()
+        .ok()
+        .filter(|id| DATABASE.rooms.contains_key(id))
+    {
+        return Ok((Some(room), rest.trim()));
+    }
+
+    DATABASE
+        .rooms
+        .iter()
+        .filter_map(|room| {
+            let prefix = argument.get(..room.name.len())?;
+            let message = argument[room.name.len()..].strip_prefix(' ')?;
+            prefix
+                .eq_ignore_ascii_case(&room.name)
+                .then(|| (room.name.len(), room.id, message.trim()))
+        })
+        .max_by_key(|(length, _, _)| *length)
+        .map(|(_, id, message)| (Some(id), message))
+        .ok_or_else(|| eyre!("No room called '{first}'"))
+}
+
+enum Session {
+    Room(u32),
+    Game(u32),
+}
+
+fn find_session(name: &str) -> Result<Session> {
+    if let Ok(id) = name.parse::<u32>() {
+        if DATABASE.rooms.contains_key(&id) {
+            return Ok(Session::Room(id));
+        }
+        if DATABASE.games.contains_key(&id) {
+            return Ok(Session::Game(id));
+        }
+    }
+
+    let room = DATABASE
+        .rooms
+        .iter()
+        .find(|r| r.name.eq_ignore_ascii_case(name))
+        .map(|r| r.id);
+    let game = DATABASE
+        .games
+        .iter()
+        .find(|g| g.name.eq_ignore_ascii_case(name))
+        .map(|g| g.id);
+
+    match (room, game) {
+        (Some(room), Some(game)) => {
+            bail!("'{name}' is both a room ({room}) and a game ({game}), close it by id instead")
+        }
+        (Some(room), None) => Ok(Session::Room(room)),
+        (None, Some(game)) => Ok(Session::Game(game)),
+        (None, None) => bail!("No room or game called '{name}'"),
+    }
+}
+
+/// Completes command names, then the names of whatever the command takes.
+struct ConsoleHelper;
+
+impl Completer for ConsoleHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        position: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..position];
+        let Some((command, argument)) = line.split_once(' ') else {
+            let commands = COMMANDS
+                .iter()
+                .filter_map(|(usage, _)| usage.split(' ').next())
+                .filter(|name| name.starts_with(&line.to_lowercase()))
+                .map(|name| name.to_string());
+            return Ok((0, candidates(commands)));
+        };
+
+        let names: Vec<String> = match command.to_lowercase().as_str() {
+            "kick" | "ban" => DATABASE
+                .users
+                .iter()
+                .filter(|u| !bot::is_bot(u.id))
+                .map(|u| u.name.clone())
+                .collect(),
+            "unban" => BANS.list().iter().map(|b| b.target.to_string()).collect(),
+            "register" | "unregister" => NICKNAMES.list().into_iter().map(|n| n.name).collect(),
+            "say" => DATABASE
+                .rooms
+                .iter()
+                .map(|r| r.name.clone())
+                .chain(["all".to_string()])
+                .collect(),
+            "keep" | "release" | "room" => DATABASE.rooms.iter().map(|r| r.name.clone()).collect(),
+            "close" => DATABASE
+                .rooms
+                .iter()
+                .map(|r| r.name.clone())
+                .chain(DATABASE.games.iter().map(|g| g.name.clone()))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let typed = argument.to_lowercase();
+        let matching = names
+            .into_iter()
+            .filter(|name| name.to_lowercase().starts_with(&typed));
+        Ok((command.len() + 1, candidates(matching)))
+    }
+}
+
+fn candidates(names: impl Iterator<Item = String>) -> Vec<Pair> {
+    let mut names: Vec<String> = names.collect();
+    names.sort_unstable();
+    names.dedup();
+    names
+        .into_iter()
+        .map(|name| Pair {
+            display: name.clone(),
+            replacement: name,
+        })
+        .collect()
+}
+
+impl Hinter for ConsoleHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ConsoleHelper {}
+
+impl Validator for ConsoleHelper {}
+
+impl Helper for ConsoleHelper {}
@@ -1,14 +1,32 @@
 use clap::Parser;
 use std::net::IpAddr;
+use std::path::PathBuf;
+use worms_server::config::{CONFIG_PATH_ENV, NO_CONSOLE_ENV};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub(crate) struct Args {
-    /// Specific ip address to listen on
-    #[arg(short, long, default_value = "0.0.0.0")]
-    pub(crate) ip: IpAddr,
+    /// Configuration file, settings it leaves out keep their defaults
+    #[arg(short, long, env = CONFIG_PATH_ENV)]
+    pub(crate) config: Option<PathBuf>,
 
-    /// Specific port to listen to
-    #[arg(short, long, default_value = "17000")]
-    pub(crate) port: u16,
+    /// Print the default configuration and exit
+    #[arg(long)]
+    pub(crate) print_default_config: bool,
+
+    /// Specific ip address to listen on, overrides the config
+    #[arg(short, long)]
+    pub(crate) ip: Option<IpAddr>,
+
+    /// Specific port to listen to, overrides the config
+    #[arg(short, long)]
+    pub(crate) port: Option<u16>,
+
+    /// Record every frame sent and received to this file, for use with the replay tool
+    #[arg(long)]
+    pub(crate) capture: Option<PathBuf>,
+
+    /// Don't read admin commands from stdin, for running under a service manager
+    #[arg(long, env = NO_CONSOLE_ENV)]
+    pub(crate) no_console: bool,
 }
@@ -0,0 +1,75 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Wrong passwords counted by what they were for, a name or a room, so guessing gets someone
+// locked out for a while. Kept apart from the failed logins `IpLimits` counts, which block a whole
+// address.
+
+#[derive(Default)]
+pub struct Attempts {
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+impl Attempts {
+    /// How long until `key` can be tried again, if it's had too many wrong passwords.
+    pub fn locked_out_for(&self, key: &str) -> Option<Duration> {
+        let mut failures = self.failures.lock();
+        let remaining = failures
+            .get(key)
+            .and_then(|f| f.locked_until)
+            .map(|until| until.saturating_duration_since(Instant::now()))?;
+        if remaining.is_zero() {
+            failures.remove(key);
+            return None;
+        }
+        Some(remaining)
+    }
+
+    /// Counts a wrong password for `key`, locking it out for `lockout` once it's had `max`, 0 for
+    /// no limit. Returns whether it's locked out now.
+    pub fn failed(&self, key: &str, max: u32, lockout: Duration) -> bool {
+        if max == 0 {
+            return false;
+        }
+        let mut failures = self.failures.lock();
+        let failure = failures.entry(key.to_string()).or_default();
+        failure.count += 1;
+        if failure.count < max {
+            return false;
+        }
+        failure.count = 0;
+        failure.locked_until = Some(Instant::now() + lockout);
+        true
+    }
+
+    pub fn succeeded(&self, key: &str) {
+        self.failures.lock().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn too_many_failures_lock_out_for_a_while() {
+        let attempts = Attempts::default();
+        let lockout = Duration::from_secs(60);
+        assert!(!attempts.failed("bob", 2, lockout));
+        assert_eq!(attempts.locked_out_for("bob"), None);
+        assert!(attempts.failed("bob", 2, lockout));
+        assert!(attempts.locked_out_for("bob").is_some());
+        assert_eq!(attempts.locked_out_for("alice"), None);
+
+        assert!(attempts.failed("alice", 1, Duration::ZERO));
+        assert_eq!(attempts.locked_out_for("alice"), None);
+        assert!(!attempts.failed("carol", 0, lockout));
+    }
+}
@@ -0,0 +1,343 @@
+use crate::config::Config;
+use crate::database::user::User;
+use crate::database::DATABASE;
+use eyre::{Context, Result};
+use log::error;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime};
+
+// A record of who said and did what, to settle moderation disputes. Each event is a JSON line in
+// `audit.dir`, in a file per day (UTC) named after it, and files older than `audit.retention_days`
+// are deleted as a new one is started. Writing is left to a thread of its own, so handlers never
+// wait on the disk.
+
+pub static AUDIT: LazyLock<AuditLog> = LazyLock::new(AuditLog::start);
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Who did something, whatever of it is known.
+#[derive(Debug, Default, Serialize)]
+pub struct Actor {
+    pub user_id: Option<u32>,
+    pub name: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub room_id: Option<u32>,
+    pub room: Option<String>,
+}
+
+impl Actor {
+    /// Don't call while holding an entry of `DATABASE.rooms`, the room's name is looked up.
+    pub fn of(user: &User) -> Self {
+        let room = DATABASE.rooms.get(&user.room_id).map(|r| r.name.clone());
+        Self {
+            user_id: Some(user.id),
+            name: Some(user.name.clone()),
+            ip: Some(user.address.ip()),
+            room_id: (user.room_id != 0).then_some(user.room_id),
+            room,
+        }
+    }
+
+    /// The user with the id, or just the id if they're gone.
+    pub fn user(user_id: u32) -> Self {
+        let user = DATABASE
+            .users
+            .get(&user_id)
+            .map(|user| (user.name.clone(), user.address.ip(), user.room_id));
+        match user {
+            Some((name, ip, room_id)) => Self {
+                user_id: Some(user_id),
+                name: Some(name),
+                ip: Some(ip),
+                room_id: (room_id != 0).then_some(room_id),
+                room: DATABASE.rooms.get(&room_id).map(|r| r.name.clone()),
+            },
+            None => Self {
+                user_id: Some(user_id),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Login,
+    Disconnect,
+    Chat {
+        message: String,
+    },
+    PrivateChat {
+        to_id: u32,
+        to_name: String,
+        message: String,
+    },
+    CreateRoom {
+        new_room_id: u32,
+        new_room: String,
+    },
+    CreateGame {
+        game_id: u32,
+    },
+    /// `by` is who had the power to do it: a moderator, a room owner, the console or the API.
+    Moderation {
+        by: &'static str,
+        action: String,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    #[serde(flatten)]
+    event: &'a Event,
+    #[serde(flatten)]
+    actor: &'a Actor,
+}
+
+pub struct AuditLog {
+    sender: Sender<Message>,
+}
+
+enum Message {
+    /// A record ready to be written, stamped with when it happened
+    Line { time: SystemTime, text: String },
+    /// Answered once everything sent before it is on disk
+    Flush(Sender<()>),
+}
+
+/// The file events are written to today.
+struct DayFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl AuditLog {
+    fn start() -> Self {
+        let (sender, receiver) = channel::<Message>();
+        let spawned = std::thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn(move || write_lines(receiver));
+        if let Err(e) = spawned {
+            error!("Audit log: failed to start writing: {e}");
+        }
+        Self { sender }
+    }
+
+    fn write(&self, actor: &Actor, event: &Event) -> Result<()> {
+        if Config::current().audit.dir.as_os_str().is_empty() {
+            return Ok(());
+        }
+
+        let now = SystemTime::now();
+        let time = humantime::format_rfc3339_millis(now).to_string();
+        let mut text = serde_json::to_string(&Record { time, event, actor })?;
+        text.push('\n');
+        // The writer only goes away if it couldn't start, which has been logged already
+        let _ = self.sender.send(Message::Line { time: now, text });
+        Ok(())
+    }
+}
+
+/// Waits for what's been recorded to be written, for a little while, before the server exits.
+pub fn finish() {
+    if Config::current().audit.dir.as_os_str().is_empty() {
+        return;
+    }
+    let (sender, receiver) = channel();
+    if AUDIT.sender.send(Message::Flush(sender)).is_ok() {
+        let _ = receiver.recv_timeout(Duration::from_secs(5));
+    }
+}
+
+/// Writes whatever is queued up, then flushes before waiting for more.
+fn write_lines(receiver: Receiver<Message>) {
+    let mut current: Option<DayFile> = None;
+    while let Ok(message) = receiver.recv() {
+        let mut flushed = Vec::new();
+        for message in std::iter::once(message).chain(receiver.try_iter()) {
+            match message {
+                Message::Line { time, text } => {
+                    if let Err(e) = write_line(&mut current, time, &text) {
+                        error!("Audit log: {e:#}");
+                        // Opened again for the next line, in case it was moved or deleted
+                        current = None;
+                    }
+                }
+                Message::Flush(done) => flushed.push(done),
+            }
+        }
+
+        if let Some(day_file) = current.as_mut() {
+            if let Err(e) = day_file.writer.flush() {
+                error!(
+                    "Audit log: failed to write {}: {e}",
+                    day_file.path.display()
+                );
+                current = None;
+            }
+        }
+        for done in flushed {
+            let _ = done.send(());
+        }
+    }
+}
+
+fn write_line(current: &mut Option<DayFile>, time: SystemTime, text: &str) -> Result<()> {
+    let config = Config::current();
+    let dir = &config.audit.dir;
+    if dir.as_os_str().is_empty() {
+        return Ok(());
+    }
+
+    let path = dir.join(file_name(time));
+    let day_file = match current.take() {
+        Some(day_file) if day_file.path == path => day_file,
+        previous => {
+            if let Some(mut previous) = previous {
+                previous
+                    .writer
+                    .flush()
+                    .wrap_err_with(|| format!("Failed to write {}", previous.path.display()))?;
+            }
+            fs::create_dir_all(dir)
+                .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+            prune(dir, time, config.audit.retention_days);
+            DayFile {
+                path,
+                writer: BufWriter::new(file),
+            }
+        }
+    };
+    let day_file = current.insert(day_file);
+    day_file
+        .writer
+        .write_all(text.as_bytes())
+        .wrap_err_with(|| format!("Failed to write {}", day_file.path.display()))
+}
+
+/// Writes an event to the audit log, logging rather than failing if it can't.
+pub fn record(actor: Actor, event: Event) {
+    if let Err(e) = AUDIT.write(&actor, &event) {
+        error!("Audit log: {e:#}");
+    }
+}
+
+/// A moderation action taken by the operator, who isn't a user.
+pub fn operator(by: &'static str, action: String) {
+    record(Actor::default(), Event::Moderation { by, action });
+}
+
+fn file_name(time: SystemTime) -> String {
+    format!("audit-{}.jsonl", day(time))
+}
+
+/// The UTC date, as `2024-01-31`.
+fn day(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()[..10].to_string()
+}
+
+/// Whether a file in the audit directory is one of ours from more than `retention_days` ago.
+fn is_expired(file_name: &str, now: SystemTime, retention_days: u32) -> bool {
+    if retention_days == 0 {
+        return false;
+    }
+    let Some(file_day) = file_name
+        .strip_prefix("audit-")
+        .and_then(|rest| rest.strip_suffix(".jsonl"))
+        .filter(|file_day| file_day.len() == 10)
+    else {
+        return false;
+    };
+    // Dates sort as text
+    file_day <= day(now - DAY * retention_days).as_str()
+}
+
+fn prune(dir: &Path, now: SystemTime, retention_days: u32) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Audit log: failed to read {}: {e}", dir.display());
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        if is_expired(&name.to_string_lossy(), now, retention_days) {
+            if let Err(e) = fs::remove_file(entry.path()) {
+                error!(
+                    "Audit log: failed to delete {}: {e}",
+                    entry.path().display()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    #[test]
+    fn files_are_named_after_the_day() {
+        let time = UNIX_EPOCH + Duration::from_secs(19_000 * 86_400 + 3_600);
+        assert_eq!(file_name(time), "audit-2022-01-08.jsonl");
+    }
+
+    #[test]
+    fn only_old_audit_files_expire() {
+        let now = UNIX_EPOCH + Duration::from_secs(19_000 * 86_400 + 3_600);
+        assert!(!is_expired("audit-2022-01-08.jsonl", now, 1));
+        assert!(is_expired("audit-2022-01-07.jsonl", now, 1));
+        assert!(!is_expired("audit-2022-01-07.jsonl", now, 2));
+        assert!(is_expired("audit-2021-12-01.jsonl", now, 30));
+        assert!(!is_expired("audit-2021-12-01.jsonl", now, 0));
+        assert!(!is_expired("notes-2021-12-01.jsonl", now, 30));
+        assert!(!is_expired("audit-2021-12-01.jsonl.bak", now, 30));
+    }
+
+    #[test]
+    fn records_are_flat_json() {
+        let actor = Actor {
+            user_id: Some(0x1000),
+            name: Some("Alice".to_string()),
+            ip: Some("203.0.113.7".parse().unwrap()),
+            room_id: Some(0x1001),
+            room: Some("Lobby".to_string()),
+        };
+        let event = Event::Chat {
+            message: "hello".to_string(),
+        };
+        let record = Record {
+            time: "2022-01-08T01:00:00.000Z".to_string(),
+            event: &event,
+            actor: &actor,
+        };
+        assert_eq!(
+            serde_json::to_value(&record).unwrap(),
+            serde_json::json!({
+                "time": "2022-01-08T01:00:00.000Z",
+                "event": "chat",
+                "message": "hello",
+                "user_id": 4096,
+                "name": "Alice",
+                "ip": "203.0.113.7",
+                "room_id": 4097,
+                "room": "Lobby",
+            })
+        );
+    }
+}
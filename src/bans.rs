@@ -0,0 +1,410 @@
+use crate::bot;
+use crate::config::Config;
+use crate::database::{Database, DATABASE};
+use crate::net::address_range::AddressRange;
+use crate::server::Server;
+use eyre::{bail, Result, WrapErr};
+use log::info;
+use parking_lot::RwLock;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::SystemTime;
+
+pub static BANS: LazyLock<BanList> = LazyLock::new(BanList::default);
+
+/// What a ban keeps out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BanTarget {
+    /// A single address or a CIDR range
+    Address(AddressRange),
+    /// A name ignoring case, `*` matches any number of characters and `?` any one
+    Name(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ban {
+    #[serde(flatten)]
+    pub target: BanTarget,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub reason: String,
+    #[serde(with = "timestamp", default = "SystemTime::now")]
+    pub created: SystemTime,
+    /// Lasts forever when left out
+    #[serde(
+        with = "optional_timestamp",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub expires: Option<SystemTime>,
+}
+
+/// The bans file, a `[[ban]]` table for each ban.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BanFile {
+    #[serde(default, rename = "ban")]
+    bans: Vec<Ban>,
+}
+
+/// Bans in effect, saved to a file on every change once one is loaded.
+#[derive(Default)]
+pub struct BanList {
+    bans: RwLock<Vec<Ban>>,
+    path: RwLock<Option<PathBuf>>,
+}
+
+impl BanTarget {
+    /// Tells the same target apart from a different one, names ignore case.
+    fn same_as(&self, other: &BanTarget) -> bool {
+        match (self, other) {
+            (BanTarget::Name(a), BanTarget::Name(b)) => a.to_lowercase() == b.to_lowercase(),
+            _ => self == other,
+        }
+    }
+}
+
+/// Addresses and ranges are told apart from names by whether they parse as one.
+impl FromStr for BanTarget {
+    type Err = eyre::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        if let Ok(range) = text.parse() {
+            return Ok(BanTarget::Address(range));
+        }
+        if text.trim().is_empty() {
+            bail!("A ban needs an address, range or name");
+        }
+        Ok(BanTarget::Name(text.trim().to_string()))
+    }
+}
+
+impl Display for BanTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BanTarget::Address(range) => write!(f, "{range}"),
+            BanTarget::Name(pattern) => write!(f, "{pattern}"),
+        }
+    }
+}
+
+impl Ban {
+    pub fn new(target: BanTarget, reason: &str, expires: Option<SystemTime>) -> Self {
+        Self {
+            target,
+            reason: reason.to_string(),
+            created: SystemTime::now(),
+            expires,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            .is_some_and(|expires| expires <= SystemTime::now())
+    }
+
+    pub fn matches(&self, name: Option<&str>, address: IpAddr) -> bool {
+        match &self.target {
+            BanTarget::Address(range) => range.contains(address),
+            BanTarget::Name(pattern) => name.is_some_and(|name| matches_pattern(pattern, name)),
+        }
+    }
+
+    /// Tells a banned user why they can't get in.
+    pub fn explanation(&self) -> String {
+        let mut explanation = "You are banned from this server".to_string();
+        if !self.reason.is_empty() {
+            explanation.push_str(&format!(": {}", self.reason));
+        }
+        if let Some(expires) = self.expires {
+            explanation.push_str(&format!(
+                " (until {})",
+                humantime::format_rfc3339_seconds(expires)
+            ));
+        }
+        explanation
+    }
+}
+
+/// Matches a name against a pattern with `*` and `?` wildcards, ignoring case.
+pub(crate) fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+
+    // Backtracks to the last `*` on a mismatch, letting it take one more character
+    let (mut p, mut n) = (0, 0);
+    let mut last_star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                last_star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match last_star {
+                Some((star, matched)) => {
+                    last_star = Some((star, matched + 1));
+                    p = star + 1;
+                    n = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+impl BanList {
+    /// Reads the bans kept in `path`, where changes are saved from then on. A missing file is
+    /// an empty list. Returns how many bans are in effect.
+    pub fn load(&self, path: &Path) -> Result<usize> {
+        let bans = if path.exists() {
+            let text = std::fs::read_to_string(path)
+                .wrap_err_with(|| format!("Unable to read bans file {}", path.display()))?;
+            toml::from_str::<BanFile>(&text)
+                .wrap_err_with(|| format!("Invalid bans file {}", path.display()))?
+                .bans
+        } else {
+            Vec::new()
+        };
+
+        *self.path.write() = Some(path.to_path_buf());
+        let mut current = self.bans.write();
+        *current = bans;
+        current.retain(|ban| !ban.is_expired());
+        Ok(current.len())
+    }
+
+    /// Reads the bans file again, for bans edited by hand.
+    pub fn reload(&self) -> Result<Option<usize>> {
+        let path = self.path.read().clone();
+        path.map(|path| self.load(&path)).transpose()
+    }
+
+    /// Adds a ban, replacing any for the same target.
+    pub fn add(&self, ban: Ban) -> Result<()> {
+        let mut bans = self.bans.write();
+        bans.retain(|b| !b.is_expired() && !b.target.same_as(&ban.target));
+        info!("Banned {}", ban.target);
+        bans.push(ban);
+        self.save(&bans)
+    }
+
+    /// Returns false if nothing was banned for the target.
+    pub fn remove(&self, target: &BanTarget) -> Result<bool> {
+        let mut bans = self.bans.write();
+        let count = bans.len();
+        bans.retain(|b| !b.is_expired() && !b.target.same_as(target));
+        if bans.len() == count {
+            return Ok(false);
+        }
+
+        info!("Lifted the ban on {}", target);
+        self.save(&bans)?;
+        Ok(true)
+    }
+
+    /// The bans in effect.
+    pub fn list(&self) -> Vec<Ban> {
+        self.bans
+            .read()
+            .iter()
+            .filter(|ban| !ban.is_expired())
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bans.read().iter().filter(|b| !b.is_expired()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The ban keeping out a connection from `address`, checked before it's logged in.
+    pub fn find_address(&self, address: IpAddr) -> Option<Ban> {
+        self.find(None, address)
+    }
+
+    /// The ban keeping out a user logging in.
+    pub fn find(&self, name: Option<&str>, address: IpAddr) -> Option<Ban> {
+        self.bans
+            .read()
+            .iter()
+            .find(|ban| !ban.is_expired() && ban.matches(name, address))
+            .cloned()
+    }
+
+    fn save(&self, bans: &[Ban]) -> Result<()> {
+        let Some(path) = self.path.read().clone() else {
+            return Ok(());
+        };
+
+        let file = BanFile {
+            bans: bans.to_vec(),
+        };
+        // Written next to the file and moved over it so a crash can't leave half a list
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, toml::to_string_pretty(&file)?)
+            .and_then(|()| std::fs::rename(&temporary, &path))
+            .wrap_err_with(|| format!("Unable to save bans to {}", path.display()))
+    }
+}
+
+/// What `ban` did.
+#[derive(Default)]
+pub struct BanOutcome {
+    pub banned: Vec<BanTarget>,
+    pub kicked: Vec<String>,
+    /// Why an address that was asked for wasn't banned
+    pub notes: Vec<String>,
+}
+
+/// Bans a target, kicking everyone the ban applies to. With a name and `with_address`, the
+/// address of whoever's online using it is banned too, unless it's exempt from the limits.
+pub async fn ban(
+    target: BanTarget,
+    reason: &str,
+    expires: Option<SystemTime>,
+    with_address: bool,
+) -> Result<BanOutcome> {
+    let mut outcome = BanOutcome::default();
+    let mut bans = vec![Ban::new(target, reason, expires)];
+    if let (BanTarget::Name(name), true) = (&bans[0].target, with_address) {
+        let address = Database::find_user(name)
+            .and_then(|id| DATABASE.users.get(&id).map(|u| u.address.ip()));
+        match address {
+            None => outcome
+                .notes
+                .push(format!("{name} isn't online, no address was banned")),
+            Some(address)
+                if Config::current()
+                    .limits
+                    .exempt
+                    .iter()
+                    .any(|range| range.contains(address)) =>
+            {
+                outcome
+                    .notes
+                    .push(format!("{address} is exempt, it wasn't banned"));
+            }
+            Some(address) => bans.push(Ban::new(
+                BanTarget::Address(address.into()),
+                reason,
+                expires,
+            )),
+        }
+    }
+
+    for ban in bans {
+        BANS.add(ban.clone())?;
+        outcome.kicked.extend(enforce(&ban).await?);
+        outcome.banned.push(ban.target);
+    }
+
+    Ok(outcome)
+}
+
+/// Kicks everyone a new ban applies to, telling them why first. Returns their names.
+pub async fn enforce(ban: &Ban) -> Result<Vec<String>> {
+    let users: Vec<(u32, String)> = DATABASE
+        .users
+        .iter()
+        .filter(|u| !bot::is_bot(u.id) && ban.matches(Some(&u.name), u.address.ip()))
+        .map(|u| (u.id, u.name.clone()))
+        .collect();
+
+    let mut kicked = Vec::with_capacity(users.len());
+    for (id, name) in users {
+        if let Some(user) = DATABASE.users.get(&id) {
+            let notice = bot::private_packet(id, &ban.explanation())?;
+            user.send_packet(notice).await?;
+        }
+        if Server::kick_user(id).await? {
+            kicked.push(name);
+        }
+    }
+
+    Ok(kicked)
+}
+
+pub(crate) mod timestamp {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&humantime::format_rfc3339_seconds(*time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        humantime::parse_rfc3339_weak(&text).map_err(serde::de::Error::custom)
+    }
+}
+
+mod optional_timestamp {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        time: &Option<SystemTime>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => timestamp::serialize(time, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<SystemTime>, D::Error> {
+        timestamp::deserialize(deserializer).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn name_patterns_ignore_case() {
+        assert!(matches_pattern("griefer", "GrIeFeR"));
+        assert!(matches_pattern("grief*", "Griefer2000"));
+        assert!(matches_pattern("*bot*", "the_bot_9"));
+        assert!(matches_pattern("b?b", "Bob"));
+        assert!(!matches_pattern("b?b", "Bobb"));
+        assert!(!matches_pattern("grief*x", "griefer"));
+    }
+
+    #[test]
+    fn bans_survive_the_file() {
+        let expires = SystemTime::UNIX_EPOCH + Duration::from_secs(4_000_000_000);
+        let file = BanFile {
+            bans: vec![
+                Ban::new(
+                    BanTarget::Address("198.51.100.0/24".parse().unwrap()),
+                    "spam",
+                    Some(expires),
+                ),
+                Ban::new(BanTarget::Name("grief*".to_string()), "", None),
+            ],
+        };
+
+        let text = toml::to_string_pretty(&file).unwrap();
+        let read: BanFile = toml::from_str(&text).unwrap();
+        assert_eq!(read.bans[0].target, file.bans[0].target);
+        assert_eq!(read.bans[0].reason, "spam");
+        assert_eq!(read.bans[0].expires, Some(expires));
+        assert_eq!(read.bans[1].target, file.bans[1].target);
+        assert_eq!(read.bans[1].expires, None);
+    }
+}
@@ -0,0 +1,185 @@
+use clap::Parser;
+use eyre::{bail, Result};
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio_util::bytes::{Bytes, BytesMut};
+use tokio_util::codec::Decoder;
+use worms_server::net::capture::{CaptureReader, RecordKind};
+use worms_server::net::packet_code::PacketCode;
+use worms_server::net::packet_handler;
+use worms_server::net::worms_codec::WormCodec;
+use worms_server::server::Server;
+
+/// Feeds a capture recorded with `--capture` back through the packet handlers of a fresh server,
+/// reporting where the replies differ from the recorded ones.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Capture file to replay
+    capture: PathBuf,
+
+    /// Print every frame, not just the ones that differ
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+/// A replayed connection, standing in for `Server::handle_connection`.
+struct Connection {
+    address: SocketAddr,
+    user_id: Option<u32>,
+    tx: Sender<Arc<Bytes>>,
+    rx: Receiver<Arc<Bytes>>,
+    buffer: BytesMut,
+    recorded: VecDeque<Bytes>,
+    replayed: VecDeque<Bytes>,
+    closed: bool,
+}
+
+impl Connection {
+    fn new(address: SocketAddr) -> Self {
+        // Large enough that a handler never waits on a full channel, nothing drains it meanwhile
+        let (tx, rx) = channel(4096);
+        Self {
+            address,
+            user_id: None,
+            tx,
+            rx,
+            buffer: BytesMut::new(),
+            recorded: VecDeque::new(),
+            replayed: VecDeque::new(),
+            closed: false,
+        }
+    }
+
+    /// Handles every complete frame in the buffer the way the server would.
+    async fn receive(&mut self, id: u32, frame: &[u8], verbose: bool) -> Result<()> {
+        self.buffer.extend_from_slice(frame);
+
+        while let Some(packet) = WormCodec.decode(&mut self.buffer)? {
+            if verbose {
+                println!("  #{id} <- {packet:?}");
+            }
+
+            match self.user_id {
+                None => {
+                    if packet.header_code != PacketCode::Login {
+                        bail!("First packet must be a login packet");
+                    }
+                    self.user_id =
+                        Some(Server::login_client(&packet, &self.tx, self.address).await?);
+                }
+                Some(user_id) => {
+                    packet_handler::dispatch(self.tx.clone(), &packet, user_id, self.address)
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    env_logger::init();
+    color_eyre::install()?;
+
+    let args = Args::parse();
+    let mut connections = BTreeMap::<u32, Connection>::new();
+    let mut start = None;
+
+    for record in CaptureReader::open(&args.capture)? {
+        let record = record?;
+        let elapsed = record.timestamp_micros - *start.get_or_insert(record.timestamp_micros);
+        let time = format!("[{:>4}.{:06}]", elapsed / 1_000_000, elapsed % 1_000_000);
+        let id = record.connection_id;
+
+        match record.kind {
+            RecordKind::Connect => {
+                let address = std::str::from_utf8(&record.payload)?.parse()?;
+                println!("{time} #{id} connected from {address}");
+                connections.insert(id, Connection::new(address));
+            }
+            RecordKind::Inbound => {
+                let Some(connection) = connections.get_mut(&id).filter(|c| !c.closed) else {
+                    bail!("Frame for unknown connection #{id}");
+                };
+
+                if let Err(e) = connection.receive(id, &record.payload, args.verbose).await {
+                    println!("{time} #{id} handler error, connection closed: {e}");
+                    connection.closed = true;
+                    if let Some(user_id) = connection.user_id {
+                        Server::disconnect_user(user_id).await?;
+                    }
+                }
+            }
+            RecordKind::Outbound => {
+                if let Some(connection) = connections.get_mut(&id) {
+                    connection.recorded.push_back(record.payload);
+                }
+            }
+            RecordKind::Disconnect => {
+                println!("{time} #{id} disconnected");
+                if let Some(connection) = connections.get_mut(&id).filter(|c| !c.closed) {
+                    connection.closed = true;
+                    if let Some(user_id) = connection.user_id {
+                        Server::disconnect_user(user_id).await?;
+                    }
+                }
+            }
+        }
+
+        // Let spawned broadcasts run, then collect everything the handlers sent
+        tokio::task::yield_now().await;
+        for connection in connections.values_mut() {
+            while let Ok(frame) = connection.rx.try_recv() {
+                connection.replayed.push_back(Bytes::clone(&frame));
+            }
+        }
+    }
+
+    let mut differences = 0;
+    for (id, connection) in &mut connections {
+        let count = connection.recorded.len().max(connection.replayed.len());
+        for index in 0..count {
+            let recorded = connection.recorded.get(index);
+            let replayed = connection.replayed.get(index);
+            if recorded == replayed && !args.verbose {
+                continue;
+            }
+
+            let marker = if recorded == replayed { "  " } else { "!=" };
+            if recorded != replayed {
+                differences += 1;
+            }
+            println!(
+                "#{id} -> {index:>4} {marker} recorded: {} | replayed: {}",
+                describe(recorded),
+                describe(replayed)
+            );
+        }
+    }
+
+    println!(
+        "Replayed {} connections, {} outbound frames differ",
+        connections.len(),
+        differences
+    );
+
+    Ok(())
+}
+
+fn describe(frame: Option<&Bytes>) -> String {
+    let Some(frame) = frame else {
+        return "<none>".to_string();
+    };
+
+    match WormCodec.decode(&mut BytesMut::from(&frame[..])) {
+        Ok(Some(packet)) => format!("{packet:?}"),
+        Ok(None) => format!("<incomplete frame of {} bytes>", frame.len()),
+        Err(e) => format!("<undecodable: {e}>"),
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt::Display;
+use tokio_util::bytes::BytesMut;
+use tokio_util::codec::Decoder;
+use worms_server::net::nation::Nation;
+use worms_server::net::packet_code::PacketCode;
+use worms_server::net::session_access::SessionAccess;
+use worms_server::net::session_type::SessionType;
+use worms_server::net::worms_codec::{WormCodec, CRC_FIRST, CRC_SECOND};
+use worms_server::net::worms_packet::{decode_text, PacketFlags, MAX_DATA_LENGTH, MAX_NAME_LENGTH};
+
+const SESSION_PADDING: usize = 35;
+
+/// Where and why a frame couldn't be decoded.
+struct Problem {
+    offset: usize,
+    message: String,
+}
+
+/// Walks a stream field by field, keeping track of the offset for diagnostics.
+struct Cursor<'a> {
+    stream: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, length: usize, field: &str) -> Result<(usize, &'a [u8]), Problem> {
+        let offset = self.position;
+        let Some(bytes) = self.stream.get(offset..offset + length) else {
+            return Err(Problem {
+                offset: self.stream.len(),
+                message: format!(
+                    "stream ends inside the {field}, {} of {length} bytes present",
+                    self.stream.len() - offset
+                ),
+            });
+        };
+
+        self.position += length;
+        Ok((offset, bytes))
+    }
+
+    fn u8(&mut self, field: &str) -> Result<(usize, u8), Problem> {
+        let (offset, bytes) = self.take(1, field)?;
+        Ok((offset, bytes[0]))
+    }
+
+    fn u32_le(&mut self, field: &str) -> Result<(usize, u32), Problem> {
+        let (offset, bytes) = self.take(4, field)?;
+        Ok((
+            offset,
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        ))
+    }
+
+    fn u32_be(&mut self, field: &str) -> Result<(usize, u32), Problem> {
+        let (offset, bytes) = self.take(4, field)?;
+        Ok((
+            offset,
+            u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        ))
+    }
+}
+
+fn field(offset: usize, name: &str, value: impl Display) {
+    println!("    {offset:08x}  {name:<14} {value}");
+}
+
+fn problem(offset: usize, message: impl Into<String>) -> Problem {
+    Problem {
+        offset,
+        message: message.into(),
+    }
+}
+
+/// Prints every frame of a stream. Returns false if decoding stopped at a problem.
+pub fn dissect_stream(stream: &[u8]) -> bool {
+    let mut cursor = Cursor {
+        stream,
+        position: 0,
+    };
+    let mut frame_number = 0;
+
+    while cursor.position < stream.len() {
+        frame_number += 1;
+        let frame_start = cursor.position;
+
+        if let Err(problem) = dissect_frame(&mut cursor, frame_number) {
+            println!(
+                "error at byte {:08x} (frame #{frame_number} + {}): {}",
+                problem.offset,
+                problem.offset - frame_start,
+                problem.message
+            );
+            print_context(stream, problem.offset);
+            return false;
+        }
+
+        // The dissector and the server should never disagree, but say so if they do
+        let frame = &stream[frame_start..cursor.position];
+        if let Err(e) = WormCodec.decode(&mut BytesMut::from(frame)) {
+            println!("    note: the server's codec rejects this frame: {e}");
+        }
+    }
+
+    println!("{frame_number} frames, {} bytes", stream.len());
+    true
+}
+
+fn dissect_frame(cursor: &mut Cursor, number: usize) -> Result<(), Problem> {
+    let (offset, code) = cursor.u32_le("packet code")?;
+    let code = PacketCode::try_from(code)
+        .map_err(|_| problem(offset, format!("unknown packet code {code} ({code:#010x})")))?;
+    let (flags_offset, raw_flags) = cursor.u32_le("flags")?;
+    let flags = PacketFlags::from_bits_truncate(raw_flags);
+
+    println!("#{number} @ {offset:08x} {code:?} ({})", u32::from(code));
+    let names: Vec<&str> = flags.iter_names().map(|(name, _)| name).collect();
+    field(
+        flags_offset,
+        "flags",
+        format!("{raw_flags:#010x} {}", names.join(" | ")),
+    );
+    let unknown = raw_flags & !PacketFlags::all().bits();
+    if unknown != 0 {
+        println!("    note: unknown flag bits {unknown:#010x} are ignored");
+    }
+
+    for (flag, name) in [
+        (PacketFlags::VALUE0, "value 0"),
+        (PacketFlags::VALUE1, "value 1"),
+        (PacketFlags::VALUE2, "value 2"),
+        (PacketFlags::VALUE3, "value 3"),
+        (PacketFlags::VALUE4, "value 4"),
+        (PacketFlags::VALUE10, "value 10"),
+    ] {
+        if flags.contains(flag) {
+            let (offset, value) = cursor.u32_le(name)?;
+            field(offset, name, format!("{value} ({value:#x})"));
+        }
+    }
+
+    if flags.contains(PacketFlags::DATALENGTH) {
+        let (offset, length) = cursor.u32_le("data length")?;
+        if length as usize > MAX_DATA_LENGTH {
+            return Err(problem(
+                offset,
+                format!("data length {length} is over the maximum of {MAX_DATA_LENGTH}"),
+            ));
+        }
+        field(offset, "data length", length);
+
+        if flags.contains(PacketFlags::DATA) {
+            let (offset, data) = cursor.take(length as usize, "data")?;
+            field(offset, "data", text(data, true));
+        } else {
+            println!("    note: data length without the DATA flag, no data follows");
+        }
+    } else if flags.contains(PacketFlags::DATA) {
+        println!("    note: DATA flag without a data length, no data follows");
+    }
+
+    if flags.contains(PacketFlags::ERRORCODE) {
+        let (offset, value) = cursor.u32_le("error code")?;
+        field(offset, "error code", value);
+    }
+
+    if flags.contains(PacketFlags::NAME) {
+        let (offset, name) = cursor.take(MAX_NAME_LENGTH, "name")?;
+        field(offset, "name", text(name, false));
+    }
+
+    if flags.contains(PacketFlags::SESSION) {
+        dissect_session(cursor)?;
+    }
+
+    Ok(())
+}
+
+fn dissect_session(cursor: &mut Cursor) -> Result<(), Problem> {
+    let (offset, crc) = cursor.u32_be("session CRC")?;
+    if crc != CRC_FIRST {
+        return Err(problem(
+            offset,
+            format!("first session CRC is {crc:#010x}, expected {CRC_FIRST:#010x}"),
+        ));
+    }
+    let (offset, crc) = cursor.u32_le("session CRC")?;
+    if crc != CRC_SECOND {
+        return Err(problem(
+            offset,
+            format!("second session CRC is {crc:#010x}, expected {CRC_SECOND:#010x}"),
+        ));
+    }
+
+    let (offset, nation) = cursor.u8("nation")?;
+    let known = u8::from(Nation::from(nation)) == nation;
+    field(
+        offset,
+        "nation",
+        if known {
+            format!("{:?} ({nation})", Nation::from(nation))
+        } else {
+            format!("unknown ({nation}), shown as no flag")
+        },
+    );
+
+    let (offset, version) = cursor.u8("game version")?;
+    field(offset, "game version", version);
+    if version != 49 {
+        println!("    note: game version is usually 49");
+    }
+
+    let (offset, release) = cursor.u8("game release")?;
+    field(offset, "game release", release);
+
+    let (offset, session_type) = cursor.u8("session type")?;
+    let session_type = SessionType::try_from(session_type)
+        .map_err(|_| problem(offset, format!("invalid session type {session_type}")))?;
+    field(offset, "session type", format!("{session_type:?}"));
+
+    let (offset, access) = cursor.u8("session access")?;
+    let access = SessionAccess::try_from(access)
+        .map_err(|_| problem(offset, format!("invalid session access {access}")))?;
+    field(offset, "access", format!("{access:?}"));
+
+    let (offset, value) = cursor.u8("session")?;
+    if value != 1 {
+        return Err(problem(
+            offset,
+            format!("expected 1 in the session, got {value}"),
+        ));
+    }
+    let (offset, value) = cursor.u8("session")?;
+    if value != 0 {
+        return Err(problem(
+            offset,
+            format!("expected 0 in the session, got {value}"),
+        ));
+    }
+
+    let (offset, padding) = cursor.take(SESSION_PADDING, "session padding")?;
+    if let Some(index) = padding.iter().position(|&byte| byte != 0) {
+        return Err(problem(
+            offset + index,
+            format!("non-zero session padding byte {:#04x}", padding[index]),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Formats a NUL terminated or padded Windows-1252 field.
+fn text(bytes: &[u8], terminated: bool) -> String {
+    let end = bytes.iter().position(|&byte| byte == 0);
+    let content = &bytes[..end.unwrap_or(bytes.len())];
+    let mut text = format!("{:?}", decode_text(content));
+
+    match end {
+        None if terminated => text.push_str(" (no NUL terminator)"),
+        Some(end) if bytes[end..].iter().any(|&byte| byte != 0) => {
+            text.push_str(" (bytes after the NUL are ignored)");
+        }
+        _ => {}
+    }
+
+    text
+}
+
+/// Prints the bytes around `offset` with a marker under it.
+fn print_context(stream: &[u8], offset: usize) {
+    let start = offset.saturating_sub(8);
+    let end = (offset + 8).min(stream.len());
+    if start >= end {
+        return;
+    }
+
+    let hex: Vec<String> = stream[start..end]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    println!("    {start:08x}  {}", hex.join(" "));
+    if offset < stream.len() {
+        println!("              {}^^", " ".repeat((offset - start) * 3));
+    }
+}
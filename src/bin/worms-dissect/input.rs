@@ -0,0 +1,93 @@
+use eyre::{bail, Result};
+
+#[derive(clap::ValueEnum, Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    /// Guess from the contents
+    Auto,
+    /// The bytes of a TCP stream as is
+    Raw,
+    /// Plain hex, `xxd` or `hexdump -C` output
+    Hex,
+    /// A libpcap capture
+    Pcap,
+}
+
+const PCAP_MAGICS: [[u8; 4]; 4] = [
+    [0xd4, 0xc3, 0xb2, 0xa1],
+    [0xa1, 0xb2, 0xc3, 0xd4],
+    [0x4d, 0x3c, 0xb2, 0xa1],
+    [0xa1, 0xb2, 0x3c, 0x4d],
+];
+const PCAPNG_MAGIC: [u8; 4] = [0x0a, 0x0d, 0x0d, 0x0a];
+
+pub fn detect(contents: &[u8]) -> Result<Format> {
+    if let Some(magic) = contents.get(..4) {
+        if PCAP_MAGICS.iter().any(|m| m == magic) {
+            return Ok(Format::Pcap);
+        }
+        if magic == PCAPNG_MAGIC {
+            bail!("pcapng captures aren't supported, save the capture as pcap instead");
+        }
+    }
+
+    let looks_like_text = !contents.is_empty()
+        && contents
+            .iter()
+            .all(|&byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace());
+    Ok(if looks_like_text {
+        Format::Hex
+    } else {
+        Format::Raw
+    })
+}
+
+/// Parses plain hex as well as the output of `xxd` and `hexdump -C`, ignoring offsets and the
+/// ASCII columns.
+pub fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+
+    for (line_number, line) in text.lines().enumerate() {
+        let mut line = line.trim();
+
+        // hexdump -C puts the ASCII column between pipes
+        if let Some(start) = line.find('|') {
+            line = &line[..start];
+        }
+
+        let mut tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.first() {
+            // xxd style offset, the ASCII column follows two spaces after the hex
+            Some(first) if first.ends_with(':') => {
+                let rest = line[first.len()..].trim_start();
+                let hex = rest.split("  ").next().unwrap_or_default();
+                tokens = hex.split_whitespace().collect();
+            }
+            // hexdump style offset, wider than any byte group
+            Some(first) if tokens.len() > 1 && first.len() >= 7 => {
+                tokens.remove(0);
+            }
+            _ => {}
+        }
+
+        for token in tokens {
+            let token = token.trim_start_matches("0x");
+            if token.len() % 2 != 0 {
+                bail!(
+                    "line {}: odd number of hex digits in '{}'",
+                    line_number + 1,
+                    token
+                );
+            }
+
+            for pair in token.as_bytes().chunks(2) {
+                let pair = std::str::from_utf8(pair)?;
+                match u8::from_str_radix(pair, 16) {
+                    Ok(byte) => bytes.push(byte),
+                    Err(_) => bail!("line {}: '{}' isn't hex", line_number + 1, token),
+                }
+            }
+        }
+    }
+
+    Ok(bytes)
+}
@@ -0,0 +1,80 @@
+mod dissect;
+mod input;
+mod pcap;
+
+use crate::input::Format;
+use clap::Parser;
+use eyre::{Result, WrapErr};
+use std::io::Read;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/// Decodes Worms 2 lobby traffic frame by frame, pointing at the byte where decoding fails.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Args {
+    /// Hex dump, raw stream or pcap file, `-` for stdin
+    input: PathBuf,
+
+    /// How to read the input
+    #[arg(short, long, value_enum, default_value = "auto")]
+    format: Format,
+
+    /// Lobby port to pick the TCP streams out of a pcap
+    #[arg(short, long, default_value = "17000")]
+    port: u16,
+}
+
+fn main() -> Result<ExitCode> {
+    color_eyre::install()?;
+    let args = Args::parse();
+
+    let contents = if args.input.as_os_str() == "-" {
+        let mut contents = Vec::new();
+        std::io::stdin().read_to_end(&mut contents)?;
+        contents
+    } else {
+        std::fs::read(&args.input)
+            .wrap_err_with(|| format!("Unable to read {}", args.input.display()))?
+    };
+
+    let format = match args.format {
+        Format::Auto => input::detect(&contents)?,
+        format => format,
+    };
+
+    let success = match format {
+        Format::Pcap => {
+            let streams = pcap::read_streams(&contents, args.port)?;
+            if streams.is_empty() {
+                println!("No TCP traffic on port {} found", args.port);
+            }
+
+            let mut success = true;
+            for (flow, stream) in streams {
+                println!("== {flow} ({} bytes) ==", stream.bytes.len());
+                let bytes = match stream.gap_at {
+                    Some(gap) => {
+                        println!("note: segments are missing after byte {gap:08x}");
+                        &stream.bytes[..gap]
+                    }
+                    None => &stream.bytes[..],
+                };
+                success &= dissect::dissect_stream(bytes);
+                println!();
+            }
+            success
+        }
+        Format::Hex => {
+            let text = std::str::from_utf8(&contents).wrap_err("Hex dump isn't text")?;
+            dissect::dissect_stream(&input::parse_hex(text)?)
+        }
+        Format::Raw | Format::Auto => dissect::dissect_stream(&contents),
+    };
+
+    Ok(if success {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
@@ -0,0 +1,241 @@
+use eyre::{bail, Result};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+const TCP_SYN: u8 = 0x02;
+
+/// One direction of a TCP connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Flow {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+}
+
+impl Display for Flow {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} -> {}", self.source, self.destination)
+    }
+}
+
+/// The reassembled payload of a flow.
+#[derive(Default)]
+pub struct Stream {
+    pub bytes: Vec<u8>,
+    next_sequence: Option<u32>,
+    pending: BTreeMap<u32, Vec<u8>>,
+    /// Bytes that never arrived, the stream can't be trusted past this point.
+    pub gap_at: Option<usize>,
+}
+
+impl Stream {
+    fn push(&mut self, sequence: u32, syn: bool, payload: &[u8]) {
+        if syn {
+            self.next_sequence = Some(sequence.wrapping_add(1));
+            return;
+        }
+        if payload.is_empty() {
+            return;
+        }
+
+        let next = *self.next_sequence.get_or_insert(sequence);
+        let offset = sequence.wrapping_sub(next) as i32;
+        if offset > 0 {
+            // Arrived early, wait for the segments before it
+            self.pending.insert(sequence, payload.to_vec());
+            return;
+        }
+
+        // Skip whatever was already received in a retransmission
+        let already_received = offset.unsigned_abs() as usize;
+        if already_received < payload.len() {
+            self.append(&payload[already_received..]);
+        }
+
+        while let Some((&sequence, _)) = self.pending.first_key_value() {
+            let next = self.next_sequence.unwrap_or(sequence);
+            let offset = sequence.wrapping_sub(next) as i32;
+            if offset > 0 {
+                break;
+            }
+
+            let payload = self.pending.remove(&sequence).unwrap_or_default();
+            let already_received = offset.unsigned_abs() as usize;
+            if already_received < payload.len() {
+                self.append(&payload[already_received..]);
+            }
+        }
+    }
+
+    fn append(&mut self, payload: &[u8]) {
+        self.bytes.extend_from_slice(payload);
+        self.next_sequence = self
+            .next_sequence
+            .map(|next| next.wrapping_add(payload.len() as u32));
+    }
+
+    fn finish(&mut self) {
+        if !self.pending.is_empty() {
+            self.gap_at = Some(self.bytes.len());
+        }
+    }
+}
+
+/// Reads a libpcap capture and reassembles the TCP streams to or from `port`.
+pub fn read_streams(contents: &[u8], port: u16) -> Result<BTreeMap<Flow, Stream>> {
+    let Some(header) = contents.get(..24) else {
+        bail!("pcap file is too short");
+    };
+
+    let little_endian = matches!(
+        header[..4],
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1]
+    );
+    let read_u32 = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    };
+    let link_type = read_u32(&header[20..24]);
+
+    let mut streams = BTreeMap::<Flow, Stream>::new();
+    let mut offset = 24;
+    while offset < contents.len() {
+        let Some(record) = contents.get(offset..offset + 16) else {
+            bail!("pcap record header at byte {offset} is cut short");
+        };
+        let included = read_u32(&record[8..12]) as usize;
+        let Some(packet) = contents.get(offset + 16..offset + 16 + included) else {
+            bail!("pcap record at byte {offset} is cut short");
+        };
+        offset += 16 + included;
+
+        let Some(ip) = strip_link_layer(link_type, packet)? else {
+            continue;
+        };
+        let Some((flow, sequence, flags, payload)) = parse_tcp(ip) else {
+            continue;
+        };
+        if flow.source.port() != port && flow.destination.port() != port {
+            continue;
+        }
+
+        streams
+            .entry(flow)
+            .or_default()
+            .push(sequence, flags & TCP_SYN != 0, payload);
+    }
+
+    streams.values_mut().for_each(Stream::finish);
+    Ok(streams)
+}
+
+fn strip_link_layer(link_type: u32, packet: &[u8]) -> Result<Option<&[u8]>> {
+    let (ether_type, payload) = match link_type {
+        LINKTYPE_ETHERNET => {
+            let Some(header) = packet.get(..14) else {
+                return Ok(None);
+            };
+            let mut ether_type = u16::from_be_bytes([header[12], header[13]]);
+            let mut start = 14;
+            // Skip a VLAN tag
+            if ether_type == 0x8100 && packet.len() >= 18 {
+                ether_type = u16::from_be_bytes([packet[16], packet[17]]);
+                start = 18;
+            }
+            (ether_type, &packet[start..])
+        }
+        LINKTYPE_LINUX_SLL => {
+            let Some(header) = packet.get(..16) else {
+                return Ok(None);
+            };
+            (u16::from_be_bytes([header[14], header[15]]), &packet[16..])
+        }
+        LINKTYPE_NULL => {
+            let Some(header) = packet.get(..4) else {
+                return Ok(None);
+            };
+            // The address family is in host byte order of the capturing machine
+            let family = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+            let family = if family > 0xffff {
+                family.swap_bytes()
+            } else {
+                family
+            };
+            let ether_type = match family {
+                2 => 0x0800,
+                24 | 28 | 30 => 0x86dd,
+                _ => return Ok(None),
+            };
+            (ether_type, &packet[4..])
+        }
+        LINKTYPE_RAW => (0, packet),
+        _ => bail!("Unsupported pcap link type {link_type}"),
+    };
+
+    Ok(match ether_type {
+        0 | 0x0800 | 0x86dd => Some(payload),
+        _ => None,
+    })
+}
+
+fn parse_tcp(ip: &[u8]) -> Option<(Flow, u32, u8, &[u8])> {
+    let version = ip.first()? >> 4;
+    let (source, destination, tcp) = match version {
+        4 => {
+            let header_length = usize::from(ip[0] & 0x0f) * 4;
+            let total_length = usize::from(u16::from_be_bytes([*ip.get(2)?, *ip.get(3)?]));
+            if *ip.get(9)? != 6 {
+                return None;
+            }
+            let source: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let destination: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            let end = total_length.min(ip.len());
+            (
+                IpAddr::from(Ipv4Addr::from(source)),
+                IpAddr::from(Ipv4Addr::from(destination)),
+                ip.get(header_length..end)?,
+            )
+        }
+        6 => {
+            // Extension headers aren't followed, the lobby doesn't use them
+            if *ip.get(6)? != 6 {
+                return None;
+            }
+            let payload_length = usize::from(u16::from_be_bytes([*ip.get(4)?, *ip.get(5)?]));
+            let source: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let destination: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            let end = (40 + payload_length).min(ip.len());
+            (
+                IpAddr::from(Ipv6Addr::from(source)),
+                IpAddr::from(Ipv6Addr::from(destination)),
+                ip.get(40..end)?,
+            )
+        }
+        _ => return None,
+    };
+
+    let source_port = u16::from_be_bytes([*tcp.first()?, *tcp.get(1)?]);
+    let destination_port = u16::from_be_bytes([*tcp.get(2)?, *tcp.get(3)?]);
+    let sequence = u32::from_be_bytes(tcp.get(4..8)?.try_into().ok()?);
+    let data_offset = usize::from(tcp.get(12)? >> 4) * 4;
+    let flags = *tcp.get(13)?;
+
+    Some((
+        Flow {
+            source: SocketAddr::new(source, source_port),
+            destination: SocketAddr::new(destination, destination_port),
+        },
+        sequence,
+        flags,
+        tcp.get(data_offset..)?,
+    ))
+}
@@ -0,0 +1,132 @@
+use crate::config::Config;
+use crate::database::user::User;
+use crate::database::{Database, DATABASE};
+use crate::greetings;
+use crate::moderation::{format_duration, split_word};
+use crate::net::nation::Nation;
+use crate::net::packet_code::PacketCode;
+use crate::net::worms_packet::WormsPacket;
+use crate::nicknames;
+use crate::rooms;
+use crate::server::Server;
+use eyre::{bail, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, LazyLock};
+use tokio::sync::mpsc;
+use tokio_util::bytes::Bytes;
+
+// A user the server speaks as. It has no connection, it's listed in every room, answers private
+// messages and is who announcements come from.
+
+/// The bot's id, taken like any user's so the game doesn't treat it differently.
+static BOT_ID: LazyLock<u32> = LazyLock::new(Database::get_next_id);
+
+const HELP: [&str; 12] = [
+    "help: Show this list",
+    "motd: Show the message of the day",
+    "stats: Show how many are online and how long the server's been up",
+    "identify <password>: Prove the name you're using is yours",
+    "register <password>: Register the name you're using",
+    "password <new password>: Change your password once identified",
+    "unlock <room> <password>: Give the password for a room before joining it",
+    "lock <password>: Set a password on the room you own",
+    "open: Take the password off the room you own",
+    "topic [text]: Set the topic of the room you own, or clear it",
+    "kick <name>: Send someone out of the room you own for good",
+    "owner <name>: Give the room you own to someone in it",
+];
+
+/// Adds the bot to the users, where it stays until the server stops.
+pub fn start() {
+    // Nothing's ever sent to the bot, the sender is closed from the start
+    let (tx, _) = mpsc::channel::<Arc<Bytes>>(1);
+    let bot = User::new(
+        tx.downgrade(),
+        id(),
+        &Config::current().bot.name,
+        Nation::None,
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+    );
+    DATABASE.users.insert(id(), bot);
+}
+
+pub fn id() -> u32 {
+    *BOT_ID
+}
+
+pub fn is_bot(user_id: u32) -> bool {
+    user_id == id()
+}
+
+/// Builds a chat line from the bot to everyone in a room.
+pub fn chat_packet(room_id: u32, message: &str) -> Result<Arc<Bytes>> {
+    WormsPacket::create(PacketCode::ChatRoom)
+        .with_value_0(id())
+        .with_value_3(room_id)
+        .with_data(&format!(
+            "GRP:[ {} ]  {message}",
+            Config::current().bot.name
+        ))
+        .build()
+}
+
+/// Builds a private chat line from the bot to a user.
+pub fn private_packet(user_id: u32, message: &str) -> Result<Arc<Bytes>> {
+    WormsPacket::create(PacketCode::ChatRoom)
+        .with_value_0(id())
+        .with_value_3(user_id)
+        .with_data(&format!(
+            "PRV:[ {} ]  {message}",
+            Config::current().bot.name
+        ))
+        .build()
+}
+
+/// Answers a private message to the bot, returning the lines to reply with.
+pub async fn handle_message(user_id: u32, message: &str) -> Result<Vec<String>> {
+    let (command, argument) = split_word(message.trim().trim_start_matches('/'));
+
+    let reply = match command.to_lowercase().as_str() {
+        "help" | "" => HELP.iter().map(|line| line.to_string()).collect(),
+        "motd" => {
+            let name = DATABASE.users.get(&user_id).map(|u| u.name.clone());
+            let motd = greetings::motd(name.as_deref().unwrap_or_default());
+            if motd.is_empty() {
+                vec!["There's no message of the day".to_string()]
+            } else {
+                motd
+            }
+        }
+        "stats" => vec![format!(
+            "{} users, {} rooms and {} games online, up for {}",
+            Database::player_count(),
+            DATABASE.rooms.len(),
+            DATABASE.games.len(),
+            format_duration(Server::uptime())
+        )],
+        "identify" | "register" | "password" => nicknames::handle_message(user_id, message).await?,
+        "unlock" => vec![rooms::unlock(user_id, argument).await?],
+        "lock" => vec![rooms::lock(user_id, argument).await?],
+        "open" => vec![rooms::open(user_id)?],
+        "topic" => vec![rooms::set_topic(user_id, argument).await?],
+        "kick" => vec![rooms::kick(user_id, argument).await?],
+        "owner" => vec![rooms::transfer(user_id, argument).await?],
+        _ => bail!("Unknown command '{command}', try help"),
+    };
+
+    Ok(reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn commands_ignore_case_and_slashes() {
+        assert_eq!(handle_message(0, "/HELP").await.unwrap().len(), HELP.len());
+        assert_eq!(handle_message(0, "").await.unwrap().len(), HELP.len());
+
+        let error = handle_message(0, "dance").await.unwrap_err().to_string();
+        assert_eq!(error, "Unknown command 'dance', try help");
+    }
+}
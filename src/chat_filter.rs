@@ -0,0 +1,339 @@
+use crate::audit::{self, Actor, Event};
+use crate::config::{ChatFilterConfig, Config};
+use crate::moderation::{self, format_duration};
+use dashmap::DashMap;
+use eyre::{Result, WrapErr};
+use log::{error, info};
+use parking_lot::Mutex;
+use regex::{Regex, RegexBuilder};
+use std::collections::VecDeque;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+
+// Checks what users say to each other before it's sent, only the message itself and not the
+// `GRP:[ name ]  ` or `PRV:[ name ]  ` in front of it. Stopped messages count as strikes against
+// the sender, enough of them and they're muted, then kicked.
+
+/// Built from the config it was last used with, rebuilt when a reload changes it.
+static FILTER: LazyLock<Mutex<Option<CachedFilter>>> = LazyLock::new(|| Mutex::new(None));
+
+type CachedFilter = (ChatFilterConfig, Arc<ChatFilter>);
+
+/// What each sender said lately and the strikes against them, by lowercase name so logging in
+/// again doesn't clear them.
+static SENDERS: LazyLock<DashMap<String, SenderRecord>> = LazyLock::new(DashMap::new);
+
+static LINKS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\b(?:https?://|www\.)\S|\b[a-z0-9-]+\.(?:com|net|org|info|biz|io|gg|co|me|ly|tv|xyz|eu|uk|de|ru|us)\b",
+    )
+    .expect("Link pattern should compile")
+});
+
+#[derive(Default)]
+struct SenderRecord {
+    /// When each message was sent, with its text lowercased
+    recent: VecDeque<(Instant, String)>,
+    strikes: VecDeque<Instant>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Send,
+    /// Send this instead, with the masked words starred out
+    Masked(String),
+    /// Don't send it, for the reason given
+    Blocked(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Penalty {
+    None,
+    /// Strikes left before the next penalty, and what it is
+    Warning(u32, &'static str),
+    Muted(Duration),
+    Kicked,
+}
+
+impl Penalty {
+    /// What to tell the sender, if anything.
+    pub fn explain(&self) -> Option<String> {
+        match self {
+            Penalty::None => None,
+            Penalty::Warning(1, next) => Some(format!("Once more and you'll be {next}")),
+            Penalty::Warning(left, next) => Some(format!("{left} more and you'll be {next}")),
+            Penalty::Muted(duration) => Some(format!(
+                "You are muted for {} for breaking the chat rules",
+                format_duration(*duration)
+            )),
+            Penalty::Kicked => Some("You were kicked for breaking the chat rules".to_string()),
+        }
+    }
+}
+
+/// The rules a message is checked against on its own.
+#[derive(Default)]
+pub struct ChatFilter {
+    masked_words: Option<Regex>,
+    blocked_words: Option<Regex>,
+    blocked_patterns: Vec<Regex>,
+    block_links: bool,
+    max_length: usize,
+    max_caps_percent: u8,
+    caps_min_letters: usize,
+}
+
+impl ChatFilter {
+    pub fn new(config: &ChatFilterConfig) -> Result<Self> {
+        let blocked_patterns = config
+            .blocked_patterns
+            .iter()
+            .map(|pattern| {
+                RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .wrap_err_with(|| {
+                        format!("chat_filter.blocked_patterns: bad pattern {pattern}")
+                    })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            masked_words: word_pattern(&config.masked_words),
+            blocked_words: word_pattern(&config.blocked_words),
+            blocked_patterns,
+            block_links: config.block_links,
+            max_length: config.max_length,
+            max_caps_percent: config.max_caps_percent,
+            caps_min_letters: config.caps_min_letters,
+        })
+    }
+
+    /// Checks a message against everything but how often it's been sent.
+    pub fn inspect(&self, text: &str) -> Verdict {
+        if self.max_length != 0 && text.chars().count() > self.max_length {
+            return Verdict::Blocked(format!("it's longer than {} characters", self.max_length));
+        }
+        if self
+            .blocked_words
+            .as_ref()
+            .is_some_and(|r| r.is_match(text))
+            || self.blocked_patterns.iter().any(|r| r.is_match(text))
+        {
+            return Verdict::Blocked("it has words that aren't allowed".to_string());
+        }
+        if self.block_links && LINKS.is_match(text) {
+            return Verdict::Blocked("links aren't allowed".to_string());
+        }
+        if self.is_shouting(text) {
+            return Verdict::Blocked("it's mostly capitals".to_string());
+        }
+
+        match &self.masked_words {
+            Some(masked) if masked.is_match(text) => {
+                let text = masked.replace_all(text, |captures: &regex::Captures| {
+                    "*".repeat(captures[0].chars().count())
+                });
+                Verdict::Masked(text.into_owned())
+            }
+            _ => Verdict::Send,
+        }
+    }
+
+    fn is_shouting(&self, text: &str) -> bool {
+        if self.max_caps_percent == 0 {
+            return false;
+        }
+        let (letters, capitals) = text
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .fold((0, 0), |(letters, capitals), c| {
+                (letters + 1, capitals + usize::from(c.is_uppercase()))
+            });
+        letters >= self.caps_min_letters.max(1)
+            && capitals * 100 > letters * usize::from(self.max_caps_percent)
+    }
+}
+
+/// Matches any of the words as a whole word ignoring case, or nothing without any.
+fn word_pattern(words: &[String]) -> Option<Regex> {
+    let words: Vec<String> = words
+        .iter()
+        .map(|word| word.trim())
+        .filter(|word| !word.is_empty())
+        .map(regex::escape)
+        .collect();
+    if words.is_empty() {
+        return None;
+    }
+    let pattern = format!(r"(?i)\b(?:{})\b", words.join("|"));
+    Some(Regex::new(&pattern).expect("Escaped words should compile"))
+}
+
+fn current_filter(config: &ChatFilterConfig) -> Arc<ChatFilter> {
+    let mut cached = FILTER.lock();
+    if let Some((built_from, filter)) = cached.as_ref() {
+        if built_from == config {
+            return Arc::clone(filter);
+        }
+    }
+
+    // Already checked when the config was read
+    let filter = Arc::new(ChatFilter::new(config).unwrap_or_else(|e| {
+        error!("Chat filter: {e:#}");
+        ChatFilter::default()
+    }));
+    *cached = Some((config.clone(), Arc::clone(&filter)));
+    filter
+}
+
+/// Checks a message a user is sending, remembering it to catch them repeating it.
+pub fn check(name: &str, text: &str) -> Verdict {
+    let config = Config::current();
+    let settings = &config.chat_filter;
+    let verdict = current_filter(settings).inspect(text);
+    if matches!(verdict, Verdict::Blocked(_)) || settings.max_repeats == 0 {
+        return verdict;
+    }
+
+    let now = Instant::now();
+    let window = Duration::from_secs(settings.repeat_window_secs);
+    let message = text.trim().to_lowercase();
+    let mut sender = SENDERS.entry(name.to_lowercase()).or_default();
+    sender
+        .recent
+        .retain(|(sent, _)| now.duration_since(*sent) < window);
+    let repeats = sender.recent.iter().filter(|(_, m)| *m == message).count();
+    if repeats >= settings.max_repeats {
+        return Verdict::Blocked("you've sent it too often already".to_string());
+    }
+    sender.recent.push_back((now, message));
+    verdict
+}
+
+/// Counts a stopped message against the user who sent it, muting them once they've had
+/// `mute_after_strikes`. Kicking them at `kick_after_strikes` is left to the caller, after they've
+/// been told why.
+pub fn strike(user_id: u32, name: &str) -> Penalty {
+    let config = Config::current();
+    let settings = &config.chat_filter;
+
+    let now = Instant::now();
+    let window = Duration::from_secs(settings.strike_window_secs);
+    let strikes = {
+        let mut sender = SENDERS.entry(name.to_lowercase()).or_default();
+        sender
+            .strikes
+            .retain(|struck| now.duration_since(*struck) < window);
+        sender.strikes.push_back(now);
+        sender.strikes.len() as u32
+    };
+
+    let (mute_after, kick_after) = (settings.mute_after_strikes, settings.kick_after_strikes);
+    let penalty = if kick_after != 0 && strikes >= kick_after {
+        Penalty::Kicked
+    } else if mute_after != 0 && strikes >= mute_after {
+        let duration = Duration::from_secs(settings.mute_secs);
+        moderation::mute(name, duration);
+        Penalty::Muted(duration)
+    } else {
+        let next = [(mute_after, "muted"), (kick_after, "kicked")]
+            .into_iter()
+            .filter(|(after, _)| *after > strikes)
+            .min_by_key(|(after, _)| *after);
+        match next {
+            Some((after, what)) => Penalty::Warning(after - strikes, what),
+            None => Penalty::None,
+        }
+    };
+
+    let action = match penalty {
+        Penalty::Muted(duration) => format!("mute {name} for {}", format_duration(duration)),
+        Penalty::Kicked => format!("kick {name}"),
+        _ => return penalty,
+    };
+    info!("Chat filter: {action} after {strikes} strikes");
+    let event = Event::Moderation {
+        by: "chat filter",
+        action,
+    };
+    audit::record(Actor::user(user_id), event);
+    penalty
+}
+
+/// Drops what's kept about a user who left, unless they have strikes still counting.
+pub fn forget(name: &str) {
+    let window = Duration::from_secs(Config::current().chat_filter.strike_window_secs);
+    SENDERS.remove_if(&name.to_lowercase(), |_, sender| {
+        sender
+            .strikes
+            .back()
+            .is_none_or(|struck| struck.elapsed() >= window)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(config: ChatFilterConfig) -> ChatFilter {
+        ChatFilter::new(&config).unwrap()
+    }
+
+    #[test]
+    fn masked_words_are_starred_out() {
+        let filter = filter(ChatFilterConfig {
+            masked_words: vec!["darn".to_string()],
+            ..Default::default()
+        });
+        assert_eq!(
+            filter.inspect("Darn it, darnation"),
+            Verdict::Masked("**** it, darnation".to_string())
+        );
+        assert_eq!(filter.inspect("hello"), Verdict::Send);
+    }
+
+    #[test]
+    fn blocked_words_patterns_and_links_stop_messages() {
+        let filter = filter(ChatFilterConfig {
+            blocked_words: vec!["cheat".to_string()],
+            blocked_patterns: vec![r"free\s+skins".to_string()],
+            block_links: true,
+            ..Default::default()
+        });
+        assert!(matches!(filter.inspect("CHEAT here"), Verdict::Blocked(_)));
+        assert!(matches!(filter.inspect("Free  Skins"), Verdict::Blocked(_)));
+        assert!(matches!(
+            filter.inspect("go to example.com"),
+            Verdict::Blocked(_)
+        ));
+        assert!(matches!(filter.inspect("www.example"), Verdict::Blocked(_)));
+        assert_eq!(filter.inspect("cheating is bad. ok"), Verdict::Send);
+    }
+
+    #[test]
+    fn length_and_capitals_are_limited() {
+        let filter = filter(ChatFilterConfig {
+            max_length: 20,
+            max_caps_percent: 50,
+            caps_min_letters: 5,
+            ..Default::default()
+        });
+        assert!(matches!(
+            filter.inspect(&"a".repeat(21)),
+            Verdict::Blocked(_)
+        ));
+        assert!(matches!(filter.inspect("HELLO THERE"), Verdict::Blocked(_)));
+        assert_eq!(filter.inspect("GG"), Verdict::Send);
+        assert_eq!(filter.inspect("Hello There"), Verdict::Send);
+    }
+
+    #[test]
+    fn bad_patterns_are_refused() {
+        let config = ChatFilterConfig {
+            blocked_patterns: vec!["(unclosed".to_string()],
+            ..Default::default()
+        };
+        assert!(ChatFilter::new(&config).is_err());
+    }
+}
@@ -0,0 +1,854 @@
+use crate::chat_filter::ChatFilter;
+use crate::greetings::{check_template, ROOM_VARIABLES, VARIABLES};
+use crate::names::CharacterClass;
+use crate::net::address_range::AddressRange;
+use crate::net::worms_packet::{encode_text, MAX_DATA_LENGTH, MAX_NAME_LENGTH};
+use eyre::{bail, eyre, OptionExt, Result, WrapErr};
+use log::LevelFilter;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, OnceLock};
+use std::time::Duration;
+use toml::{Table, Value};
+
+static CONFIG: LazyLock<RwLock<Arc<Config>>> = LazyLock::new(RwLock::default);
+static SOURCE: OnceLock<ConfigSource> = OnceLock::new();
+
+/// Environment variables starting with this override single settings, `WORMS_LIMITS__PACKETS_PER_SECOND=10`
+/// sets `packets_per_second` in the `[limits]` section.
+const ENV_PREFIX: &str = "WORMS_";
+/// Holds the path of the config file itself, not a setting.
+pub const CONFIG_PATH_ENV: &str = "WORMS_CONFIG";
+/// Turns the admin console off, a command line switch rather than a setting.
+pub const NO_CONSOLE_ENV: &str = "WORMS_NO_CONSOLE";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub limits: LimitsConfig,
+    pub messages: MessagesConfig,
+    pub rooms: RoomsConfig,
+    pub logging: LoggingConfig,
+    pub admin_api: AdminApiConfig,
+    pub bans: BansConfig,
+    pub moderation: ModerationConfig,
+    pub nicknames: NicknamesConfig,
+    pub bot: BotConfig,
+    pub history: HistoryConfig,
+    pub audit: AuditConfig,
+    pub chat_filter: ChatFilterConfig,
+    pub names: NamesConfig,
+    pub room_names: RoomNamesConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address to listen on
+    pub ip: IpAddr,
+    /// Port to listen on
+    pub port: u16,
+    /// Seconds a logged in client may stay silent before it's disconnected
+    pub authorized_ttl_secs: NonZeroU64,
+    /// Seconds a new connection has to send its login packet
+    pub unauthorized_ttl_secs: NonZeroU64,
+    /// Seconds between announcing a shutdown and disconnecting everyone
+    pub shutdown_grace_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Connections accepted per second from one address
+    pub accepts_per_second: NonZeroU32,
+    /// Connections open at once from one address, 0 for no limit
+    pub max_sessions_per_ip: u32,
+    /// Login attempts per minute from one address
+    pub logins_per_minute: NonZeroU32,
+    /// Failed logins in a row from one address before it's blocked, 0 to never block
+    pub max_failed_logins: u32,
+    /// Seconds an address stays blocked after too many failed logins
+    pub failed_login_block_secs: u64,
+    /// Leading bits of an IPv4 address the limits above count by, 24 counts a /24 as one address
+    pub ipv4_prefix: u8,
+    /// Leading bits of an IPv6 address the limits above count by, 64 counts a /64 as one address
+    pub ipv6_prefix: u8,
+    /// Ranges the limits per address don't apply to, like the LAN the server is on
+    pub exempt: Vec<AddressRange>,
+    /// Packets handled per second from one client
+    pub packets_per_second: NonZeroU32,
+    /// Packets in a row over the packet quota before the client is disconnected
+    pub max_limited_count: u32,
+    /// Packets queued for a client before handlers wait on it, a reload only affects new
+    /// connections
+    pub outbound_queue_size: NonZeroUsize,
+    /// Users, rooms and games to allocate space for up front
+    pub starting_capacity: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MessagesConfig {
+    /// Sent to every user after logging in, a chat message per line, empty to send nothing.
+    /// `{name}`, `{online}` and `{uptime}` are filled in.
+    pub motd: String,
+    /// Sent to users joining a room without a greeting of its own, empty to send nothing. Takes
+    /// the same variables as the MOTD and `{room}`.
+    pub room_greeting: String,
+    /// Sent to every user when a shutdown starts, `{seconds}` is replaced by the grace period
+    pub shutdown_notice: String,
+    /// Greetings for rooms by name ignoring case, instead of `room_greeting`
+    pub room_greetings: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomsConfig {
+    /// Close rooms once the last user or game leaves them, permanent ones stay open
+    pub remove_when_empty: bool,
+    /// Users in a room at once, 0 for no limit. Permanent rooms can have a capacity of their own.
+    pub max_users: u32,
+    /// Rooms one user can own at once, 0 for no limit
+    pub max_per_user: u32,
+    /// Rooms open at once, permanent ones included, 0 for no limit
+    pub max_rooms: u32,
+    /// File the permanent rooms are kept in. Empty keeps them in memory only.
+    pub file: PathBuf,
+    /// Wrong passwords a user can give for rooms before they have to wait, 0 for no limit
+    pub max_unlock_attempts: u32,
+    /// Seconds a user can't try another room password for after too many wrong ones
+    pub unlock_lockout_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Most detailed level logged: off, error, warn, info, debug or trace. `RUST_LOG` still
+    /// limits it when set.
+    pub level: LevelFilter,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminApiConfig {
+    /// Serve the admin HTTP API
+    pub enabled: bool,
+    /// Address to listen on, keep it local unless something in front of it adds TLS
+    pub ip: IpAddr,
+    /// Port to listen on
+    pub port: u16,
+    /// Bearer token every request has to carry
+    pub token: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BansConfig {
+    /// File the bans are kept in, created on the first ban. Empty keeps them in memory only.
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationConfig {
+    /// Users who can use the moderator chat commands, by name ignoring case
+    pub moderators: Vec<String>,
+    /// Seconds a `/mute` lasts when it doesn't say
+    pub default_mute_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NicknamesConfig {
+    /// File the registered names are kept in, created on the first registration. Empty keeps
+    /// them in memory only.
+    pub file: PathBuf,
+    /// Seconds a registered name has to identify before it's disconnected
+    pub grace_secs: NonZeroU64,
+    /// Let users register the name they're using, otherwise only admins can register names
+    pub self_register: bool,
+    /// Wrong passwords for a name before whoever's trying is kicked, 0 for no limit
+    pub max_identify_attempts: u32,
+    /// Seconds a name can't try another password for after too many wrong ones
+    pub identify_lockout_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BotConfig {
+    /// Name of the user the server speaks as, nobody can log in with it
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Recent messages kept for each room and shown to users joining it, 0 to keep none
+    pub depth: usize,
+    /// Seconds a message is shown to users joining for after it was sent
+    pub max_age_secs: NonZeroU64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Directory the audit log is written to, a file a day. Empty writes none.
+    pub dir: PathBuf,
+    /// Days of audit log kept, older files are deleted. 0 keeps them all.
+    pub retention_days: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatFilterConfig {
+    /// Words replaced with `*` in messages, whole words ignoring case
+    pub masked_words: Vec<String>,
+    /// Words that stop a message from being sent, whole words ignoring case
+    pub blocked_words: Vec<String>,
+    /// Regular expressions that stop a message from being sent when they match anywhere in it
+    pub blocked_patterns: Vec<String>,
+    /// Stop messages with web addresses in them
+    pub block_links: bool,
+    /// Longest message in characters, 0 for no limit
+    pub max_length: usize,
+    /// Percentage of a message's letters that may be capitals, 0 for no limit
+    pub max_caps_percent: u8,
+    /// Letters a message needs before the capitals limit applies
+    pub caps_min_letters: usize,
+    /// Times the same message may be sent within `repeat_window_secs`, 0 for no limit
+    pub max_repeats: usize,
+    pub repeat_window_secs: u64,
+    /// Stopped messages within `strike_window_secs` before the sender is muted, 0 to never mute
+    pub mute_after_strikes: u32,
+    /// Seconds the sender is muted for
+    pub mute_secs: u64,
+    /// Stopped messages within `strike_window_secs` before the sender is kicked, 0 to never kick
+    pub kick_after_strikes: u32,
+    pub strike_window_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NamesConfig {
+    /// Fewest characters a name can have, not counting spaces around it
+    pub min_length: NonZeroUsize,
+    /// Kinds of characters names can have: letters, digits, spaces and punctuation
+    pub allowed_characters: Vec<CharacterClass>,
+    /// Names only usable once registered, with `*` and `?` wildcards, ignoring case and lookalikes
+    pub reserved: Vec<String>,
+    /// Treat names that only differ by lookalike characters, like `0` and `O`, as the same
+    pub fold_confusables: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomNamesConfig {
+    /// Fewest characters a room name can have, not counting spaces around it
+    pub min_length: NonZeroUsize,
+    /// Most characters a room name can have, 0 for as many as the game sends
+    pub max_length: usize,
+    /// Kinds of characters room names can have: letters, digits, spaces and punctuation
+    pub allowed_characters: Vec<CharacterClass>,
+    /// Words room names can't have, ignoring case and lookalikes, even spaced out letter by letter
+    pub banned_words: Vec<String>,
+    /// Starts of names kept for official rooms, only moderators can make rooms with them
+    pub reserved_prefixes: Vec<String>,
+}
+
+/// Where the configuration is read from, kept to read it again on reload.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSource {
+    pub path: Option<PathBuf>,
+    /// Command line overrides, these win over the file and the environment
+    pub ip: Option<IpAddr>,
+    pub port: Option<u16>,
+}
+
+/// What a reload changed.
+#[derive(Debug, Default)]
+pub struct ReloadReport {
+    /// Settings that changed and are now in effect
+    pub applied: Vec<String>,
+    /// Settings that changed but are only read at startup
+    pub needs_restart: Vec<&'static str>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 17000,
+            authorized_ttl_secs: NonZeroU64::new(10 * 60).unwrap(),
+            unauthorized_ttl_secs: NonZeroU64::new(3).unwrap(),
+            shutdown_grace_secs: 5,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            accepts_per_second: NonZeroU32::new(1).unwrap(),
+            max_sessions_per_ip: 10,
+            logins_per_minute: NonZeroU32::new(20).unwrap(),
+            max_failed_logins: 5,
+            failed_login_block_secs: 5 * 60,
+            ipv4_prefix: 32,
+            ipv6_prefix: 128,
+            exempt: Vec::new(),
+            packets_per_second: NonZeroU32::new(5).unwrap(),
+            max_limited_count: 10,
+            outbound_queue_size: NonZeroUsize::new(100).unwrap(),
+            starting_capacity: 1024,
+        }
+    }
+}
+
+impl Default for MessagesConfig {
+    fn default() -> Self {
+        Self {
+            motd: String::new(),
+            room_greeting: String::new(),
+            shutdown_notice: "The server is shutting down in {seconds} seconds".to_string(),
+            room_greetings: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for RoomsConfig {
+    fn default() -> Self {
+        Self {
+            remove_when_empty: true,
+            max_users: 0,
+            max_per_user: 2,
+            max_rooms: 200,
+            file: PathBuf::from("rooms.toml"),
+            max_unlock_attempts: 5,
+            unlock_lockout_secs: 5 * 60,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+        }
+    }
+}
+
+impl Default for AdminApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ip: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 17001,
+            token: String::new(),
+        }
+    }
+}
+
+impl Default for BansConfig {
+    fn default() -> Self {
+        Self {
+            file: PathBuf::from("bans.toml"),
+        }
+    }
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        Self {
+            moderators: Vec::new(),
+            default_mute_secs: 10 * 60,
+        }
+    }
+}
+
+impl Default for NicknamesConfig {
+    fn default() -> Self {
+        Self {
+            file: PathBuf::from("nicknames.toml"),
+            grace_secs: NonZeroU64::new(60).unwrap(),
+            self_register: true,
+            max_identify_attempts: 3,
+            identify_lockout_secs: 5 * 60,
+        }
+    }
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        Self {
+            name: "Server".to_string(),
+        }
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            depth: 20,
+            max_age_secs: NonZeroU64::new(3600).unwrap(),
+        }
+    }
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("audit"),
+            retention_days: 30,
+        }
+    }
+}
+
+impl Default for ChatFilterConfig {
+    fn default() -> Self {
+        Self {
+            masked_words: Vec::new(),
+            blocked_words: Vec::new(),
+            blocked_patterns: Vec::new(),
+            block_links: false,
+            max_length: 0,
+            max_caps_percent: 0,
+            caps_min_letters: 8,
+            max_repeats: 0,
+            repeat_window_secs: 30,
+            mute_after_strikes: 3,
+            mute_secs: 5 * 60,
+            kick_after_strikes: 5,
+            strike_window_secs: 10 * 60,
+        }
+    }
+}
+
+impl Default for NamesConfig {
+    fn default() -> Self {
+        Self {
+            min_length: NonZeroUsize::new(1).unwrap(),
+            allowed_characters: vec![
+                CharacterClass::Letters,
+                CharacterClass::Digits,
+                CharacterClass::Spaces,
+                CharacterClass::Punctuation,
+            ],
+            reserved: vec!["admin*".to_string(), "moderator*".to_string()],
+            fold_confusables: true,
+        }
+    }
+}
+
+impl Default for RoomNamesConfig {
+    fn default() -> Self {
+        Self {
+            min_length: NonZeroUsize::new(1).unwrap(),
+            max_length: 0,
+            allowed_characters: NamesConfig::default().allowed_characters,
+            banned_words: Vec::new(),
+            reserved_prefixes: vec!["Official".to_string()],
+        }
+    }
+}
+
+impl HistoryConfig {
+    pub fn max_age(&self) -> Duration {
+        Duration::from_secs(self.max_age_secs.get())
+    }
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> Result<Config> {
+        let mut config = Config::load(self.path.as_deref())?;
+        if let Some(ip) = self.ip {
+            config.server.ip = ip;
+        }
+        if let Some(port) = self.port {
+            config.server.port = port;
+        }
+
+        Ok(config)
+    }
+}
+
+impl LimitsConfig {
+    pub fn failed_login_block(&self) -> Duration {
+        Duration::from_secs(self.failed_login_block_secs)
+    }
+}
+
+impl ModerationConfig {
+    pub fn default_mute(&self) -> Duration {
+        Duration::from_secs(self.default_mute_secs)
+    }
+}
+
+impl NicknamesConfig {
+    pub fn grace(&self) -> Duration {
+        Duration::from_secs(self.grace_secs.get())
+    }
+}
+
+impl ServerConfig {
+    pub fn authorized_ttl(&self) -> Duration {
+        Duration::from_secs(self.authorized_ttl_secs.get())
+    }
+
+    pub fn unauthorized_ttl(&self) -> Duration {
+        Duration::from_secs(self.unauthorized_ttl_secs.get())
+    }
+
+    pub fn shutdown_grace(&self) -> Duration {
+        Duration::from_secs(self.shutdown_grace_secs)
+    }
+}
+
+impl Config {
+    /// The configuration in use, the defaults until one is installed.
+    pub fn current() -> Arc<Config> {
+        Arc::clone(&CONFIG.read())
+    }
+
+    pub fn install(config: Config) {
+        log::set_max_level(config.logging.level);
+        *CONFIG.write() = Arc::new(config);
+    }
+
+    /// Loads and installs the configuration, remembering where it came from for `reload`.
+    pub fn init(source: ConfigSource) -> Result<Arc<Config>> {
+        Self::install(source.load()?);
+        if SOURCE.set(source).is_err() {
+            bail!("The config has already been initialized");
+        }
+
+        Ok(Self::current())
+    }
+
+    /// Reads the configuration again and applies it. Everything but the settings read at startup
+    /// takes effect for connected users too, sessions are left as they are. Nothing changes if the
+    /// new configuration is invalid.
+    pub fn reload() -> Result<ReloadReport> {
+        let source = SOURCE
+            .get()
+            .ok_or_eyre("The config wasn't loaded from anywhere, there's nothing to reload")?;
+        let mut config = source.load()?;
+
+        let running = Self::current();
+        let needs_restart = config.keep_startup_settings(&running);
+        let mut applied = Vec::new();
+        changed_settings("", &to_table(&running)?, &to_table(&config)?, &mut applied);
+
+        Self::install(config);
+        Ok(ReloadReport {
+            applied,
+            needs_restart,
+        })
+    }
+
+    /// Puts back the running values of settings that only take effect on a restart, so the
+    /// installed config keeps describing the server as it runs. Returns the ones that differed.
+    fn keep_startup_settings(&mut self, running: &Config) -> Vec<&'static str> {
+        let mut differed = Vec::new();
+
+        if self.server.ip != running.server.ip {
+            self.server.ip = running.server.ip;
+            differed.push("server.ip");
+        }
+        if self.server.port != running.server.port {
+            self.server.port = running.server.port;
+            differed.push("server.port");
+        }
+        if self.limits.starting_capacity != running.limits.starting_capacity {
+            self.limits.starting_capacity = running.limits.starting_capacity;
+            differed.push("limits.starting_capacity");
+        }
+        if self.admin_api.enabled != running.admin_api.enabled {
+            self.admin_api.enabled = running.admin_api.enabled;
+            differed.push("admin_api.enabled");
+        }
+        if self.admin_api.ip != running.admin_api.ip {
+            self.admin_api.ip = running.admin_api.ip;
+            differed.push("admin_api.ip");
+        }
+        if self.admin_api.port != running.admin_api.port {
+            self.admin_api.port = running.admin_api.port;
+            differed.push("admin_api.port");
+        }
+        if self.bans.file != running.bans.file {
+            self.bans.file = running.bans.file.clone();
+            differed.push("bans.file");
+        }
+        if self.nicknames.file != running.nicknames.file {
+            self.nicknames.file = running.nicknames.file.clone();
+            differed.push("nicknames.file");
+        }
+        if self.rooms.file != running.rooms.file {
+            self.rooms.file = running.rooms.file.clone();
+            differed.push("rooms.file");
+        }
+        if self.bot.name != running.bot.name {
+            self.bot.name = running.bot.name.clone();
+            differed.push("bot.name");
+        }
+
+        differed
+    }
+
+    /// Reads the config file, if any, applies the `WORMS_` environment overrides and validates the
+    /// result.
+    pub fn load(path: Option<&Path>) -> Result<Config> {
+        let (text, source) = match path {
+            Some(path) => (
+                std::fs::read_to_string(path)
+                    .wrap_err_with(|| format!("Unable to read config file {}", path.display()))?,
+                format!(" in {}", path.display()),
+            ),
+            None => (String::new(), String::new()),
+        };
+
+        // Parsing the text first points errors at the line they're on
+        let mut config: Config =
+            toml::from_str(&text).wrap_err_with(|| format!("Invalid config{source}"))?;
+
+        let overrides: Vec<(String, String)> = std::env::vars()
+            .filter(|(key, _)| {
+                key.starts_with(ENV_PREFIX) && key != CONFIG_PATH_ENV && key != NO_CONSOLE_ENV
+            })
+            .collect();
+        if !overrides.is_empty() {
+            let mut table: Table = toml::from_str(&text)?;
+            for (key, value) in &overrides {
+                apply_override(&mut table, key, value)?;
+            }
+
+            let names: Vec<&str> = overrides.iter().map(|(key, _)| key.as_str()).collect();
+            config = Config::deserialize(Value::Table(table)).wrap_err_with(|| {
+                format!(
+                    "Invalid config{source} with the overrides from {}",
+                    names.join(", ")
+                )
+            })?;
+        }
+
+        config
+            .validate()
+            .wrap_err_with(|| format!("Invalid config{source}"))?;
+        Ok(config)
+    }
+
+    /// Checks what the types alone can't.
+    pub fn validate(&self) -> Result<()> {
+        check_template("messages.motd", &self.messages.motd, &VARIABLES)?;
+        check_template(
+            "messages.room_greeting",
+            &self.messages.room_greeting,
+            &ROOM_VARIABLES,
+        )?;
+        for (room, greeting) in &self.messages.room_greetings {
+            check_template(
+                &format!("messages.room_greetings.{room}"),
+                greeting,
+                &ROOM_VARIABLES,
+            )?;
+        }
+        check_chat_message("messages.shutdown_notice", &self.messages.shutdown_notice)?;
+
+        if self.limits.ipv4_prefix > 32 {
+            bail!("limits.ipv4_prefix: must be at most 32");
+        }
+        if self.limits.ipv6_prefix > 128 {
+            bail!("limits.ipv6_prefix: must be at most 128");
+        }
+
+        let bot_name = &self.bot.name;
+        if bot_name.is_empty() || bot_name.contains(' ') {
+            bail!("bot.name: must be a single word");
+        }
+        match encode_text(bot_name) {
+            Some(encoded) if encoded.len() <= MAX_NAME_LENGTH => {}
+            Some(_) => bail!("bot.name: can be at most {MAX_NAME_LENGTH} characters long"),
+            None => bail!("bot.name: can only contain characters from Windows-1252"),
+        }
+
+        ChatFilter::new(&self.chat_filter)?;
+        if self.chat_filter.max_caps_percent > 100 {
+            bail!("chat_filter.max_caps_percent: must be at most 100");
+        }
+
+        if self.admin_api.enabled && self.admin_api.token.is_empty() {
+            bail!("admin_api.token: must be set when the admin API is enabled");
+        }
+
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+
+/// Characters of a message the server sends as chat. The bot's messages add the "GRP:[ name ]  "
+/// prefix and everything gets a NUL terminator.
+pub(crate) const MAX_CHAT_LENGTH: usize =
+    MAX_DATA_LENGTH - "GRP:[  ]  ".len() - MAX_NAME_LENGTH - 1;
+
+/// Checks a message the server sends as chat fits into a chat packet.
+pub(crate) fn check_chat_message(setting: &str, message: &str) -> Result<()> {
+    let Some(encoded) = encode_text(message) else {
+        bail!("{setting}: can only contain characters from Windows-1252");
+    };
+    if encoded.len() > MAX_CHAT_LENGTH {
+        bail!(
+            "{setting}: is {} characters long, at most {MAX_CHAT_LENGTH} fit in a chat message",
+            encoded.len()
+        );
+    }
+
+    Ok(())
+}
+
+fn to_table(config: &Config) -> Result<Table> {
+    match Value::try_from(config)? {
+        Value::Table(table) => Ok(table),
+        _ => bail!("Config didn't serialize to a table"),
+    }
+}
+
+/// Collects the dotted names of the settings that differ between two configs.
+fn changed_settings(prefix: &str, old: &Table, new: &Table, changed: &mut Vec<String>) {
+    for (key, value) in new {
+        let name = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+
+        match (old.get(key), value) {
+            (Some(Value::Table(old)), Value::Table(new)) => {
+                changed_settings(&name, old, new, changed)
+            }
+            (Some(old), new) if old == new => {}
+            _ => changed.push(name),
+        }
+    }
+}
+
+/// Sets the setting named by an environment variable, `WORMS_SECTION__KEY`.
+fn apply_override(table: &mut Table, variable: &str, value: &str) -> Result<()> {
+    // Numbers and booleans are read as such, anything else is taken as a string
+    let typed = toml::from_str::<Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .filter(|typed| !typed.is_str());
+    let Some(typed) = typed else {
+        return set_override(table, variable, Value::String(value.to_string()));
+    };
+
+    // Unless the setting is a string, `123456` could be a token or a name
+    let mut as_string = table.clone();
+    set_override(table, variable, typed)?;
+    if Config::deserialize(Value::Table(table.clone())).is_err() {
+        set_override(&mut as_string, variable, Value::String(value.to_string()))?;
+        if Config::deserialize(Value::Table(as_string.clone())).is_ok() {
+            *table = as_string;
+        }
+    }
+    Ok(())
+}
+
+fn set_override(table: &mut Table, variable: &str, value: Value) -> Result<()> {
+    let path = variable[ENV_PREFIX.len()..].to_ascii_lowercase();
+    let keys: Vec<&str> = path.split("__").collect();
+    let Some((key, sections)) = keys.split_last().filter(|(key, _)| !key.is_empty()) else {
+        bail!("{variable}: doesn't name a setting");
+    };
+
+    let mut current = table;
+    for section in sections {
+        current = current
+            .entry(section.to_string())
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| eyre!("{variable}: '{section}' isn't a section"))?;
+    }
+    current.insert(key.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_round_trips() {
+        let config = Config::default();
+        let text = config.to_toml().unwrap();
+
+        assert_eq!(toml::from_str::<Config>(&text).unwrap(), config);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn overrides_set_nested_settings() {
+        let mut table = Table::new();
+        apply_override(&mut table, "WORMS_LIMITS__PACKETS_PER_SECOND", "7").unwrap();
+        apply_override(&mut table, "WORMS_SERVER__IP", "127.0.0.1").unwrap();
+        apply_override(&mut table, "WORMS_MESSAGES__MOTD", "Welcome!").unwrap();
+
+        let config = Config::deserialize(Value::Table(table)).unwrap();
+        assert_eq!(config.limits.packets_per_second.get(), 7);
+        assert_eq!(config.server.ip, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(config.messages.motd, "Welcome!");
+    }
+
+    #[test]
+    fn numeric_overrides_stay_strings_where_strings_go() {
+        let mut table = Table::new();
+        apply_override(&mut table, "WORMS_ADMIN_API__TOKEN", "123456").unwrap();
+        apply_override(&mut table, "WORMS_BOT__NAME", "42").unwrap();
+        apply_override(&mut table, "WORMS_ADMIN_API__ENABLED", "true").unwrap();
+        apply_override(&mut table, "WORMS_ADMIN_API__PORT", "18000").unwrap();
+
+        let config = Config::deserialize(Value::Table(table)).unwrap();
+        assert_eq!(config.admin_api.token, "123456");
+        assert_eq!(config.bot.name, "42");
+        assert!(config.admin_api.enabled);
+        assert_eq!(config.admin_api.port, 18000);
+    }
+
+    #[test]
+    fn reload_keeps_startup_settings() {
+        let running = Config::default();
+        let mut config = Config::default();
+        config.server.port = 17001;
+        config.limits.packets_per_second = NonZeroU32::new(9).unwrap();
+        config.messages.motd = "Hello".to_string();
+
+        assert_eq!(config.keep_startup_settings(&running), vec!["server.port"]);
+        assert_eq!(config.server.port, running.server.port);
+
+        let mut changed = Vec::new();
+        changed_settings(
+            "",
+            &to_table(&running).unwrap(),
+            &to_table(&config).unwrap(),
+            &mut changed,
+        );
+        assert_eq!(changed, vec!["limits.packets_per_second", "messages.motd"]);
+    }
+
+    #[test]
+    fn zero_quota_is_rejected() {
+        let error = toml::from_str::<Config>("[limits]\npackets_per_second = 0")
+            .unwrap_err()
+            .to_string();
+
+        assert!(error.contains("packets_per_second"), "{error}");
+        assert!(error.contains("line 2"), "{error}");
+    }
+}
@@ -2,9 +2,12 @@ pub(crate) mod game;
 pub(crate) mod room;
 pub(crate) mod user;
 
+use crate::bot;
+use crate::config::Config;
 use crate::database::game::Game;
 use crate::database::room::Room;
 use crate::database::user::User;
+use crate::names;
 use dashmap::DashMap;
 use nohash_hasher::BuildNoHashHasher;
 use parking_lot::Mutex;
@@ -25,33 +28,31 @@ pub struct Database {
 
 impl Database {
     pub(crate) const ID_START: u32 = 0x1000;
-    const STARTING_CAPACITY: usize = 1024;
 
     fn initialize() -> Self {
+        Self::with_capacity(Config::current().limits.starting_capacity)
+    }
+
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
         Self {
-            users: DashMap::with_capacity_and_hasher(
-                Self::STARTING_CAPACITY,
-                BuildNoHashHasher::default(),
-            ),
-            rooms: DashMap::with_capacity_and_hasher(
-                Self::STARTING_CAPACITY,
-                BuildNoHashHasher::default(),
-            ),
-            games: DashMap::with_capacity_and_hasher(
-                Self::STARTING_CAPACITY,
-                BuildNoHashHasher::default(),
-            ),
+            users: DashMap::with_capacity_and_hasher(capacity, BuildNoHashHasher::default()),
+            rooms: DashMap::with_capacity_and_hasher(capacity, BuildNoHashHasher::default()),
+            games: DashMap::with_capacity_and_hasher(capacity, BuildNoHashHasher::default()),
             next_id: AtomicU32::new(Database::ID_START),
             reusable_ids: Mutex::default(),
         }
     }
 
     pub fn get_next_id() -> u32 {
-        if let Some(id) = DATABASE.reusable_ids.lock().pop() {
+        DATABASE.next_id()
+    }
+
+    pub(crate) fn next_id(&self) -> u32 {
+        if let Some(id) = self.reusable_ids.lock().pop() {
             return id;
         }
 
-        DATABASE.next_id.fetch_add(1, Ordering::Relaxed)
+        self.next_id.fetch_add(1, Ordering::Relaxed)
     }
 
     pub fn recycle_id(id: u32) {
@@ -59,15 +60,42 @@ impl Database {
         if SHUTDOWN_TOKEN.is_cancelled() {
             return;
         }
+        DATABASE.recycle(id);
+    }
+
+    pub(crate) fn recycle(&self, id: u32) {
         if id >= Database::ID_START {
-            DATABASE.reusable_ids.lock().push(id);
+            self.reusable_ids.lock().push(id);
         }
     }
 
+    /// Keeps an id saved from an earlier run from being handed out.
+    pub fn reserve_id(&self, id: u32) {
+        self.next_id
+            .fetch_max(id.saturating_add(1), Ordering::Relaxed);
+        self.reusable_ids.lock().retain(|reusable| *reusable != id);
+    }
+
+    /// The id of the user with a name, ignoring case. The bot isn't one to be found.
+    pub fn find_user(name: &str) -> Option<u32> {
+        DATABASE
+            .users
+            .iter()
+            .find(|u| !bot::is_bot(u.id) && u.name.eq_ignore_ascii_case(name))
+            .map(|u| u.id)
+    }
+
+    /// Users logged in, not counting the bot.
+    pub fn player_count() -> usize {
+        DATABASE.users.len() - usize::from(DATABASE.users.contains_key(&bot::id()))
+    }
+
+    /// Whether someone's logged in with the name, or one that looks the same.
     pub fn check_user_exists(name: &str) -> bool {
+        let name = names::comparable(name);
         DATABASE
             .users
             .iter()
-            .any(|u| u.name.eq_ignore_ascii_case(name))
+            .any(|u| names::comparable(&u.name) == name)
     }
 }
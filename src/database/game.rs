@@ -37,4 +37,4 @@ impl Drop for Game {
     fn drop(&mut self) {
         Database::recycle_id(self.id);
     }
-}
\ No newline at end of file
+}
@@ -1,13 +1,44 @@
 use crate::database::Database;
+use crate::history::ChatLine;
+use crate::names;
+use crate::net::address_range::AddressRange;
 use crate::net::nation::Nation;
+use crate::net::session_access::SessionAccess;
 use crate::net::session_info::SessionInfo;
 use crate::net::session_type::SessionType;
+use std::collections::VecDeque;
+use std::net::IpAddr;
 use std::sync::Arc;
 
 pub struct Room {
     pub id: u32,
     pub name: String,
     pub session: Arc<SessionInfo>,
+    /// Kept open while empty and saved to the rooms file, set up by the operator.
+    pub permanent: bool,
+    /// Sent to whoever joins instead of the configured greeting, if not empty.
+    pub greeting: String,
+    /// Argon2 hash of the password needed to join a protected room. Until it has one only the
+    /// owner can join.
+    pub password_hash: String,
+    /// Most users in the room at once, 0 for `rooms.max_users`.
+    pub capacity: u32,
+    /// Made the room or had it passed on to them, 0 for the operator's or once nobody's left.
+    pub owner: u32,
+    pub topic: String,
+    /// Users the owner sent out, who can't come back.
+    pub kicked: Vec<Kick>,
+    /// Recent chat, oldest first.
+    pub history: VecDeque<ChatLine>,
+}
+
+/// Someone sent out of a room, kept by name and address so logging in again doesn't get them back
+/// in, and whoever gets their id next isn't kept out.
+pub struct Kick {
+    /// As `names::comparable` has it
+    name: String,
+    /// None for an address exempt from the limits, a LAN for example
+    range: Option<AddressRange>,
 }
 
 impl Room {
@@ -16,12 +47,59 @@ impl Room {
             id,
             name: name.to_string(),
             session: SessionInfo::new(nation, SessionType::Room),
+            permanent: false,
+            greeting: String::new(),
+            password_hash: String::new(),
+            capacity: 0,
+            owner: 0,
+            topic: String::new(),
+            kicked: Vec::new(),
+            history: VecDeque::new(),
         }
     }
+
+    /// Changes the flag and whether a password is needed to join, given as its hash.
+    pub fn set_access(&mut self, nation: Nation, protected: bool, password_hash: &str) {
+        let access = if protected {
+            SessionAccess::Protected
+        } else {
+            SessionAccess::Public
+        };
+        self.session = SessionInfo::new_with_access(nation, SessionType::Room, access);
+        self.password_hash = password_hash.to_string();
+    }
+
+    pub fn kick(&mut self, name: &str, range: Option<AddressRange>) {
+        self.kicked.push(Kick {
+            name: names::comparable(name),
+            range,
+        });
+    }
+
+    pub fn is_kicked(&self, name: &str, address: IpAddr) -> bool {
+        let name = names::comparable(name);
+        self.kicked.iter().any(|kick| {
+            kick.name == name || kick.range.is_some_and(|range| range.contains(address))
+        })
+    }
+
+    pub fn is_protected(&self) -> bool {
+        self.session.access == SessionAccess::Protected
+    }
+
+    /// Whether another user fits, with `max_users` for a room without a capacity of its own.
+    pub fn is_full(&self, users: usize, max_users: u32) -> bool {
+        let capacity = if self.capacity != 0 {
+            self.capacity
+        } else {
+            max_users
+        };
+        capacity != 0 && users >= capacity as usize
+    }
 }
 
 impl Drop for Room {
     fn drop(&mut self) {
         Database::recycle_id(self.id);
     }
-}
\ No newline at end of file
+}
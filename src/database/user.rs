@@ -2,26 +2,55 @@ use crate::database::Database;
 use crate::net::nation::Nation;
 use crate::net::session_info::SessionInfo;
 use crate::net::session_type::SessionType;
+use crate::net::worms_packet::encode_text;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::sync::mpsc::WeakSender;
 use tokio_util::bytes::Bytes;
+use tokio_util::sync::CancellationToken;
 
 pub struct User {
     pub sender: WeakSender<Arc<Bytes>>,
     pub id: u32,
     pub name: String,
+    /// The name as Windows-1252, to match chat prefixes without decoding every message.
+    pub encoded_name: Bytes,
     pub session: Arc<SessionInfo>,
     pub room_id: u32,
+    pub address: SocketAddr,
+    pub connected_at: SystemTime,
+    /// Proved the name is theirs, only ever set for registered names.
+    pub identified: bool,
+    /// Rooms with a password they've given it for.
+    pub unlocked_rooms: Vec<u32>,
+    /// Cancelled to close the user's connection once they've been removed.
+    pub kick_token: CancellationToken,
 }
 
 impl User {
-    pub fn new(sender: WeakSender<Arc<Bytes>>, id: u32, name: &str, nation: Nation) -> Self {
+    pub fn new(
+        sender: WeakSender<Arc<Bytes>>,
+        id: u32,
+        name: &str,
+        nation: Nation,
+        address: SocketAddr,
+    ) -> Self {
         Self {
             sender,
             id,
             name: name.to_string(),
+            encoded_name: encode_text(name).map_or_else(
+                || Bytes::copy_from_slice(name.as_bytes()),
+                |e| Bytes::from(e.into_owned()),
+            ),
             session: SessionInfo::new(nation, SessionType::User),
             room_id: 0,
+            address,
+            connected_at: SystemTime::now(),
+            identified: false,
+            unlocked_rooms: Vec::new(),
+            kick_token: CancellationToken::new(),
         }
     }
 
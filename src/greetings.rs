@@ -0,0 +1,149 @@
+use crate::bot;
+use crate::config::{check_chat_message, Config, MAX_CHAT_LENGTH};
+use crate::database::{Database, DATABASE};
+use crate::moderation::format_duration;
+use crate::server::Server;
+use eyre::{bail, Result};
+use std::sync::Arc;
+use tokio::sync::mpsc::Sender;
+use tokio_util::bytes::Bytes;
+
+// The message of the day and the greetings for rooms, templates with `{name}` style variables
+// filled in for whoever they're sent to.
+
+/// Variables every template can use.
+pub const VARIABLES: [&str; 3] = ["name", "online", "uptime"];
+/// Variables greetings for rooms can use, those and `{room}`.
+pub const ROOM_VARIABLES: [&str; 4] = ["name", "online", "uptime", "room"];
+
+/// Checks a template only uses the variables given, and that its lines fit in a chat message.
+pub fn check_template(setting: &str, template: &str, variables: &[&str]) -> Result<()> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(length) = rest[start..].find('}') else {
+            bail!("{setting}: has a '{{' without a '}}'");
+        };
+        let variable = &rest[start + 1..start + length];
+        if !variables.contains(&variable) {
+            bail!(
+                "{setting}: has an unknown variable {{{variable}}}, it can use {}",
+                variables
+                    .iter()
+                    .map(|v| format!("{{{v}}}"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        rest = &rest[start + length..];
+    }
+
+    for line in template.lines() {
+        check_chat_message(setting, line)?;
+    }
+    Ok(())
+}
+
+/// Fills in a template, one chat message per line. Lines the variables made too long are cut
+/// short, empty ones are left out.
+fn render(template: &str, variables: &[(&str, &str)]) -> Vec<String> {
+    let mut text = template.to_string();
+    for (name, value) in variables {
+        text = text.replace(&format!("{{{name}}}"), value);
+    }
+
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.chars().take(MAX_CHAT_LENGTH).collect())
+        .collect()
+}
+
+fn render_for(template: &str, user_name: &str, room_name: &str) -> Vec<String> {
+    if template.is_empty() {
+        return Vec::new();
+    }
+
+    let online = Database::player_count().to_string();
+    let uptime = format_duration(Server::uptime());
+    render(
+        template,
+        &[
+            ("name", user_name),
+            ("online", &online),
+            ("uptime", &uptime),
+            ("room", room_name),
+        ],
+    )
+}
+
+/// The message of the day for a user.
+pub fn motd(user_name: &str) -> Vec<String> {
+    render_for(&Config::current().messages.motd, user_name, "")
+}
+
+/// The greeting for a room: a permanent room's own, the one configured for its name or else the
+/// one for every room.
+pub fn room_greeting(user_name: &str, room_name: &str, own_greeting: &str) -> Vec<String> {
+    if !own_greeting.is_empty() {
+        return render_for(own_greeting, user_name, room_name);
+    }
+
+    let config = Config::current();
+    let template = config
+        .messages
+        .room_greetings
+        .iter()
+        .find(|(room, _)| room.eq_ignore_ascii_case(room_name))
+        .map_or(&config.messages.room_greeting, |(_, greeting)| greeting);
+    render_for(template, user_name, room_name)
+}
+
+/// Sends the message of the day to a user who just logged in.
+pub async fn send_motd(tx: &Sender<Arc<Bytes>>, user_id: u32) -> Result<()> {
+    let Some(name) = DATABASE.users.get(&user_id).map(|u| u.name.clone()) else {
+        return Ok(());
+    };
+    for line in motd(&name) {
+        tx.send(bot::private_packet(user_id, &line)?).await?;
+    }
+    Ok(())
+}
+
+/// Greets a user who just joined a room.
+pub async fn send_room_greeting(tx: &Sender<Arc<Bytes>>, user_id: u32, room_id: u32) -> Result<()> {
+    let Some(name) = DATABASE.users.get(&user_id).map(|u| u.name.clone()) else {
+        return Ok(());
+    };
+    let Some((room_name, greeting)) = DATABASE
+        .rooms
+        .get(&room_id)
+        .map(|r| (r.name.clone(), r.greeting.clone()))
+    else {
+        return Ok(());
+    };
+    for line in room_greeting(&name, &room_name, &greeting) {
+        tx.send(bot::private_packet(user_id, &line)?).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_fill_in_variables_per_line() {
+        let lines = render(
+            "Hi {name}, welcome to {room}!\n\n{online} online",
+            &[("name", "Alice"), ("room", "Lobby"), ("online", "3")],
+        );
+        assert_eq!(lines, vec!["Hi Alice, welcome to Lobby!", "3 online"]);
+    }
+
+    #[test]
+    fn templates_only_use_known_variables() {
+        assert!(check_template("motd", "Hi {name}, up {uptime}", &VARIABLES).is_ok());
+        assert!(check_template("motd", "In {room}", &VARIABLES).is_err());
+        assert!(check_template("greeting", "In {room}", &ROOM_VARIABLES).is_ok());
+        assert!(check_template("motd", "Broken {name", &VARIABLES).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use crate::bot;
+use crate::config::Config;
+use crate::database::DATABASE;
+use crate::net::packet_code::PacketCode;
+use crate::net::worms_packet::{WormsPacket, MAX_DATA_LENGTH};
+use eyre::Result;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::mpsc::Sender;
+use tokio_util::bytes::{BufMut, Bytes, BytesMut};
+
+// Recent chat in each room, shown to whoever joins so they don't walk into a silent room. Only
+// messages to the whole room are kept, private ones never are.
+
+/// A message sent to a room.
+pub struct ChatLine {
+    pub sent: SystemTime,
+    pub sender_id: u32,
+    /// Windows-1252 encoded, as the message had them
+    pub sender_name: Bytes,
+    pub body: Bytes,
+}
+
+impl ChatLine {
+    /// The message as it was sent, with the time it was sent at after the name.
+    fn replay_data(&self) -> Bytes {
+        let time = humantime::format_rfc3339_seconds(self.sent).to_string();
+        let mut data = BytesMut::new();
+        data.put_slice(b"GRP:[ ");
+        data.put_slice(&self.sender_name);
+        data.put_slice(b" ]  [");
+        data.put_slice(&time.as_bytes()[11..16]);
+        data.put_slice(b"] ");
+        data.put_slice(&self.body);
+        // Leave room for the trailing NUL
+        data.truncate(MAX_DATA_LENGTH - 1);
+        data.freeze()
+    }
+}
+
+/// Keeps a message sent to a room, dropping the oldest once the room has `history.depth`.
+pub fn record(room_id: u32, sender_id: u32, sender_name: Bytes, body: Bytes) {
+    let depth = Config::current().history.depth;
+    if depth == 0 {
+        return;
+    }
+
+    if let Some(mut room) = DATABASE.rooms.get_mut(&room_id) {
+        room.history.push_back(ChatLine {
+            sent: SystemTime::now(),
+            sender_id,
+            sender_name,
+            body,
+        });
+        while room.history.len() > depth {
+            room.history.pop_front();
+        }
+    }
+}
+
+/// Sends a user who just joined a room the messages sent to it lately.
+pub async fn replay(tx: &Sender<Arc<Bytes>>, user_id: u32, room_id: u32) -> Result<()> {
+    let config = Config::current();
+    let now = SystemTime::now();
+    let lines: Vec<(u32, Bytes)> = match DATABASE.rooms.get(&room_id) {
+        Some(room) => {
+            let skip = room.history.len().saturating_sub(config.history.depth);
+            room.history
+                .iter()
+                .skip(skip)
+                .filter(|line| {
+                    now.duration_since(line.sent)
+                        .is_ok_and(|age| age <= config.history.max_age())
+                })
+                .map(|line| (line.sender_id, line.replay_data()))
+                .collect()
+        }
+        None => return Ok(()),
+    };
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    tx.send(bot::private_packet(
+        user_id,
+        "Chat from before you joined, times in UTC:",
+    )?)
+    .await?;
+    for (sender_id, data) in lines {
+        let packet = WormsPacket::create(PacketCode::ChatRoom)
+            .with_value_0(sender_id)
+            .with_value_3(room_id)
+            .with_raw_data(data)
+            .build()?;
+        tx.send(packet).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn replayed_lines_carry_the_time_sent() {
+        let line = ChatLine {
+            sent: UNIX_EPOCH + Duration::from_secs(14 * 3600 + 5 * 60 + 59),
+            sender_id: 0x1000,
+            sender_name: Bytes::from_static(b"Alice"),
+            body: Bytes::from_static(b"hello"),
+        };
+        assert_eq!(&line.replay_data()[..], b"GRP:[ Alice ]  [14:05] hello");
+    }
+
+    #[test]
+    fn long_lines_still_fit_a_packet() {
+        let line = ChatLine {
+            sent: SystemTime::now(),
+            sender_id: 0x1000,
+            sender_name: Bytes::from_static(b"Alice"),
+            body: Bytes::from(vec![b'x'; MAX_DATA_LENGTH]),
+        };
+        assert_eq!(line.replay_data().len(), MAX_DATA_LENGTH - 1);
+    }
+}
@@ -0,0 +1,276 @@
+use crate::config::LimitsConfig;
+use crate::net::address_range::AddressRange;
+use dashmap::DashMap;
+use governor::{DefaultKeyedRateLimiter, Quota, RateLimiter};
+use parking_lot::RwLock;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+pub static IP_LIMITS: LazyLock<IpLimits> = LazyLock::new(IpLimits::default);
+
+/// Why a connection was turned away.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Refusal {
+    /// Connecting faster than `limits.accepts_per_second`
+    TooFast,
+    /// Already has `limits.max_sessions_per_ip` connections open
+    TooManySessions,
+    /// Failed to log in too often and hasn't waited out the block
+    Blocked,
+    /// Logging in more often than `limits.logins_per_minute`
+    TooManyLogins,
+}
+
+/// Limits per address, or per range of addresses when the config groups them by prefix.
+#[derive(Default)]
+pub struct IpLimits {
+    sessions: DashMap<AddressRange, u32>,
+    failures: DashMap<AddressRange, Failures>,
+    accepts: RwLock<Option<(NonZeroU32, DefaultKeyedRateLimiter<AddressRange>)>>,
+    logins: RwLock<Option<(NonZeroU32, DefaultKeyedRateLimiter<AddressRange>)>>,
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    blocked_until: Option<Instant>,
+}
+
+/// Counts as one of an address' open sessions until dropped.
+pub struct Session<'a> {
+    limits: &'a IpLimits,
+    key: Option<AddressRange>,
+}
+
+impl IpLimits {
+    /// What an address is counted as, None when it's exempt.
+    pub(crate) fn key(limits: &LimitsConfig, address: IpAddr) -> Option<AddressRange> {
+        if limits.exempt.iter().any(|range| range.contains(address)) {
+            return None;
+        }
+
+        let address = address.to_canonical();
+        let prefix = if address.is_ipv4() {
+            limits.ipv4_prefix
+        } else {
+            limits.ipv6_prefix
+        };
+        Some(AddressRange::containing(address, prefix))
+    }
+
+    /// Checks a new connection against the limits, returning the session it counts as.
+    pub fn accept(&self, limits: &LimitsConfig, address: IpAddr) -> Result<Session<'_>, Refusal> {
+        let Some(key) = Self::key(limits, address) else {
+            return Ok(Session {
+                limits: self,
+                key: None,
+            });
+        };
+
+        if self.is_blocked(&key) {
+            return Err(Refusal::Blocked);
+        }
+        if !check(
+            &self.accepts,
+            limits.accepts_per_second,
+            Quota::per_second,
+            &key,
+        ) {
+            return Err(Refusal::TooFast);
+        }
+
+        let mut sessions = self.sessions.entry(key).or_insert(0);
+        if limits.max_sessions_per_ip != 0 && *sessions >= limits.max_sessions_per_ip {
+            return Err(Refusal::TooManySessions);
+        }
+        *sessions += 1;
+
+        Ok(Session {
+            limits: self,
+            key: Some(key),
+        })
+    }
+
+    /// Checks a login attempt against the limits, counted when its packet arrives.
+    pub fn login_attempt(&self, limits: &LimitsConfig, address: IpAddr) -> Result<(), Refusal> {
+        let Some(key) = Self::key(limits, address) else {
+            return Ok(());
+        };
+
+        if self.is_blocked(&key) {
+            return Err(Refusal::Blocked);
+        }
+        if !check(
+            &self.logins,
+            limits.logins_per_minute,
+            Quota::per_minute,
+            &key,
+        ) {
+            return Err(Refusal::TooManyLogins);
+        }
+
+        Ok(())
+    }
+
+    /// Counts a failed login, blocking the address once it's failed too often in a row.
+    pub fn login_failed(&self, limits: &LimitsConfig, address: IpAddr) {
+        let Some(key) = Self::key(limits, address) else {
+            return;
+        };
+
+        let mut failures = self.failures.entry(key).or_insert(Failures {
+            count: 0,
+            last: Instant::now(),
+            blocked_until: None,
+        });
+        failures.count += 1;
+        failures.last = Instant::now();
+
+        if limits.max_failed_logins != 0 && failures.count >= limits.max_failed_logins {
+            log::warn!(
+                "Blocking {} for {} seconds after {} failed logins",
+                key,
+                limits.failed_login_block_secs,
+                failures.count
+            );
+            failures.count = 0;
+            failures.blocked_until = Some(Instant::now() + limits.failed_login_block());
+        }
+    }
+
+    /// Forgets the failed logins before a successful one.
+    pub fn login_succeeded(&self, limits: &LimitsConfig, address: IpAddr) {
+        if let Some(key) = Self::key(limits, address) {
+            self.failures.remove(&key);
+        }
+    }
+
+    fn is_blocked(&self, key: &AddressRange) -> bool {
+        self.failures
+            .get(key)
+            .and_then(|failures| failures.blocked_until)
+            .is_some_and(|until| until > Instant::now())
+    }
+
+    /// Drops what's no longer needed to enforce the limits, call it every now and then.
+    pub fn prune(&self) {
+        // Failures in a row aren't in a row anymore after a while
+        const FORGET_AFTER: Duration = Duration::from_secs(60 * 60);
+        let now = Instant::now();
+        self.failures
+            .retain(|_, failures| match failures.blocked_until {
+                Some(until) => until > now,
+                None => now.duration_since(failures.last) < FORGET_AFTER,
+            });
+
+        for limiter in [&self.accepts, &self.logins] {
+            if let Some((_, limiter)) = &*limiter.read() {
+                limiter.retain_recent();
+                limiter.shrink_to_fit();
+            }
+        }
+    }
+}
+
+/// Checks a keyed limiter, building it again when a reload changed its quota.
+fn check(
+    limiter: &RwLock<Option<(NonZeroU32, DefaultKeyedRateLimiter<AddressRange>)>>,
+    rate: NonZeroU32,
+    quota: fn(NonZeroU32) -> Quota,
+    key: &AddressRange,
+) -> bool {
+    if let Some((current, limiter)) = &*limiter.read() {
+        if *current == rate {
+            return limiter.check_key(key).is_ok();
+        }
+    }
+
+    let mut limiter = limiter.write();
+    if limiter.as_ref().is_none_or(|(current, _)| *current != rate) {
+        *limiter = Some((rate, RateLimiter::dashmap(quota(rate))));
+    }
+    limiter
+        .as_ref()
+        .is_some_and(|(_, limiter)| limiter.check_key(key).is_ok())
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.limits.sessions.remove_if_mut(&key, |_, sessions| {
+                *sessions -= 1;
+                *sessions == 0
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> LimitsConfig {
+        LimitsConfig {
+            accepts_per_second: NonZeroU32::new(100).unwrap(),
+            max_sessions_per_ip: 2,
+            max_failed_logins: 2,
+            ipv4_prefix: 24,
+            exempt: vec!["192.168.0.0/16".parse().unwrap()],
+            ..LimitsConfig::default()
+        }
+    }
+
+    #[test]
+    fn sessions_are_counted_per_prefix() {
+        let ip_limits = IpLimits::default();
+        let limits = limits();
+
+        let first = ip_limits.accept(&limits, "198.51.100.1".parse().unwrap());
+        let second = ip_limits.accept(&limits, "198.51.100.2".parse().unwrap());
+        assert!(first.is_ok() && second.is_ok());
+        assert_eq!(
+            ip_limits
+                .accept(&limits, "198.51.100.3".parse().unwrap())
+                .err(),
+            Some(Refusal::TooManySessions)
+        );
+        assert!(ip_limits
+            .accept(&limits, "198.51.101.1".parse().unwrap())
+            .is_ok());
+
+        drop(first);
+        assert!(ip_limits
+            .accept(&limits, "198.51.100.3".parse().unwrap())
+            .is_ok());
+
+        // Exempt addresses aren't counted at all
+        let lan: Vec<_> = (0..5)
+            .map(|_| ip_limits.accept(&limits, "192.168.1.10".parse().unwrap()))
+            .collect();
+        assert!(lan.iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn failed_logins_block_the_address() {
+        let ip_limits = IpLimits::default();
+        let limits = limits();
+        let address = "203.0.113.9".parse().unwrap();
+
+        ip_limits.login_failed(&limits, address);
+        ip_limits.login_succeeded(&limits, address);
+        ip_limits.login_failed(&limits, address);
+        assert!(ip_limits.login_attempt(&limits, address).is_ok());
+
+        ip_limits.login_failed(&limits, address);
+        assert_eq!(
+            ip_limits.login_attempt(&limits, address),
+            Err(Refusal::Blocked)
+        );
+        assert_eq!(
+            ip_limits.accept(&limits, address).err(),
+            Some(Refusal::Blocked)
+        );
+    }
+}
@@ -0,0 +1,18 @@
+pub mod admin;
+pub mod attempts;
+pub mod audit;
+pub mod bans;
+pub mod bot;
+pub mod chat_filter;
+pub mod config;
+pub mod database;
+pub mod greetings;
+pub mod history;
+pub mod ip_limits;
+pub mod metrics;
+pub mod moderation;
+pub mod names;
+pub mod net;
+pub mod nicknames;
+pub mod rooms;
+pub mod server;
@@ -1,17 +1,17 @@
-#![allow(clippy::all)]
-
 use crate::args::Args;
-use crate::database::SHUTDOWN_TOKEN;
+use worms_server::bans::BANS;
+use worms_server::config::{Config, ConfigSource};
+use worms_server::database::SHUTDOWN_TOKEN;
+use worms_server::net::capture::Recorder;
+use worms_server::nicknames::NICKNAMES;
+use worms_server::rooms::PERMANENT_ROOMS;
 
 use clap::Parser;
-use log::{error, info};
-use server::Server;
+use log::{error, info, warn, LevelFilter};
 use std::net::SocketAddr;
+use worms_server::server::Server;
 
 mod args;
-pub(crate) mod database;
-pub(crate) mod net;
-pub(crate) mod server;
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> eyre::Result<()> {
@@ -20,32 +20,156 @@ async fn main() -> eyre::Result<()> {
     handle_ctrl_c_signal();
 
     let args = Args::try_parse()?;
-    let server_address = SocketAddr::new(args.ip, args.port);
+    if args.print_default_config {
+        print!("{}", Config::default().to_toml()?);
+        return Ok(());
+    }
+
+    let config = Config::init(ConfigSource {
+        path: args.config.clone(),
+        ip: args.ip,
+        port: args.port,
+    })?;
+    let server_address = SocketAddr::new(config.server.ip, config.server.port);
+    if !config.bans.file.as_os_str().is_empty() {
+        let count = BANS.load(&config.bans.file)?;
+        info!("{} bans in effect", count);
+    }
+    if !config.nicknames.file.as_os_str().is_empty() {
+        let count = NICKNAMES.load(&config.nicknames.file)?;
+        info!("{} registered names", count);
+    }
+    if !config.rooms.file.as_os_str().is_empty() {
+        let count = PERMANENT_ROOMS.load(&config.rooms.file).await?;
+        info!("{} permanent rooms", count);
+    }
+    handle_reload_signal();
+
+    if config.admin_api.enabled {
+        let address = SocketAddr::new(config.admin_api.ip, config.admin_api.port);
+        worms_server::admin::api::start(address).await?;
+    }
+
+    if let Some(path) = &args.capture {
+        Recorder::start(path)?;
+        info!("Recording traffic to {}", path.display());
+    }
+
+    if !args.no_console {
+        worms_server::admin::console::start()?;
+        println!("Type help for the console commands");
+    }
+
     if let Err(e) = Server::start_server(server_address).await {
         log::error!("Server encountered an error: {}", e);
     }
+    worms_server::audit::finish();
 
     Ok(())
 }
 
 fn initialize_environment() -> eyre::Result<()> {
     dotenvy::dotenv()?;
-    env_logger::init();
+    // Let everything of ours through, the config sets the level and can change it while running.
+    // Dependencies only get to warn, their debug logs are noise here.
+    let mut logger = env_logger::Builder::new();
+    logger
+        .filter_level(LevelFilter::Warn)
+        .filter_module("worms_server", LevelFilter::Trace);
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        logger.parse_filters(&scope_log_filters(&filters));
+    }
+    logger.init();
     color_eyre::install()?;
 
     Ok(())
 }
 
+/// Narrows a bare level in `RUST_LOG`, like `info`, to the server so dependencies stay at warn.
+/// Levels given for a module are left as they are.
+fn scope_log_filters(filters: &str) -> String {
+    filters
+        .split(',')
+        .map(|directive| match directive.trim().parse::<LevelFilter>() {
+            Ok(level) => format!("worms_server={level}"),
+            Err(_) => directive.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
 fn handle_ctrl_c_signal() {
-    let cancellation_token = SHUTDOWN_TOKEN.clone();
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = tokio::signal::ctrl_c().await {
+                error!("Ctrl-C signal handler encountered an error: {}", e);
+                SHUTDOWN_TOKEN.cancel();
+                return;
+            }
+            Server::interrupt();
+        }
+    });
+}
+
+/// Reloads the config on SIGHUP.
+#[cfg(unix)]
+fn handle_reload_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
 
     tokio::spawn(async move {
-        if let Err(e) = tokio::signal::ctrl_c().await {
-            error!("Ctrl-C signal handler encountered an error: {}", e);
-            cancellation_token.cancel();
-            return;
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                error!(
+                    "Unable to listen for SIGHUP, config reloads are disabled: {}",
+                    e
+                );
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            reload_config().await;
         }
-        info!("Server shutting down");
-        cancellation_token.cancel();
     });
 }
+
+#[cfg(not(unix))]
+fn handle_reload_signal() {}
+
+async fn reload_config() {
+    match Config::reload() {
+        Ok(report) => {
+            if report.applied.is_empty() {
+                info!("Config reloaded, nothing changed");
+            } else {
+                info!("Config reloaded, applied: {}", report.applied.join(", "));
+            }
+            if !report.needs_restart.is_empty() {
+                warn!(
+                    "Restart the server to apply: {}",
+                    report.needs_restart.join(", ")
+                );
+            }
+        }
+        Err(e) => error!("Config reload failed, keeping the current config: {:#}", e),
+    }
+
+    match BANS.reload() {
+        Ok(Some(count)) => info!("Bans reloaded, {} in effect", count),
+        Ok(None) => {}
+        Err(e) => error!("Bans reload failed, keeping the current bans: {:#}", e),
+    }
+
+    match NICKNAMES.reload() {
+        Ok(Some(count)) => info!("Nicknames reloaded, {} registered", count),
+        Ok(None) => {}
+        Err(e) => error!("Nicknames reload failed, keeping the current ones: {:#}", e),
+    }
+
+    match PERMANENT_ROOMS.reload().await {
+        Ok(Some(count)) => info!("Rooms reloaded, {} permanent", count),
+        Ok(None) => {}
+        Err(e) => error!("Rooms reload failed: {:#}", e),
+    }
+}
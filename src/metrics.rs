@@ -0,0 +1,205 @@
+use crate::database::{Database, DATABASE};
+use crate::ip_limits::Refusal;
+use crate::net::packet_code::PacketCode;
+use eyre::Result;
+use prometheus::{
+    exponential_buckets, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, Opts, Registry, TextEncoder,
+};
+use std::sync::LazyLock;
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Why a connection didn't make it past logging in.
+#[derive(Debug, Copy, Clone)]
+pub enum LoginFailure {
+    DuplicateName,
+    BadName,
+    BadFirstPacket,
+    Timeout,
+    ShuttingDown,
+    Banned,
+    Unidentified,
+}
+
+impl LoginFailure {
+    fn label(self) -> &'static str {
+        match self {
+            LoginFailure::DuplicateName => "duplicate_name",
+            LoginFailure::BadName => "bad_name",
+            LoginFailure::BadFirstPacket => "bad_first_packet",
+            LoginFailure::Timeout => "timeout",
+            LoginFailure::ShuttingDown => "shutting_down",
+            LoginFailure::Banned => "banned",
+            LoginFailure::Unidentified => "unidentified",
+        }
+    }
+}
+
+/// Where a rate limit was hit.
+#[derive(Debug, Copy, Clone)]
+pub enum RateLimit {
+    /// Too many connections from one address
+    Accept,
+    /// Too many packets from one client
+    Connection,
+    /// Too many connections open at once from one address
+    Sessions,
+    /// Too many logins from one address
+    Login,
+    /// An address blocked after failing to log in too often
+    Blocked,
+}
+
+impl From<Refusal> for RateLimit {
+    fn from(refusal: Refusal) -> Self {
+        match refusal {
+            Refusal::TooFast => RateLimit::Accept,
+            Refusal::TooManySessions => RateLimit::Sessions,
+            Refusal::TooManyLogins => RateLimit::Login,
+            Refusal::Blocked => RateLimit::Blocked,
+        }
+    }
+}
+
+pub struct Metrics {
+    registry: Registry,
+    users: IntGauge,
+    rooms: IntGauge,
+    games: IntGauge,
+    logins: IntCounter,
+    failed_logins: IntCounterVec,
+    packets_received: IntCounterVec,
+    decode_errors: IntCounter,
+    rate_limited: IntCounterVec,
+    outbound_queue_depth: Histogram,
+    dispatch_seconds: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let metrics = Self {
+            registry: Registry::new(),
+            users: IntGauge::new("worms_users", "Users logged in").unwrap(),
+            rooms: IntGauge::new("worms_rooms", "Open rooms").unwrap(),
+            games: IntGauge::new("worms_games", "Games being hosted").unwrap(),
+            logins: IntCounter::new("worms_logins_total", "Successful logins").unwrap(),
+            failed_logins: IntCounterVec::new(
+                Opts::new(
+                    "worms_failed_logins_total",
+                    "Connections that failed to log in",
+                ),
+                &["reason"],
+            )
+            .unwrap(),
+            packets_received: IntCounterVec::new(
+                Opts::new("worms_packets_received_total", "Packets received by code"),
+                &["code"],
+            )
+            .unwrap(),
+            decode_errors: IntCounter::new(
+                "worms_decode_errors_total",
+                "Connections closed because a frame couldn't be read",
+            )
+            .unwrap(),
+            rate_limited: IntCounterVec::new(
+                Opts::new(
+                    "worms_rate_limited_total",
+                    "Connections or packets over a rate limit",
+                ),
+                &["stage"],
+            )
+            .unwrap(),
+            outbound_queue_depth: Histogram::with_opts(
+                HistogramOpts::new(
+                    "worms_outbound_queue_depth",
+                    "Packets waiting in a connection's queue when it sends",
+                )
+                .buckets(vec![1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0]),
+            )
+            .unwrap(),
+            dispatch_seconds: HistogramVec::new(
+                HistogramOpts::new(
+                    "worms_dispatch_seconds",
+                    "Time spent handling a packet, by code",
+                )
+                .buckets(exponential_buckets(0.000_05, 4.0, 8).unwrap()),
+                &["code"],
+            )
+            .unwrap(),
+        };
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 10] = [
+            Box::new(metrics.users.clone()),
+            Box::new(metrics.rooms.clone()),
+            Box::new(metrics.games.clone()),
+            Box::new(metrics.logins.clone()),
+            Box::new(metrics.failed_logins.clone()),
+            Box::new(metrics.packets_received.clone()),
+            Box::new(metrics.decode_errors.clone()),
+            Box::new(metrics.rate_limited.clone()),
+            Box::new(metrics.outbound_queue_depth.clone()),
+            Box::new(metrics.dispatch_seconds.clone()),
+        ];
+        for collector in collectors {
+            metrics
+                .registry
+                .register(collector)
+                .expect("Metric names should be unique");
+        }
+
+        metrics
+    }
+
+    pub fn login(&self) {
+        self.logins.inc();
+    }
+
+    pub fn login_failed(&self, reason: LoginFailure) {
+        self.failed_logins
+            .with_label_values(&[reason.label()])
+            .inc();
+    }
+
+    pub fn packet_received(&self, code: PacketCode) {
+        self.packets_received
+            .with_label_values(&[code.name()])
+            .inc();
+    }
+
+    pub fn decode_error(&self) {
+        self.decode_errors.inc();
+    }
+
+    pub fn rate_limited(&self, stage: RateLimit) {
+        let stage = match stage {
+            RateLimit::Accept => "accept",
+            RateLimit::Connection => "connection",
+            RateLimit::Sessions => "sessions",
+            RateLimit::Login => "login",
+            RateLimit::Blocked => "blocked",
+        };
+        self.rate_limited.with_label_values(&[stage]).inc();
+    }
+
+    pub fn outbound_queue_depth(&self, depth: usize) {
+        self.outbound_queue_depth.observe(depth as f64);
+    }
+
+    /// Times a packet handler until the returned timer is dropped.
+    pub fn dispatch_timer(&self, code: PacketCode) -> prometheus::HistogramTimer {
+        self.dispatch_seconds
+            .with_label_values(&[code.name()])
+            .start_timer()
+    }
+
+    /// Everything in the Prometheus text format.
+    pub fn render(&self) -> Result<String> {
+        // The counts are read when scraped rather than kept up to date on every change
+        self.users.set(Database::player_count() as i64);
+        self.rooms.set(DATABASE.rooms.len() as i64);
+        self.games.set(DATABASE.games.len() as i64);
+
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+}
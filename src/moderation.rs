@@ -0,0 +1,254 @@
+use crate::bans::{self, BanTarget};
+use crate::bot;
+use crate::config::{check_chat_message, Config};
+use crate::database::{Database, DATABASE};
+use crate::server::Server;
+use dashmap::DashMap;
+use eyre::{bail, eyre, Result};
+use log::info;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Muted users by lowercase name, so logging in again doesn't lift it.
+static MUTES: LazyLock<DashMap<String, Instant>> = LazyLock::new(DashMap::new);
+
+const HELP: [&str; 8] = [
+    "/kick <name> [reason]",
+    "/mute <name> [duration] [reason]",
+    "/unmute <name>",
+    "/ban [--address] <name|ip|range> [duration] [reason]",
+    "/closegame <host|id>",
+    "/announce <text>",
+    "/help",
+    "Durations look like 10m, 2h or 7days",
+];
+
+/// Whether a name is listed as a moderator's. Whoever uses it isn't one until they identify.
+pub fn is_moderator(name: &str) -> bool {
+    Config::current()
+        .moderation
+        .moderators
+        .iter()
+        .any(|moderator| moderator.eq_ignore_ascii_case(name))
+}
+
+/// Whether a user is a moderator who has proved the name is theirs.
+pub fn may_moderate(user_id: u32) -> bool {
+    DATABASE
+        .users
+        .get(&user_id)
+        .is_some_and(|user| user.identified && is_moderator(&user.name))
+}
+
+/// How much longer a user is muted for, if they are.
+pub fn muted_for(name: &str) -> Option<Duration> {
+    let key = name.to_lowercase();
+    let remaining = MUTES
+        .get(&key)
+        .map(|until| until.saturating_duration_since(Instant::now()))?;
+
+    if remaining.is_zero() {
+        MUTES.remove_if(&key, |_, until| *until <= Instant::now());
+        return None;
+    }
+    Some(remaining)
+}
+
+/// Stops a user's messages for a while, keeping it if they log in again.
+pub(crate) fn mute(name: &str, duration: Duration) {
+    MUTES.insert(name.to_lowercase(), Instant::now() + duration);
+}
+
+/// Runs a moderator's slash command, returning what to tell them.
+pub async fn run_command(moderator_id: u32, line: &str) -> Result<Vec<String>> {
+    if !may_moderate(moderator_id) {
+        bail!("Only identified moderators can use moderator commands");
+    }
+    let moderator = DATABASE
+        .users
+        .get(&moderator_id)
+        .map(|user| user.name.clone())
+        .unwrap_or_default();
+    let line = line.trim_start_matches('/');
+    let (command, argument) = split_word(line);
+    info!("Moderator {} ran /{}", moderator, line);
+
+    let reply = match command.to_lowercase().as_str() {
+        "kick" => {
+            let (name, reason) = split_word(argument);
+            let id = find_user(name)?;
+            if !reason.is_empty() {
+                tell(id, &format!("You were kicked: {reason}")).await?;
+            }
+            Server::kick_user(id).await?;
+            vec![format!("Kicked {name}")]
+        }
+        "mute" => {
+            let (name, rest) = split_word(argument);
+            let id = find_user(name)?;
+            let (duration, reason) = split_duration(rest);
+            let duration = duration.unwrap_or(Config::current().moderation.default_mute());
+
+            // Under the name they're using, as `muted_for` looks it up
+            let name = DATABASE
+                .users
+                .get(&id)
+                .map_or_else(|| name.to_string(), |user| user.name.clone());
+            mute(&name, duration);
+            let mut notice = format!("You are muted for {}", format_duration(duration));
+            if !reason.is_empty() {
+                notice.push_str(&format!(": {reason}"));
+            }
+            tell(id, &notice).await?;
+            vec![format!("Muted {name} for {}", format_duration(duration))]
+        }
+        "unmute" => {
+            let (name, _) = split_word(argument);
+            let id = Database::find_user(name);
+            let name = id
+                .and_then(|id| DATABASE.users.get(&id).map(|user| user.name.clone()))
+                .unwrap_or_else(|| name.to_string());
+            if MUTES.remove(&name.to_lowercase()).is_none() {
+                bail!("{name} isn't muted");
+            }
+            if let Some(id) = id {
+                tell(id, "You can chat again").await?;
+            }
+            vec![format!("Unmuted {name}")]
+        }
+        "ban" => {
+            let (with_address, argument) = split_flag(argument, "--address");
+            let (target, rest) = split_word(argument);
+            if target.is_empty() {
+                bail!("Usage: /ban [--address] <name|ip|range> [duration] [reason]");
+            }
+            let target: BanTarget = target.parse()?;
+            let (duration, reason) = split_duration(rest);
+            let expires = duration.map(|duration| SystemTime::now() + duration);
+
+            let outcome = bans::ban(target, reason, expires, with_address).await?;
+            let mut reply: Vec<String> = outcome
+                .banned
+                .iter()
+                .map(|target| format!("Banned {target}"))
+                .collect();
+            reply.extend(outcome.notes);
+            reply.extend(outcome.kicked.iter().map(|name| format!("Kicked {name}")));
+            reply
+        }
+        "closegame" => {
+            let game_id = argument
+                .parse::<u32>()
+                .ok()
+                .filter(|id| DATABASE.games.contains_key(id))
+                .or_else(|| {
+                    DATABASE
+                        .games
+                        .iter()
+                        .find(|g| g.name.eq_ignore_ascii_case(argument))
+                        .map(|g| g.id)
+                })
+                .ok_or_else(|| eyre!("No game hosted by '{argument}'"))?;
+            Server::close_game(game_id).await?;
+            vec![format!("Closed {argument}")]
+        }
+        "announce" => {
+            check_chat_message("announcement", argument)?;
+            if argument.is_empty() {
+                bail!("Usage: /announce <text>");
+            }
+            let sent = Server::broadcast_notice(None, argument).await?;
+            vec![format!("Announced to {sent} users")]
+        }
+        "help" => HELP.iter().map(|line| line.to_string()).collect(),
+        _ => bail!("Unknown command /{command}, try /help"),
+    };
+
+    Ok(reply)
+}
+
+/// Sends a notice to a single user.
+async fn tell(user_id: u32, message: &str) -> Result<()> {
+    let Some(user) = DATABASE.users.get(&user_id) else {
+        return Ok(());
+    };
+    let packet = bot::private_packet(user_id, message)?;
+    user.send_packet(packet).await
+}
+
+fn find_user(name: &str) -> Result<u32> {
+    if name.is_empty() {
+        bail!("Missing the name, see /help");
+    }
+    Database::find_user(name).ok_or_else(|| eyre!("No user called '{name}'"))
+}
+
+/// Splits off the first word, or a quoted phrase for names with spaces in them.
+pub(crate) fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    if let Some((word, rest)) = text.strip_prefix('"').and_then(|text| text.split_once('"')) {
+        return (word, rest.trim_start());
+    }
+    let (word, rest) = text.split_once(' ').unwrap_or((text, ""));
+    (word, rest.trim_start())
+}
+
+/// Splits off a leading `flag`, telling whether it was there.
+pub(crate) fn split_flag<'a>(text: &'a str, flag: &str) -> (bool, &'a str) {
+    match split_word(text) {
+        (word, rest) if word == flag => (true, rest),
+        _ => (false, text),
+    }
+}
+
+/// Splits off a leading duration like `10m`, leaving the text as it is if there's none.
+pub(crate) fn split_duration(text: &str) -> (Option<Duration>, &str) {
+    let (word, rest) = split_word(text);
+    match humantime::parse_duration(word) {
+        Ok(duration) => (Some(duration), rest),
+        Err(_) => (None, text),
+    }
+}
+
+/// Formats a duration to the second, `1h 30m`.
+pub(crate) fn format_duration(duration: Duration) -> String {
+    humantime::format_duration(Duration::from_secs(duration.as_secs().max(1))).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_split_on_words_and_quotes() {
+        assert_eq!(split_word("bob 10m spam"), ("bob", "10m spam"));
+        assert_eq!(split_word("\"Big Bob\" spam"), ("Big Bob", "spam"));
+        assert_eq!(split_word(""), ("", ""));
+        assert_eq!(
+            split_duration("10m spam"),
+            (Some(Duration::from_secs(600)), "spam")
+        );
+        assert_eq!(split_duration("spam again"), (None, "spam again"));
+        assert_eq!(split_flag("--address bob", "--address"), (true, "bob"));
+        assert_eq!(
+            split_flag("bob --address", "--address"),
+            (false, "bob --address")
+        );
+    }
+
+    #[tokio::test]
+    async fn commands_need_an_identified_moderator() {
+        let error = run_command(0, "/help").await.unwrap_err().to_string();
+        assert_eq!(error, "Only identified moderators can use moderator commands");
+    }
+
+    #[test]
+    fn durations_read_naturally() {
+        assert_eq!(format_duration(Duration::from_millis(300)), "1s");
+        assert_eq!(format_duration(Duration::from_secs(3_725)), "1h 2m 5s");
+        assert_eq!(
+            format_duration(Duration::from_secs(90_061)),
+            "1day 1h 1m 1s"
+        );
+    }
+}
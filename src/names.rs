@@ -0,0 +1,358 @@
+use crate::bans::matches_pattern;
+use crate::config::{Config, NamesConfig, RoomNamesConfig};
+use crate::nicknames::NICKNAMES;
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+
+// Which names users can log in with and make rooms with. Names are compared with lookalike
+// characters folded together, so nobody can pass as someone else by writing `0` for `O` or `rn`
+// for `m`.
+
+/// Kinds of characters `allowed_characters` can let into names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CharacterClass {
+    /// Letters, accented ones too
+    Letters,
+    Digits,
+    /// Spaces between words, names can't start or end with one
+    Spaces,
+    /// Anything else printable, like `-`, `_` or `[`
+    Punctuation,
+}
+
+impl CharacterClass {
+    fn of(c: char) -> Option<Self> {
+        if c.is_alphabetic() {
+            Some(CharacterClass::Letters)
+        } else if c.is_ascii_digit() {
+            Some(CharacterClass::Digits)
+        } else if c == ' ' {
+            Some(CharacterClass::Spaces)
+        } else if c.is_control() || c.is_whitespace() {
+            None
+        } else {
+            Some(CharacterClass::Punctuation)
+        }
+    }
+}
+
+/// Why a name can't be used.
+#[derive(Debug, PartialEq)]
+pub enum NameProblem {
+    TooShort(usize),
+    TooLong(usize),
+    Character(char),
+    Spacing,
+    Reserved,
+    /// Looks like a registered name, but isn't it
+    Registered(String),
+    ReservedPrefix(String),
+    Banned,
+}
+
+impl Display for NameProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            NameProblem::TooShort(1) => write!(f, "Names can't be blank"),
+            NameProblem::TooShort(length) => {
+                write!(f, "Names need at least {length} characters")
+            }
+            NameProblem::TooLong(length) => {
+                write!(f, "Names can be at most {length} characters long")
+            }
+            NameProblem::Character(c) if c.is_control() || c.is_whitespace() => {
+                write!(f, "Names can't have invisible characters")
+            }
+            NameProblem::Character(c) => write!(f, "Names can't have '{c}' in them"),
+            NameProblem::Spacing => write!(f, "Names can't start or end with a space"),
+            NameProblem::Reserved => write!(f, "That name is reserved"),
+            NameProblem::Registered(name) => write!(f, "That name is too like {name}'s"),
+            NameProblem::ReservedPrefix(prefix) => {
+                write!(
+                    f,
+                    "Names starting with {prefix} are kept for official rooms"
+                )
+            }
+            NameProblem::Banned => write!(f, "That name isn't allowed"),
+        }
+    }
+}
+
+/// Checks a name someone's logging in with against `names`. Reserved names, moderators' among
+/// them, are let through when they're registered, whoever uses one still has to identify.
+/// Lookalikes of registered names are turned away, they could never identify.
+pub fn check(name: &str) -> Result<(), NameProblem> {
+    let config = Config::current();
+    check_with(&config.names, &config.moderation.moderators, name, |name| {
+        NICKNAMES.registered_name(name)
+    })
+}
+
+fn check_with(
+    config: &NamesConfig,
+    moderators: &[String],
+    name: &str,
+    registered_name: impl Fn(&str) -> Option<String>,
+) -> Result<(), NameProblem> {
+    check_form(name, config.min_length.get(), 0, &config.allowed_characters)?;
+
+    let registered = registered_name(name);
+    if let Some(registered) = &registered {
+        if registered.to_lowercase() != name.to_lowercase() {
+            return Err(NameProblem::Registered(registered.clone()));
+        }
+    }
+
+    let folded = fold(name);
+    let reserved = config
+        .reserved
+        .iter()
+        .any(|pattern| matches_pattern(&fold(pattern), &folded))
+        || moderators.iter().any(|moderator| fold(moderator) == folded);
+    if reserved && registered.is_none() {
+        return Err(NameProblem::Reserved);
+    }
+    Ok(())
+}
+
+/// Checks a name someone wants to make a room with against `room_names`. Reserved prefixes are
+/// left to those allowed them, moderators.
+pub fn check_room(name: &str, may_use_reserved: bool) -> Result<(), NameProblem> {
+    check_room_with(&Config::current().room_names, name, may_use_reserved)
+}
+
+fn check_room_with(
+    config: &RoomNamesConfig,
+    name: &str,
+    may_use_reserved: bool,
+) -> Result<(), NameProblem> {
+    check_form(
+        name,
+        config.min_length.get(),
+        config.max_length,
+        &config.allowed_characters,
+    )?;
+
+    let folded = fold(name);
+    let words: Vec<&str> = folded
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect();
+    if config
+        .banned_words
+        .iter()
+        .map(|banned| fold(banned).replace(|c: char| !c.is_alphanumeric(), ""))
+        .any(|banned| !banned.is_empty() && spells(&words, &banned))
+    {
+        return Err(NameProblem::Banned);
+    }
+
+    if let Some(prefix) = config
+        .reserved_prefixes
+        .iter()
+        .find(|prefix| !prefix.is_empty() && folded.starts_with(&fold(prefix)))
+    {
+        if !may_use_reserved {
+            return Err(NameProblem::ReservedPrefix(prefix.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// Whether some of the words in a row spell `banned` out, like `h eck` or `h.e.c.k`. A word only
+/// having it inside isn't, so `Scunthorpe` or `classic` aren't caught.
+fn spells(words: &[&str], banned: &str) -> bool {
+    (0..words.len()).any(|start| {
+        let mut joined = String::new();
+        for word in &words[start..] {
+            joined.push_str(word);
+            if joined.len() >= banned.len() {
+                return joined == banned;
+            }
+        }
+        false
+    })
+}
+
+/// The length and characters of a name, `max_length` 0 for no limit.
+fn check_form(
+    name: &str,
+    min_length: usize,
+    max_length: usize,
+    allowed_characters: &[CharacterClass],
+) -> Result<(), NameProblem> {
+    if name.trim().chars().count() < min_length {
+        return Err(NameProblem::TooShort(min_length));
+    }
+    if max_length != 0 && name.chars().count() > max_length {
+        return Err(NameProblem::TooLong(max_length));
+    }
+    if name.starts_with(' ') || name.ends_with(' ') {
+        return Err(NameProblem::Spacing);
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|&c| CharacterClass::of(c).is_none_or(|class| !allowed_characters.contains(&class)))
+    {
+        return Err(NameProblem::Character(c));
+    }
+    Ok(())
+}
+
+/// The form of a name two names are the same by, with lookalike characters folded together
+/// unless `names.fold_confusables` is off, and runs of spaces as one.
+pub fn comparable(name: &str) -> String {
+    let name = if Config::current().names.fold_confusables {
+        fold(name)
+    } else {
+        name.to_lowercase()
+    };
+    name.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Lowercases a name and swaps characters that look alike for one of them. `*` and `?` are
+/// kept, so patterns fold too.
+pub fn fold(name: &str) -> String {
+    let mut folded = String::with_capacity(name.len());
+    for c in name.chars().flat_map(char::to_lowercase) {
+        match c {
+            'i' | 'l' | '1' | '|' | '!' | 'ì' | 'í' | 'î' | 'ï' | 'і' | 'ι' => {
+                folded.push('l')
+            }
+            'o' | '0' | 'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'о' | 'ο' => folded.push('o'),
+            's' | '5' | '$' | 'š' | 'ѕ' => folded.push('s'),
+            'a' | '@' | 'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'а' | 'α' => folded.push('a'),
+            'e' | 'è' | 'é' | 'ê' | 'ë' | 'е' | 'ε' => folded.push('e'),
+            'u' | 'ù' | 'ú' | 'û' | 'ü' => folded.push('u'),
+            'y' | 'ý' | 'ÿ' | 'у' => folded.push('y'),
+            'c' | 'ç' | 'с' => folded.push('c'),
+            'n' | 'ñ' => folded.push('n'),
+            'z' | 'ž' => folded.push('z'),
+            'd' | 'ð' => folded.push('d'),
+            // Cyrillic and Greek letters written like Latin ones, some only as capitals
+            'в' | 'β' => folded.push('b'),
+            'р' | 'ρ' => folded.push('p'),
+            'х' | 'χ' => folded.push('x'),
+            'к' | 'κ' => folded.push('k'),
+            'м' | 'μ' => folded.push('m'),
+            'т' | 'τ' => folded.push('t'),
+            'н' | 'η' => folded.push('h'),
+            'ј' => folded.push('j'),
+            'æ' => folded.push_str("ae"),
+            'œ' => folded.push_str("oe"),
+            'ß' => folded.push_str("ss"),
+            // Read as nothing, or as a space
+            '\u{ad}' => {}
+            '\u{a0}' | '_' => folded.push(' '),
+            c => folded.push(c),
+        }
+    }
+    folded.replace("rn", "m").replace("vv", "w")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(config: &NamesConfig, name: &str) -> Result<(), NameProblem> {
+        check_with(config, &["Mod".to_string()], name, |name| {
+            (fold(name) == fold("Admin")).then(|| "Admin".to_string())
+        })
+    }
+
+    #[test]
+    fn lookalikes_fold_together() {
+        assert_eq!(fold("B0B"), fold("bob"));
+        assert_eq!(fold("Wil1iam"), fold("william"));
+        assert_eq!(fold("Modern"), fold("Modem"));
+        assert_eq!(fold("José"), fold("jose"));
+        assert_eq!(fold("Воb"), fold("Bob"));
+        assert_eq!(fold("Рета"), fold("Peta"));
+        assert_ne!(fold("Alice"), fold("Alicia"));
+    }
+
+    #[test]
+    fn names_need_allowed_characters_and_length() {
+        let config = NamesConfig {
+            min_length: 3.try_into().unwrap(),
+            allowed_characters: vec![CharacterClass::Letters, CharacterClass::Spaces],
+            ..Default::default()
+        };
+        assert_eq!(check(&config, "Big Bob"), Ok(()));
+        assert_eq!(check(&config, "Al"), Err(NameProblem::TooShort(3)));
+        assert_eq!(check(&config, "     "), Err(NameProblem::TooShort(3)));
+        assert_eq!(check(&config, " Bob"), Err(NameProblem::Spacing));
+        assert_eq!(check(&config, "Bob2"), Err(NameProblem::Character('2')));
+        assert_eq!(check(&config, "Bo\tb"), Err(NameProblem::Character('\t')));
+    }
+
+    #[test]
+    fn room_names_keep_out_banned_words_and_reserved_prefixes() {
+        let config = RoomNamesConfig {
+            max_length: 12,
+            banned_words: vec!["heck".to_string()],
+            reserved_prefixes: vec!["Official".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(check_room_with(&config, "Fun Room", false), Ok(()));
+        assert_eq!(
+            check_room_with(&config, "HÉCK room", false),
+            Err(NameProblem::Banned)
+        );
+        assert_eq!(
+            check_room_with(&config, "What the h eck", false),
+            Err(NameProblem::TooLong(12))
+        );
+        assert_eq!(
+            check_room_with(&config, "The h eck", false),
+            Err(NameProblem::Banned)
+        );
+        assert_eq!(
+            check_room_with(&config, "0fficial Cup", false),
+            Err(NameProblem::ReservedPrefix("Official".to_string()))
+        );
+        assert_eq!(check_room_with(&config, "Official Cup", true), Ok(()));
+    }
+
+    #[test]
+    fn banned_words_inside_other_words_are_fine() {
+        let config = RoomNamesConfig {
+            banned_words: vec!["ass".to_string(), "heck".to_string()],
+            ..Default::default()
+        };
+        for name in [
+            "Classic",
+            "Assassins",
+            "Scunthorpe",
+            "Checkmate",
+            "Grass hut",
+        ] {
+            assert_eq!(check_room_with(&config, name, false), Ok(()), "{name}");
+        }
+        for name in ["Kick @ss", "H.E.C.K", "what the heck?", "a s s"] {
+            assert_eq!(
+                check_room_with(&config, name, false),
+                Err(NameProblem::Banned),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn reserved_names_need_registering() {
+        let config = NamesConfig {
+            reserved: vec!["admin*".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(check(&config, "Adm1nistrator"), Err(NameProblem::Reserved));
+        assert_eq!(check(&config, "Admin"), Ok(()));
+        assert_eq!(check(&config, "admin"), Ok(()));
+        assert_eq!(
+            check(&config, "Adm1n"),
+            Err(NameProblem::Registered("Admin".to_string()))
+        );
+        assert_eq!(check(&config, "Bob"), Ok(()));
+        assert_eq!(check(&config, "M0D"), Err(NameProblem::Reserved));
+    }
+}
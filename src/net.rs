@@ -1,8 +1,11 @@
+pub mod address_range;
+pub mod capture;
 pub mod nation;
 pub mod packet_code;
 pub mod packet_handler;
+pub mod requests;
 pub mod session_access;
 pub mod session_info;
 pub mod session_type;
 pub mod worms_codec;
-pub mod worms_packet;
\ No newline at end of file
+pub mod worms_packet;
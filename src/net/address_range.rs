@@ -0,0 +1,153 @@
+use eyre::{eyre, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// An address, or a range of them written as `203.0.113.0/24`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct AddressRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl AddressRange {
+    /// The range of `prefix` leading bits `address` is in, a prefix longer than the address
+    /// has is cut down to it.
+    pub fn containing(address: IpAddr, prefix: u8) -> Self {
+        let address = address.to_canonical();
+        let prefix = prefix.min(if address.is_ipv4() { 32 } else { 128 });
+        Self {
+            network: mask(address, prefix),
+            prefix,
+        }
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        // Clients on a dual stack socket show up as IPv4 mapped IPv6 addresses
+        let address = address.to_canonical();
+        match (self.network, address) {
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                mask(address, self.prefix) == self.network
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Zeroes everything but the first `prefix` bits of an address.
+fn mask(address: IpAddr, prefix: u8) -> IpAddr {
+    match address {
+        IpAddr::V4(v4) => {
+            let bits = u32::from(v4)
+                .checked_shr(32 - u32::from(prefix))
+                .map_or(0, |bits| bits << (32 - u32::from(prefix)));
+            IpAddr::from(bits.to_be_bytes())
+        }
+        IpAddr::V6(v6) => {
+            let bits = u128::from(v6)
+                .checked_shr(128 - u32::from(prefix))
+                .map_or(0, |bits| bits << (128 - u32::from(prefix)));
+            IpAddr::from(bits.to_be_bytes())
+        }
+    }
+}
+
+impl FromStr for AddressRange {
+    type Err = eyre::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let (address, prefix) = text.split_once('/').unwrap_or((text, ""));
+        let address = IpAddr::from_str(address)
+            .map_err(|_| eyre!("'{text}' isn't an address or range"))?
+            .to_canonical();
+        let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            "" => max_prefix,
+            prefix => prefix
+                .parse()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| eyre!("'{text}' has an invalid prefix length"))?,
+        };
+
+        Ok(Self {
+            network: mask(address, prefix),
+            prefix,
+        })
+    }
+}
+
+impl From<IpAddr> for AddressRange {
+    fn from(address: IpAddr) -> Self {
+        AddressRange::containing(address, u8::MAX)
+    }
+}
+
+impl TryFrom<String> for AddressRange {
+    type Error = eyre::Error;
+
+    fn try_from(text: String) -> Result<Self> {
+        text.parse()
+    }
+}
+
+impl From<AddressRange> for String {
+    fn from(range: AddressRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl Display for AddressRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let max_prefix = if self.network.is_ipv4() { 32 } else { 128 };
+        if self.prefix == max_prefix {
+            write!(f, "{}", self.network)
+        } else {
+            write!(f, "{}/{}", self.network, self.prefix)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_contain_their_addresses() {
+        let range: AddressRange = "203.0.113.77/24".parse().unwrap();
+        assert_eq!(range.to_string(), "203.0.113.0/24");
+        assert!(range.contains("203.0.113.1".parse().unwrap()));
+        assert!(range.contains("::ffff:203.0.113.200".parse().unwrap()));
+        assert!(!range.contains("203.0.114.1".parse().unwrap()));
+        assert!(!range.contains("2001:db8::1".parse().unwrap()));
+
+        let single: AddressRange = "2001:db8::1".parse().unwrap();
+        assert_eq!(single.to_string(), "2001:db8::1");
+        assert!(!single.contains("2001:db8::2".parse().unwrap()));
+        assert!("0.0.0.0/0"
+            .parse::<AddressRange>()
+            .unwrap()
+            .contains("198.51.100.3".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<AddressRange>().is_err());
+    }
+
+    #[test]
+    fn addresses_group_by_prefix() {
+        let address = "198.51.100.23".parse().unwrap();
+        assert_eq!(
+            AddressRange::containing(address, 24).to_string(),
+            "198.51.100.0/24"
+        );
+        assert_eq!(
+            AddressRange::containing(address, 64).to_string(),
+            "198.51.100.23"
+        );
+        let address = "2001:db8:1:2:3:4:5:6".parse().unwrap();
+        assert_eq!(
+            AddressRange::containing(address, 64).to_string(),
+            "2001:db8:1:2::/64"
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use crate::net::worms_codec::WormCodec;
+use crate::net::worms_packet::WormsPacket;
+use eyre::{bail, Result, WrapErr};
+use log::error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_util::bytes::{Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+const MAGIC: &[u8; 4] = b"WCAP";
+const VERSION: u16 = 1;
+
+pub static RECORDER: OnceLock<Recorder> = OnceLock::new();
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[repr(u8)]
+pub enum RecordKind {
+    /// A client connected, the payload is its address.
+    Connect = 0,
+    /// A frame received from the client.
+    Inbound = 1,
+    /// A frame sent to the client.
+    Outbound = 2,
+    /// The connection was closed.
+    Disconnect = 3,
+}
+
+impl TryFrom<u8> for RecordKind {
+    type Error = eyre::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(RecordKind::Connect),
+            1 => Ok(RecordKind::Inbound),
+            2 => Ok(RecordKind::Outbound),
+            3 => Ok(RecordKind::Disconnect),
+            _ => bail!("Invalid record kind {}", value),
+        }
+    }
+}
+
+/// A single entry of a capture file.
+///
+/// Layout (little endian): timestamp in microseconds since the unix epoch `u64`, connection id
+/// `u32`, user id `u32` (0 before login), kind `u8`, payload length `u32`, payload.
+#[derive(Debug)]
+pub struct CaptureRecord {
+    pub timestamp_micros: u64,
+    pub connection_id: u32,
+    pub user_id: u32,
+    pub kind: RecordKind,
+    pub payload: Bytes,
+}
+
+impl CaptureRecord {
+    pub fn write_to(&self, dst: &mut impl Write) -> std::io::Result<()> {
+        dst.write_all(&self.timestamp_micros.to_le_bytes())?;
+        dst.write_all(&self.connection_id.to_le_bytes())?;
+        dst.write_all(&self.user_id.to_le_bytes())?;
+        dst.write_all(&[self.kind as u8])?;
+        dst.write_all(&(self.payload.len() as u32).to_le_bytes())?;
+        dst.write_all(&self.payload)
+    }
+
+    /// Reads the next record, `None` at the end of the capture.
+    pub fn read_from(src: &mut impl Read) -> Result<Option<Self>> {
+        let mut header = [0u8; 21];
+        match src.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        let timestamp_micros = u64::from_le_bytes(header[0..8].try_into()?);
+        let connection_id = u32::from_le_bytes(header[8..12].try_into()?);
+        let user_id = u32::from_le_bytes(header[12..16].try_into()?);
+        let kind = RecordKind::try_from(header[16])?;
+        let length = u32::from_le_bytes(header[17..21].try_into()?) as usize;
+
+        let mut payload = vec![0u8; length];
+        src.read_exact(&mut payload)
+            .wrap_err("Capture ends in the middle of a record")?;
+
+        Ok(Some(Self {
+            timestamp_micros,
+            connection_id,
+            user_id,
+            kind,
+            payload: payload.into(),
+        }))
+    }
+}
+
+/// Reads the records of a capture file written by the `Recorder`.
+pub struct CaptureReader<R> {
+    src: R,
+}
+
+impl CaptureReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = File::open(path.as_ref())
+            .wrap_err_with(|| format!("Unable to open {}", path.as_ref().display()))?;
+        Self::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut src: R) -> Result<Self> {
+        let mut header = [0u8; 6];
+        src.read_exact(&mut header).wrap_err("Not a capture file")?;
+        if &header[..4] != MAGIC {
+            bail!("Not a capture file");
+        }
+
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if version != VERSION {
+            bail!("Unsupported capture version {}", version);
+        }
+
+        Ok(Self { src })
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        CaptureRecord::read_from(&mut self.src).transpose()
+    }
+}
+
+/// Writes every frame of every connection to a capture file, on its own thread so connections
+/// never wait on the disk.
+pub struct Recorder {
+    sender: Sender<CaptureRecord>,
+    next_connection_id: AtomicU32,
+}
+
+impl Recorder {
+    /// Starts recording to `path`, truncating it if it exists.
+    pub fn start(path: impl AsRef<Path>) -> Result<()> {
+        let file = File::create(path.as_ref())
+            .wrap_err_with(|| format!("Unable to create {}", path.as_ref().display()))?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+
+        let (sender, receiver) = channel::<CaptureRecord>();
+        std::thread::Builder::new()
+            .name("capture-writer".to_string())
+            .spawn(move || {
+                // Write whatever is queued up, then flush before waiting for more
+                while let Ok(record) = receiver.recv() {
+                    let result = std::iter::once(record)
+                        .chain(receiver.try_iter())
+                        .try_for_each(|record| record.write_to(&mut writer))
+                        .and_then(|()| writer.flush());
+
+                    if let Err(e) = result {
+                        error!("Error writing capture: {}", e);
+                        return;
+                    }
+                }
+            })?;
+
+        let recorder = Recorder {
+            sender,
+            next_connection_id: AtomicU32::new(1),
+        };
+        if RECORDER.set(recorder).is_err() {
+            bail!("Recorder already started");
+        }
+
+        Ok(())
+    }
+
+    /// Starts recording a new connection, `None` if recording is disabled.
+    pub fn session(address: SocketAddr) -> Option<Arc<CaptureSession>> {
+        let recorder = RECORDER.get()?;
+        let session = Arc::new(CaptureSession {
+            connection_id: recorder.next_connection_id.fetch_add(1, Ordering::Relaxed),
+            user_id: AtomicU32::new(0),
+        });
+        session.record(RecordKind::Connect, Bytes::from(address.to_string()));
+
+        Some(session)
+    }
+
+    fn record(&self, record: CaptureRecord) {
+        // The writer only goes away if writing failed, which has been logged already
+        let _ = self.sender.send(record);
+    }
+}
+
+pub struct CaptureSession {
+    connection_id: u32,
+    user_id: AtomicU32,
+}
+
+impl CaptureSession {
+    pub fn set_user_id(&self, user_id: u32) {
+        self.user_id.store(user_id, Ordering::Relaxed);
+    }
+
+    fn record(&self, kind: RecordKind, payload: Bytes) {
+        let Some(recorder) = RECORDER.get() else {
+            return;
+        };
+
+        let timestamp_micros = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+
+        recorder.record(CaptureRecord {
+            timestamp_micros,
+            connection_id: self.connection_id,
+            user_id: self.user_id.load(Ordering::Relaxed),
+            kind,
+            payload,
+        });
+    }
+}
+
+impl Drop for CaptureSession {
+    fn drop(&mut self) {
+        self.record(RecordKind::Disconnect, Bytes::new());
+    }
+}
+
+/// `WormCodec` that also hands every frame to the connection's capture session, if there is one.
+pub struct RecordingCodec {
+    session: Option<Arc<CaptureSession>>,
+}
+
+impl RecordingCodec {
+    pub fn new(session: Option<Arc<CaptureSession>>) -> Self {
+        Self { session }
+    }
+}
+
+impl Encoder<Arc<Bytes>> for RecordingCodec {
+    type Error = eyre::Error;
+
+    fn encode(&mut self, item: Arc<Bytes>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        if let Some(session) = &self.session {
+            session.record(RecordKind::Outbound, Bytes::clone(&item));
+        }
+
+        WormCodec.encode(item, dst)
+    }
+}
+
+impl Decoder for RecordingCodec {
+    type Item = WormsPacket;
+    type Error = eyre::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(session) = &self.session else {
+            return WormCodec.decode(src);
+        };
+
+        // Copy the frame before the codec takes it apart, but only once it's complete
+        let frame = match WormCodec::frame_length(src) {
+            Ok(Some(length)) if src.len() >= length => Some(Bytes::copy_from_slice(&src[..length])),
+            _ => None,
+        };
+
+        match WormCodec.decode(src) {
+            Ok(packet) => {
+                if let (Some(_), Some(frame)) = (&packet, frame) {
+                    session.record(RecordKind::Inbound, frame);
+                }
+                Ok(packet)
+            }
+            Err(e) => {
+                // Keep the bytes that failed to decode, they're usually what's being debugged
+                session.record(RecordKind::Inbound, Bytes::copy_from_slice(src));
+                Err(e)
+            }
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use std::str::FromStr;
+
 /// <summary>
 /// Represents the flag sent with in a <see cref="SessionInfo"/>.
 /// </summary>
@@ -229,3 +231,17 @@ impl From<u8> for Nation {
         }
     }
 }
+
+/// Parses the name of a flag, ignoring case. `None` is no flag.
+impl FromStr for Nation {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        (0..=u8::from(Nation::Team17))
+            .map(Nation::from)
+            .find(|nation| format!("{nation:?}").eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                eyre::eyre!("Unknown nation '{s}', use a two letter country code, Skull or Team17")
+            })
+    }
+}
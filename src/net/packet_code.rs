@@ -30,6 +30,37 @@ pub enum PacketCode {
     Unknown = 0,
 }
 
+impl PacketCode {
+    /// The name of the code, as the metrics label it.
+    pub fn name(self) -> &'static str {
+        match self {
+            PacketCode::ListRooms => "ListRooms",
+            PacketCode::ListItem => "ListItem",
+            PacketCode::ListEnd => "ListEnd",
+            PacketCode::ListUsers => "ListUsers",
+            PacketCode::ListGames => "ListGames",
+            PacketCode::Login => "Login",
+            PacketCode::LoginReply => "LoginReply",
+            PacketCode::CreateRoom => "CreateRoom",
+            PacketCode::CreateRoomReply => "CreateRoomReply",
+            PacketCode::Join => "Join",
+            PacketCode::JoinReply => "JoinReply",
+            PacketCode::Leave => "Leave",
+            PacketCode::LeaveReply => "LeaveReply",
+            PacketCode::DisconnectUser => "DisconnectUser",
+            PacketCode::Close => "Close",
+            PacketCode::CloseReply => "CloseReply",
+            PacketCode::CreateGame => "CreateGame",
+            PacketCode::CreateGameReply => "CreateGameReply",
+            PacketCode::ChatRoom => "ChatRoom",
+            PacketCode::ChatRoomReply => "ChatRoomReply",
+            PacketCode::ConnectGame => "ConnectGame",
+            PacketCode::ConnectGameReply => "ConnectGameReply",
+            PacketCode::Unknown => "Unknown",
+        }
+    }
+}
+
 impl From<PacketCode> for u32 {
     fn from(value: PacketCode) -> Self {
         match value {
@@ -91,3 +122,16 @@ impl TryFrom<u32> for PacketCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_match_the_variants() {
+        for code in (0..2000).filter_map(|value| PacketCode::try_from(value).ok()) {
+            assert_eq!(code.name(), format!("{code:?}"));
+        }
+        assert_eq!(PacketCode::Unknown.name(), "Unknown");
+    }
+}
@@ -9,6 +9,7 @@ pub(crate) mod list_games_handler;
 pub(crate) mod list_rooms_handler;
 pub(crate) mod list_users_handler;
 
+use crate::metrics::METRICS;
 use crate::net::{
     packet_code::PacketCode,
     packet_handler::{
@@ -27,57 +28,59 @@ use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
 use tokio_util::bytes::Bytes;
 
-pub trait PacketHandler {
+pub(crate) trait PacketHandler {
+    type Request: for<'a> TryFrom<&'a WormsPacket, Error = eyre::Error>;
+
     async fn handle_packet(
         tx: Sender<Arc<Bytes>>,
-        packet: Arc<WormsPacket>,
+        request: Self::Request,
         _client_id: u32,
         _address: SocketAddr,
     ) -> Result<()>;
 }
 
+/// Parses the packet into the handler's typed request and runs the handler with it.
+async fn handle<H: PacketHandler>(
+    tx: Sender<Arc<Bytes>>,
+    packet: &WormsPacket,
+    client_id: u32,
+    address: SocketAddr,
+) -> Result<()> {
+    let request = H::Request::try_from(packet)?;
+    H::handle_packet(tx, request, client_id, address).await
+}
+
 pub async fn dispatch(
-    code: PacketCode,
     tx: Sender<Arc<Bytes>>,
-    packet: Arc<WormsPacket>,
+    packet: &WormsPacket,
     client_id: u32,
     address: SocketAddr,
 ) -> Result<()> {
+    let code = packet.header_code;
     debug!("Dispatching handler for: {:?}", &code);
+    let _timer = METRICS.dispatch_timer(code);
     match code {
-        PacketCode::ListRooms => {
-            ListRoomsHandler::handle_packet(tx, packet, client_id, address).await
-        }
+        PacketCode::ListRooms => handle::<ListRoomsHandler>(tx, packet, client_id, address).await,
 
-        PacketCode::CreateRoom => {
-            CreateRoomHandler::handle_packet(tx, packet, client_id, address).await
-        }
+        PacketCode::CreateRoom => handle::<CreateRoomHandler>(tx, packet, client_id, address).await,
 
-        PacketCode::ListUsers => {
-            ListUsersHandler::handle_packet(tx, packet, client_id, address).await
-        }
+        PacketCode::ListUsers => handle::<ListUsersHandler>(tx, packet, client_id, address).await,
 
-        PacketCode::ListGames => {
-            ListGamesHandler::handle_packet(tx, packet, client_id, address).await
-        }
+        PacketCode::ListGames => handle::<ListGamesHandler>(tx, packet, client_id, address).await,
 
-        PacketCode::Join => JoinHandler::handle_packet(tx, packet, client_id, address).await,
+        PacketCode::Join => handle::<JoinHandler>(tx, packet, client_id, address).await,
 
-        PacketCode::CreateGame => {
-            CreateGameHandler::handle_packet(tx, packet, client_id, address).await
-        }
+        PacketCode::CreateGame => handle::<CreateGameHandler>(tx, packet, client_id, address).await,
 
-        PacketCode::ChatRoom => {
-            ChatRoomHandler::handle_packet(tx, packet, client_id, address).await
-        }
+        PacketCode::ChatRoom => handle::<ChatRoomHandler>(tx, packet, client_id, address).await,
 
         PacketCode::ConnectGame => {
-            ConnectGameHandler::handle_packet(tx, packet, client_id, address).await
+            handle::<ConnectGameHandler>(tx, packet, client_id, address).await
         }
 
-        PacketCode::Close => CloseHandler::handle_packet(tx, packet, client_id, address).await,
+        PacketCode::Close => handle::<CloseHandler>(tx, packet, client_id, address).await,
 
-        PacketCode::Leave => LeaveHandler::handle_packet(tx, packet, client_id, address).await,
+        PacketCode::Leave => handle::<LeaveHandler>(tx, packet, client_id, address).await,
 
         _ => bail!("Unknown packet dispatched! {:?}", code),
     }
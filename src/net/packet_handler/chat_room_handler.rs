@@ -1,57 +1,182 @@
+use crate::audit::{self, Actor, Event};
+use crate::bot;
+use crate::chat_filter::{self, Penalty, Verdict};
+use crate::config::Config;
 use crate::database::DATABASE;
+use crate::history;
+use crate::moderation;
 use crate::net::packet_code::PacketCode;
 use crate::net::packet_handler::PacketHandler;
-use crate::net::worms_packet::WormsPacket;
+use crate::net::requests::{ChatKind, ChatRoomRequest};
+use crate::net::worms_packet::{decode_text, encode_text, WormsPacket};
+use crate::nicknames::{self, NICKNAMES};
+use crate::server::Server;
 use eyre::{bail, OptionExt, Result};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
-use tokio_util::bytes::Bytes;
+use tokio_util::bytes::{Bytes, BytesMut};
 
 pub struct ChatRoomHandler;
 
+impl ChatRoomHandler {
+    async fn reply(tx: &Sender<Arc<Bytes>>, error_code: u32) -> Result<()> {
+        let packet = WormsPacket::create(PacketCode::ChatRoomReply)
+            .with_error_code(error_code)
+            .build()?;
+        tx.send(packet).await?;
+        Ok(())
+    }
+}
+
 impl PacketHandler for ChatRoomHandler {
+    type Request = ChatRoomRequest;
+
     async fn handle_packet(
         tx: Sender<Arc<Bytes>>,
-        packet: Arc<WormsPacket>,
+        request: ChatRoomRequest,
         client_id: u32,
         _address: SocketAddr,
     ) -> Result<()> {
-        if packet.value_0 != Some(client_id) {
+        if request.from_id != client_id {
             bail!("From user invalid!");
         }
 
-        let message = packet
-            .data
-            .as_ref()
-            .ok_or_eyre("No message included in chat packet!")?;
-        let target_id = packet
-            .value_3
-            .ok_or_eyre("No target id included in chat packet!")?;
-
-        let client_user = DATABASE
-            .users
-            .get(&client_id)
-            .ok_or_eyre(format!("User '{client_id}' not found!"))?;
-
-        // Regular chat
-        let prefix = format!("GRP:[ {} ]  ", &client_user.name);
-        if message.starts_with(&prefix) {
-            // Check if user can access the room.
-            if client_user.room_id == target_id {
+        let target_id = request.target_id;
+        let (client_room_id, client_name, encoded_name, identified, mut split) = {
+            let client_user = DATABASE
+                .users
+                .get(&client_id)
+                .ok_or_eyre(format!("User '{client_id}' not found!"))?;
+
+            (
+                client_user.room_id,
+                client_user.name.clone(),
+                client_user.encoded_name.clone(),
+                client_user.identified,
+                request.split_message(&client_user.encoded_name),
+            )
+        };
+
+        if let Some((kind, body)) = &split {
+            let text = decode_text(body);
+            let notice = |message: &str| bot::private_packet(client_id, message);
+
+            if *kind == ChatKind::Private && bot::is_bot(target_id) {
+                let replies = bot::handle_message(client_id, &text)
+                    .await
+                    .unwrap_or_else(|e| vec![e.to_string()]);
+                for reply in replies {
+                    tx.send(bot::private_packet(client_id, &reply)?).await?;
+                }
+                return ChatRoomHandler::reply(&tx, 0).await;
+            }
+
+            if *kind == ChatKind::Group && nicknames::is_password_command(&text) {
+                // Sent to the room by mistake, it likely has a password in it
+                let bot_name = &Config::current().bot.name;
+                tx.send(notice(&format!(
+                    "Not sent, commands for {bot_name} go in a private message to them"
+                ))?)
+                .await?;
+                return ChatRoomHandler::reply(&tx, 1).await;
+            }
+
+            if text.starts_with('/') && moderation::is_moderator(&client_name) {
+                // Otherwise anyone logging in with a moderator's name would get their commands
+                if !identified {
+                    let bot_name = &Config::current().bot.name;
+                    // Moderators' names are only registered by admins, don't invite anyone to
+                    let message = if NICKNAMES.is_registered(&client_name) {
+                        format!("Identify with {bot_name} before using moderator commands")
+                    } else {
+                        "Moderator commands need your name registered by an admin".to_string()
+                    };
+                    tx.send(notice(&message)?).await?;
+                    return ChatRoomHandler::reply(&tx, 1).await;
+                }
+
+                // Commands aren't chat, only the moderator sees anything of them
+                let replies = match moderation::run_command(client_id, &text).await {
+                    Ok(replies) => {
+                        let action = text.trim_start_matches('/').to_string();
+                        let event = Event::Moderation {
+                            by: "moderator",
+                            action,
+                        };
+                        audit::record(Actor::user(client_id), event);
+                        replies
+                    }
+                    Err(e) => vec![e.to_string()],
+                };
+                for reply in replies {
+                    tx.send(notice(&reply)?).await?;
+                }
+                return ChatRoomHandler::reply(&tx, 0).await;
+            }
+
+            if nicknames::needs_identifying(client_id) {
+                tx.send(notice("Identify before chatting, your name is registered")?)
+                    .await?;
+                return ChatRoomHandler::reply(&tx, 1).await;
+            }
+
+            if let Some(remaining) = moderation::muted_for(&client_name) {
+                let message = format!(
+                    "You are muted, your messages aren't sent for another {}",
+                    moderation::format_duration(remaining)
+                );
+                tx.send(notice(&message)?).await?;
+                return ChatRoomHandler::reply(&tx, 1).await;
+            }
+        }
+
+        let mut message = request.message;
+        if let Some((_, body)) = &mut split {
+            match chat_filter::check(&client_name, &decode_text(body)) {
+                Verdict::Send => {}
+                Verdict::Masked(masked) => {
+                    let masked = encode_text(&masked).ok_or_eyre("Masked message won't encode")?;
+                    let mut data = BytesMut::from(&message[..message.len() - body.len()]);
+                    data.extend_from_slice(&masked);
+                    message = data.freeze();
+                    *body = message.slice(message.len() - masked.len()..);
+                }
+                Verdict::Blocked(reason) => {
+                    let notice = |text: &str| bot::private_packet(client_id, text);
+                    let penalty = chat_filter::strike(client_id, &client_name);
+                    tx.send(notice(&format!("Not sent, {reason}"))?).await?;
+                    if let Some(explanation) = penalty.explain() {
+                        tx.send(notice(&explanation)?).await?;
+                    }
+                    if penalty == Penalty::Kicked {
+                        Server::kick_user(client_id).await?;
+                    }
+                    return ChatRoomHandler::reply(&tx, 1).await;
+                }
+            }
+        }
+
+        match split {
+            // Regular chat, check if user can access the room.
+            Some((ChatKind::Group, body)) if client_room_id == target_id => {
+                // The message is forwarded as received, no need to decode and encode it again
                 let packet = WormsPacket::create(PacketCode::ChatRoom)
                     .with_value_0(client_id)
-                    .with_value_3(client_user.room_id)
-                    .with_data(message)
+                    .with_value_3(client_room_id)
+                    .with_raw_data(message)
                     .build()?;
 
                 for user in DATABASE
                     .users
                     .iter()
-                    .filter(|u| u.id != client_id && u.room_id == client_user.room_id)
+                    .filter(|u| u.id != client_id && u.room_id == client_room_id)
                 {
                     user.send_packet(Arc::clone(&packet)).await?;
                 }
+                let message = decode_text(&body).into_owned();
+                history::record(client_room_id, client_id, encoded_name, body);
+                audit::record(Actor::user(client_id), Event::Chat { message });
 
                 let packet = WormsPacket::create(PacketCode::ChatRoomReply)
                     .with_error_code(0)
@@ -61,29 +186,34 @@ impl PacketHandler for ChatRoomHandler {
 
                 return Ok(());
             }
-        }
+            // Private chat, check if user can access the user.
+            Some((ChatKind::Private, body)) => {
+                if let Some(target_user) = DATABASE.users.get(&target_id) {
+                    if target_user.room_id == client_room_id {
+                        let packet = WormsPacket::create(PacketCode::ChatRoom)
+                            .with_value_0(client_id)
+                            .with_value_3(target_user.id)
+                            .with_raw_data(message)
+                            .build()?;
+
+                        target_user.send_packet(packet).await?;
+                        let event = Event::PrivateChat {
+                            to_id: target_id,
+                            to_name: target_user.name.clone(),
+                            message: decode_text(&body).into_owned(),
+                        };
+                        drop(target_user);
+                        audit::record(Actor::user(client_id), event);
 
-        // Private chat
-        let prefix = format!("PRV:[ {} ]  ", &client_user.name);
-        if message.starts_with(&prefix) {
-            // Check if user can access the user.
-            if let Some(target_user) = DATABASE.users.get(&target_id) {
-                if target_user.room_id == client_user.room_id {
-                    let packet = WormsPacket::create(PacketCode::ChatRoom)
-                        .with_value_0(client_id)
-                        .with_value_3(target_user.id)
-                        .with_data(message)
-                        .build()?;
-
-                    target_user.send_packet(packet).await?;
-
-                    let packet = WormsPacket::create(PacketCode::ChatRoomReply)
-                        .with_error_code(0)
-                        .build()?;
-                    tx.send(packet).await?;
-                    return Ok(());
+                        let packet = WormsPacket::create(PacketCode::ChatRoomReply)
+                            .with_error_code(0)
+                            .build()?;
+                        tx.send(packet).await?;
+                        return Ok(());
+                    }
                 }
             }
+            _ => {}
         }
 
         // Failed to send
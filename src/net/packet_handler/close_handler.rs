@@ -1,5 +1,6 @@
 use crate::net::packet_code::PacketCode;
 use crate::net::packet_handler::PacketHandler;
+use crate::net::requests::CloseRequest;
 use crate::net::worms_packet::WormsPacket;
 use eyre::Result;
 use std::net::SocketAddr;
@@ -10,13 +11,15 @@ use tokio_util::bytes::Bytes;
 pub struct CloseHandler;
 
 impl PacketHandler for CloseHandler {
+    type Request = CloseRequest;
+
     async fn handle_packet(
         tx: Sender<Arc<Bytes>>,
-        packet: Arc<WormsPacket>,
+        request: CloseRequest,
         _client_id: u32,
         _address: SocketAddr,
     ) -> Result<()> {
-        if packet.value_10.is_some() {
+        if request.id.is_some() {
             let packet = WormsPacket::create(PacketCode::CloseReply)
                 .with_error_code(0)
                 .build()?;
@@ -1,8 +1,9 @@
 use crate::database::DATABASE;
 use crate::net::packet_code::PacketCode;
 use crate::net::packet_handler::PacketHandler;
+use crate::net::requests::ConnectGameRequest;
 use crate::net::worms_packet::WormsPacket;
-use eyre::{OptionExt, Result};
+use eyre::Result;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
@@ -11,15 +12,15 @@ use tokio_util::bytes::Bytes;
 pub struct ConnectGameHandler;
 
 impl PacketHandler for ConnectGameHandler {
+    type Request = ConnectGameRequest;
+
     async fn handle_packet(
         tx: Sender<Arc<Bytes>>,
-        packet: Arc<WormsPacket>,
+        request: ConnectGameRequest,
         client_id: u32,
         _address: SocketAddr,
     ) -> Result<()> {
-        let game_id = packet.value_0.ok_or_eyre("no game id included!")?;
-
-        if let Some(game) = DATABASE.games.get(&game_id) {
+        if let Some(game) = DATABASE.games.get(&request.game_id) {
             let user_room_id = { DATABASE.users.get(&client_id).map(|u| u.room_id) };
 
             if Some(game.room_id) == user_room_id {
@@ -1,23 +1,28 @@
+use crate::audit::{self, Actor, Event};
+use crate::bot;
 use crate::database::game::Game;
 use crate::database::{Database, DATABASE};
 use crate::net::packet_code::PacketCode;
 use crate::net::packet_handler::PacketHandler;
+use crate::net::requests::CreateGameRequest;
 use crate::net::worms_packet::WormsPacket;
 use crate::server::Server;
-use eyre::{bail, eyre, OptionExt, Result};
-use std::net::{IpAddr, SocketAddr};
+use eyre::{bail, OptionExt, Result};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
 use tokio_util::bytes::Bytes;
 
 pub struct CreateGameHandler;
 
-const INVALID_MESSAGE: &str = "GRP:Cannot host your game. Please use FrontendKitWS with fkNetcode. More information at worms2d.info/fkNetcode";
+const INVALID_MESSAGE: &str = "Cannot host your game. Please use FrontendKitWS with fkNetcode. More information at worms2d.info/fkNetcode";
 
 impl PacketHandler for CreateGameHandler {
+    type Request = CreateGameRequest;
+
     async fn handle_packet(
         tx: Sender<Arc<Bytes>>,
-        packet: Arc<WormsPacket>,
+        request: CreateGameRequest,
         client_id: u32,
         address: SocketAddr,
     ) -> Result<()> {
@@ -26,26 +31,11 @@ impl PacketHandler for CreateGameHandler {
             .get(&client_id)
             .ok_or_eyre("client user not found!")?;
 
-        if packet.value_1 != Some(0)
-            || packet.value_2 != Some(client_user.room_id)
-            || packet.value_4 != Some(0x800)
-            || packet.data.is_none()
-            || packet.name.is_none()
-            || packet.session.is_none()
-        {
+        if request.room_id != client_user.room_id {
             bail!("Invalid Data!");
         }
 
-        let ip_result = packet
-            .data
-            .as_ref()
-            .ok_or(eyre!(
-                "No ip received in create game handler!, got {:?}",
-                packet.data
-            ))?
-            .parse::<IpAddr>();
-
-        if let Ok(ip) = ip_result {
+        if let Some(ip) = request.ip {
             if address.ip().to_string() == "127.0.0.1" || ip == address.ip() {
                 let new_id = Database::get_next_id();
 
@@ -55,7 +45,7 @@ impl PacketHandler for CreateGameHandler {
                     client_user.session.nation,
                     client_user.room_id,
                     address.ip(),
-                    packet.session.as_ref().unwrap().access,
+                    request.access,
                 );
 
                 let packet = WormsPacket::create(PacketCode::CreateGame)
@@ -67,6 +57,10 @@ impl PacketHandler for CreateGameHandler {
                     .with_session(&game.session)
                     .build()?;
 
+                audit::record(
+                    Actor::of(&client_user),
+                    Event::CreateGame { game_id: new_id },
+                );
                 DATABASE.games.insert(new_id, game);
                 Server::broadcast_all_except(packet, &client_id).await?;
 
@@ -86,12 +80,8 @@ impl PacketHandler for CreateGameHandler {
             .build()?;
         tx.send(packet).await?;
 
-        let packet = WormsPacket::create(PacketCode::ChatRoom)
-            .with_value_1(client_user.id)
-            .with_value_3(client_user.room_id)
-            .with_data(INVALID_MESSAGE)
-            .build()?;
-        tx.send(packet).await?;
+        tx.send(bot::chat_packet(client_user.room_id, INVALID_MESSAGE)?)
+            .await?;
 
         Ok(())
     }
@@ -1,10 +1,18 @@
+use crate::audit::{self, Actor, Event};
+use crate::bot;
+use crate::config::Config;
 use crate::database::room::Room;
 use crate::database::{Database, DATABASE};
+use crate::moderation;
+use crate::names::{self, NameProblem};
 use crate::net::packet_code::PacketCode;
 use crate::net::packet_handler::PacketHandler;
+use crate::net::requests::CreateRoomRequest;
+use crate::net::session_access::SessionAccess;
 use crate::net::worms_packet::WormsPacket;
+use crate::rooms;
 use crate::server::Server;
-use eyre::{bail, OptionExt, Result};
+use eyre::Result;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
@@ -12,50 +20,105 @@ use tokio_util::bytes::Bytes;
 
 pub struct CreateRoomHandler;
 
+/// Reply error codes, the game itself only tells apart making the room or not.
+const CREATE_TAKEN: u32 = 1;
+const CREATE_TOO_MANY: u32 = 2;
+const CREATE_SERVER_FULL: u32 = 3;
+const CREATE_NAME_LENGTH: u32 = 4;
+const CREATE_NAME_CHARACTERS: u32 = 5;
+const CREATE_NAME_BANNED: u32 = 6;
+const CREATE_NAME_RESERVED: u32 = 7;
+
 impl PacketHandler for CreateRoomHandler {
+    type Request = CreateRoomRequest;
+
     async fn handle_packet(
         tx: Sender<Arc<Bytes>>,
-        packet: Arc<WormsPacket>,
+        request: CreateRoomRequest,
         client_id: u32,
         _address: SocketAddr,
     ) -> Result<()> {
-        if packet.value_1 != Some(0)
-            || packet.value_4 != Some(0)
-            || packet.data.is_none()
-            || packet.name.is_none()
-            || packet.session.is_none()
-        {
-            bail!("Invalid Data!");
-        }
-
-        let room_name = packet
-            .name
-            .as_ref()
-            .ok_or_eyre("no room name included in create room handler!")?;
+        let room_name = request.name_text();
+        let config = Config::current();
+        let is_moderator = moderation::may_moderate(client_id);
 
-        if DATABASE
+        let comparable = names::comparable(&room_name);
+        let taken = DATABASE
             .rooms
             .iter()
-            .any(|r| r.name.eq_ignore_ascii_case(room_name))
+            .find(|r| names::comparable(&r.name) == comparable)
+            .map(|r| r.name.clone());
+
+        let refusal = if let Err(problem) = names::check_room(&room_name, is_moderator) {
+            let error_code = match problem {
+                NameProblem::TooShort(_) | NameProblem::TooLong(_) => CREATE_NAME_LENGTH,
+                NameProblem::Character(_) | NameProblem::Spacing => CREATE_NAME_CHARACTERS,
+                NameProblem::Banned => CREATE_NAME_BANNED,
+                NameProblem::Reserved
+                | NameProblem::Registered(_)
+                | NameProblem::ReservedPrefix(_) => CREATE_NAME_RESERVED,
+            };
+            Some((error_code, problem.to_string()))
+        } else if let Some(taken) = taken {
+            Some((
+                CREATE_TAKEN,
+                format!("There's already a room called {taken}"),
+            ))
+        } else if config.rooms.max_rooms != 0
+            && DATABASE.rooms.len() >= config.rooms.max_rooms as usize
+        {
+            Some((
+                CREATE_SERVER_FULL,
+                "No more rooms fit on the server, join one instead".to_string(),
+            ))
+        } else if config.rooms.max_per_user != 0
+            && rooms::owned_count(client_id) >= config.rooms.max_per_user as usize
         {
+            Some((
+                CREATE_TOO_MANY,
+                "You own as many rooms as anyone can, leave one before making another".to_string(),
+            ))
+        } else {
+            None
+        };
+
+        if let Some((error_code, message)) = refusal {
             let packet = WormsPacket::create(PacketCode::CreateRoomReply)
                 .with_value_1(0)
-                .with_error_code(1)
+                .with_error_code(error_code)
                 .build()?;
             tx.send(packet).await?;
+
+            tx.send(bot::private_packet(client_id, &message)?).await?;
         } else {
             let new_id = Database::get_next_id();
-            let new_room = Room::new(new_id, room_name, packet.session.as_ref().unwrap().nation);
+            let mut new_room = Room::new(new_id, &room_name, request.nation);
+            new_room.owner = client_id;
+            if request.access == SessionAccess::Protected {
+                // Without a password in the packet the owner sets one once they're in
+                let password = request.password_text();
+                let password_hash = if password.is_empty() {
+                    String::new()
+                } else {
+                    rooms::hash_password(&password).await?
+                };
+                new_room.set_access(request.nation, true, &password_hash);
+            }
 
             // Notify all users of this newly made room, made early since the room will be consumed
             let packet = WormsPacket::create(PacketCode::CreateRoom)
                 .with_value_1(new_id)
                 .with_value_4(0)
                 .with_data("")
-                .with_name(room_name)
+                .with_raw_name(request.name.clone())
                 .with_session(&new_room.session)
                 .build()?;
+            let event = Event::CreateRoom {
+                new_room_id: new_id,
+                new_room: new_room.name.clone(),
+            };
             DATABASE.rooms.insert(new_id, new_room);
+            audit::record(Actor::user(client_id), event);
 
             Server::broadcast_all_except(packet, &client_id).await?;
 
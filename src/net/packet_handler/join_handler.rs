@@ -1,7 +1,13 @@
+use crate::bot;
+use crate::config::Config;
 use crate::database::DATABASE;
+use crate::greetings;
+use crate::history;
 use crate::net::packet_code::PacketCode;
 use crate::net::packet_handler::PacketHandler;
+use crate::net::requests::JoinRequest;
 use crate::net::worms_packet::WormsPacket;
+use crate::rooms;
 use crate::server::Server;
 use eyre::{bail, OptionExt, Result};
 use std::net::SocketAddr;
@@ -11,22 +17,92 @@ use tokio_util::bytes::Bytes;
 
 pub struct JoinHandler;
 
+/// Reply error codes, the game itself only tells apart joining or not.
+const JOIN_FAILED: u32 = 1;
+const JOIN_FULL: u32 = 2;
+const JOIN_LOCKED: u32 = 3;
+const JOIN_KICKED: u32 = 4;
+
+impl JoinHandler {
+    /// Turns a user away from a room, telling them why.
+    async fn refuse(
+        tx: &Sender<Arc<Bytes>>,
+        client_id: u32,
+        error_code: u32,
+        message: &str,
+    ) -> Result<()> {
+        let packet = WormsPacket::create(PacketCode::JoinReply)
+            .with_error_code(error_code)
+            .build()?;
+        tx.send(packet).await?;
+        tx.send(bot::private_packet(client_id, message)?).await?;
+        Ok(())
+    }
+}
+
 impl PacketHandler for JoinHandler {
+    type Request = JoinRequest;
+
     async fn handle_packet(
         tx: Sender<Arc<Bytes>>,
-        packet: Arc<WormsPacket>,
+        request: JoinRequest,
         client_id: u32,
-        _address: SocketAddr,
+        address: SocketAddr,
     ) -> Result<()> {
-        let join_id = packet.value_2.as_ref().map_or(0, |p| *p);
-        let user_room_id_original = DATABASE.users.get(&client_id).map_or(0, |u| u.room_id);
+        let join_id = request.join_id;
+        let (user_room_id_original, client_name) = DATABASE
+            .users
+            .get(&client_id)
+            .map_or((0, String::new()), |u| (u.room_id, u.name.clone()));
 
-        if join_id == 0 || packet.value_10 != Some(client_id) {
+        if request.client_id != client_id {
             bail!("Invalid Data!");
         }
 
         // Check rooms
-        if DATABASE.rooms.contains_key(&join_id) {
+        let room = DATABASE.rooms.get(&join_id).map(|room| {
+            let users = DATABASE
+                .users
+                .iter()
+                .filter(|u| u.id != client_id && u.room_id == join_id)
+                .count();
+            let awaiting_password = room.is_protected() && room.password_hash.is_empty();
+            let refusal = if room.is_kicked(&client_name, address.ip()) {
+                Some((JOIN_KICKED, format!("You were kicked out of {}", room.name)))
+            } else if room.is_full(users, Config::current().rooms.max_users) {
+                Some((JOIN_FULL, format!("{} is full, try again later", room.name)))
+            } else if rooms::is_unlocked(client_id, &room) {
+                None
+            } else if awaiting_password {
+                let message = format!("{} is locked until its owner sets a password", room.name);
+                Some((JOIN_LOCKED, message))
+            } else {
+                let message = format!(
+                    "{0} needs a password, send unlock {0} <password> privately to {1}",
+                    room.name,
+                    Config::current().bot.name
+                );
+                Some((JOIN_LOCKED, message))
+            };
+            (
+                room.name.clone(),
+                room.topic.clone(),
+                awaiting_password,
+                refusal,
+            )
+        });
+        if let Some((room_name, topic, awaiting_password, refusal)) = room {
+            if let Some((error_code, message)) = refusal {
+                return JoinHandler::refuse(&tx, client_id, error_code, &message).await;
+            }
+
+            // A room nobody owns anymore goes to whoever joins it next
+            if let Some(mut room) = DATABASE.rooms.get_mut(&join_id) {
+                if room.owner == 0 && !room.permanent {
+                    room.owner = client_id;
+                }
+            }
+
             DATABASE
                 .users
                 .get_mut(&client_id)
@@ -44,6 +120,20 @@ impl PacketHandler for JoinHandler {
                 .build()?;
             tx.send(packet).await?;
 
+            greetings::send_room_greeting(&tx, client_id, join_id).await?;
+            if !topic.is_empty() {
+                tx.send(bot::private_packet(client_id, &format!("Topic: {topic}"))?)
+                    .await?;
+            }
+            if awaiting_password {
+                // Only the owner gets this far
+                let message = format!(
+                    "Nobody else can join {room_name} until you set a password, send lock <password> privately to {}",
+                    Config::current().bot.name
+                );
+                tx.send(bot::private_packet(client_id, &message)?).await?;
+            }
+            history::replay(&tx, client_id, join_id).await?;
             return Ok(());
         } else if let Some(game) = DATABASE.games.get(&join_id) {
             if game.room_id == user_room_id_original {
@@ -64,7 +154,7 @@ impl PacketHandler for JoinHandler {
 
         // if we got to here then there was no room or game to join
         let packet = WormsPacket::create(PacketCode::JoinReply)
-            .with_error_code(1)
+            .with_error_code(JOIN_FAILED)
             .build()?;
         tx.send(packet).await?;
 
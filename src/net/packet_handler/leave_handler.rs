@@ -1,6 +1,7 @@
 use crate::database::DATABASE;
 use crate::net::packet_code::PacketCode;
 use crate::net::packet_handler::PacketHandler;
+use crate::net::requests::LeaveRequest;
 use crate::net::worms_packet::WormsPacket;
 use crate::server::Server;
 use eyre::{bail, Result};
@@ -13,18 +14,20 @@ use tokio_util::bytes::Bytes;
 pub struct LeaveHandler;
 
 impl PacketHandler for LeaveHandler {
+    type Request = LeaveRequest;
+
     async fn handle_packet(
         tx: Sender<Arc<Bytes>>,
-        packet: Arc<WormsPacket>,
+        request: LeaveRequest,
         client_id: u32,
         _address: SocketAddr,
     ) -> Result<()> {
-        if packet.value_2.is_none() || packet.value_10 != Some(client_id) {
+        if request.client_id != client_id {
             bail!("Invalid Data!");
         }
         let client_room_id = { DATABASE.users.get(&client_id).map_or(0, |u| u.room_id) };
 
-        if packet.value_2 == Some(client_room_id) {
+        if request.room_id == client_room_id {
             let leave_result = Server::leave_room(client_room_id, client_id).await;
             {
                 if leave_result.is_err() {
@@ -1,6 +1,7 @@
 use crate::database::{Database, DATABASE};
 use crate::net::packet_code::PacketCode;
 use crate::net::packet_handler::PacketHandler;
+use crate::net::requests::ListRoomContentsRequest;
 use crate::net::worms_packet::WormsPacket;
 use eyre::{bail, Result};
 use std::net::SocketAddr;
@@ -11,9 +12,11 @@ use tokio_util::bytes::Bytes;
 pub struct ListGamesHandler;
 
 impl PacketHandler for ListGamesHandler {
+    type Request = ListRoomContentsRequest;
+
     async fn handle_packet(
         tx: Sender<Arc<Bytes>>,
-        packet: Arc<WormsPacket>,
+        request: ListRoomContentsRequest,
         client_id: u32,
         _address: SocketAddr,
     ) -> Result<()> {
@@ -22,10 +25,7 @@ impl PacketHandler for ListGamesHandler {
             .get(&client_id)
             .map_or(0, |user| user.room_id);
 
-        if user_room_id < Database::ID_START
-            || packet.value_2 != Some(user_room_id)
-            || packet.value_4 != Some(0)
-        {
+        if user_room_id < Database::ID_START || request.room_id != user_room_id {
             bail!("Invalid Data!");
         }
 
@@ -1,8 +1,9 @@
 use crate::database::DATABASE;
 use crate::net::packet_code::PacketCode;
 use crate::net::packet_handler::PacketHandler;
+use crate::net::requests::ListRoomsRequest;
 use crate::net::worms_packet::WormsPacket;
-use eyre::{bail, Result};
+use eyre::Result;
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
@@ -11,16 +12,14 @@ use tokio_util::bytes::Bytes;
 pub struct ListRoomsHandler;
 
 impl PacketHandler for ListRoomsHandler {
+    type Request = ListRoomsRequest;
+
     async fn handle_packet(
         tx: Sender<Arc<Bytes>>,
-        packet: Arc<WormsPacket>,
+        _request: ListRoomsRequest,
         _client_id: u32,
         _address: SocketAddr,
     ) -> Result<()> {
-        if packet.value_4 != Some(0) {
-            bail!("Invalid Data!");
-        }
-
         for room in &DATABASE.rooms {
             let packet = WormsPacket::create(PacketCode::ListItem)
                 .with_value_1(*room.key())
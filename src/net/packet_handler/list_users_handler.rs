@@ -1,6 +1,8 @@
+use crate::bot;
 use crate::database::{Database, DATABASE};
 use crate::net::packet_code::PacketCode;
 use crate::net::packet_handler::PacketHandler;
+use crate::net::requests::ListRoomContentsRequest;
 use crate::net::worms_packet::WormsPacket;
 use eyre::{bail, Result};
 use std::net::SocketAddr;
@@ -11,9 +13,11 @@ use tokio_util::bytes::Bytes;
 pub struct ListUsersHandler;
 
 impl PacketHandler for ListUsersHandler {
+    type Request = ListRoomContentsRequest;
+
     async fn handle_packet(
         tx: Sender<Arc<Bytes>>,
-        packet: Arc<WormsPacket>,
+        request: ListRoomContentsRequest,
         client_id: u32,
         _address: SocketAddr,
     ) -> Result<()> {
@@ -22,14 +26,16 @@ impl PacketHandler for ListUsersHandler {
             .get(&client_id)
             .map_or(0, |user| user.room_id);
 
-        if user_room_id < Database::ID_START
-            || packet.value_2 != Some(user_room_id)
-            || packet.value_4 != Some(0)
-        {
+        if user_room_id < Database::ID_START || request.room_id != user_room_id {
             bail!("Invalid Data!");
         }
 
-        for user in DATABASE.users.iter().filter(|p| p.room_id == user_room_id) {
+        // The bot is in every room so it can be messaged from anywhere
+        for user in DATABASE
+            .users
+            .iter()
+            .filter(|p| p.room_id == user_room_id || bot::is_bot(p.id))
+        {
             let packet = WormsPacket::create(PacketCode::ListItem)
                 .with_value_1(*user.key())
                 .with_name(&user.name)
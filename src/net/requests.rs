@@ -0,0 +1,255 @@
+use crate::net::nation::Nation;
+use crate::net::session_access::SessionAccess;
+use crate::net::worms_packet::{decode_text, WormsPacket};
+use eyre::{bail, Error, OptionExt, Result};
+use std::borrow::Cow;
+use std::net::IpAddr;
+use tokio_util::bytes::Bytes;
+
+// Typed views of the packets a client sends. They are validated once when parsed from the frame, so
+// handlers don't have to check every `value_N` by hand, and text fields borrow the frame's bytes.
+
+pub struct LoginRequest {
+    /// Windows-1252 encoded user name.
+    pub name: Bytes,
+    pub nation: Nation,
+}
+
+impl LoginRequest {
+    pub fn name_text(&self) -> Cow<'_, str> {
+        decode_text(&self.name)
+    }
+}
+
+impl TryFrom<&WormsPacket> for LoginRequest {
+    type Error = Error;
+
+    fn try_from(packet: &WormsPacket) -> Result<Self> {
+        let name = packet.name.clone().ok_or_eyre("No name specified!")?;
+        let nation = packet
+            .session
+            .as_ref()
+            .map(|s| s.nation)
+            .ok_or_eyre("No nation specified!")?;
+
+        Ok(Self { name, nation })
+    }
+}
+
+pub struct ListRoomsRequest;
+
+impl TryFrom<&WormsPacket> for ListRoomsRequest {
+    type Error = Error;
+
+    fn try_from(packet: &WormsPacket) -> Result<Self> {
+        if packet.value_4 != Some(0) {
+            bail!("Invalid Data!");
+        }
+
+        Ok(Self)
+    }
+}
+
+/// Shared by `ListUsers` and `ListGames`, both of which list the contents of a room.
+pub struct ListRoomContentsRequest {
+    pub room_id: u32,
+}
+
+impl TryFrom<&WormsPacket> for ListRoomContentsRequest {
+    type Error = Error;
+
+    fn try_from(packet: &WormsPacket) -> Result<Self> {
+        match (packet.value_2, packet.value_4) {
+            (Some(room_id), Some(0)) => Ok(Self { room_id }),
+            _ => bail!("Invalid Data!"),
+        }
+    }
+}
+
+pub struct CreateRoomRequest {
+    /// Windows-1252 encoded room name.
+    pub name: Bytes,
+    pub nation: Nation,
+    pub access: SessionAccess,
+    /// Windows-1252 encoded password for a protected room, empty if none was sent.
+    pub password: Bytes,
+}
+
+impl CreateRoomRequest {
+    pub fn name_text(&self) -> Cow<'_, str> {
+        decode_text(&self.name)
+    }
+
+    pub fn password_text(&self) -> Cow<'_, str> {
+        decode_text(&self.password)
+    }
+}
+
+impl TryFrom<&WormsPacket> for CreateRoomRequest {
+    type Error = Error;
+
+    fn try_from(packet: &WormsPacket) -> Result<Self> {
+        match (
+            packet.value_1,
+            packet.value_4,
+            &packet.data,
+            &packet.name,
+            &packet.session,
+        ) {
+            (Some(0), Some(0), Some(data), Some(name), Some(session)) => Ok(Self {
+                name: name.clone(),
+                nation: session.nation,
+                access: session.access,
+                password: data.clone(),
+            }),
+            _ => bail!("Invalid Data!"),
+        }
+    }
+}
+
+pub struct JoinRequest {
+    /// The room or game to join.
+    pub join_id: u32,
+    pub client_id: u32,
+}
+
+impl TryFrom<&WormsPacket> for JoinRequest {
+    type Error = Error;
+
+    fn try_from(packet: &WormsPacket) -> Result<Self> {
+        match (packet.value_2, packet.value_10) {
+            (Some(join_id), Some(client_id)) if join_id != 0 => Ok(Self { join_id, client_id }),
+            _ => bail!("Invalid Data!"),
+        }
+    }
+}
+
+pub struct LeaveRequest {
+    pub room_id: u32,
+    pub client_id: u32,
+}
+
+impl TryFrom<&WormsPacket> for LeaveRequest {
+    type Error = Error;
+
+    fn try_from(packet: &WormsPacket) -> Result<Self> {
+        match (packet.value_2, packet.value_10) {
+            (Some(room_id), Some(client_id)) => Ok(Self { room_id, client_id }),
+            _ => bail!("Invalid Data!"),
+        }
+    }
+}
+
+pub struct CloseRequest {
+    pub id: Option<u32>,
+}
+
+impl TryFrom<&WormsPacket> for CloseRequest {
+    type Error = Error;
+
+    fn try_from(packet: &WormsPacket) -> Result<Self> {
+        Ok(Self {
+            id: packet.value_10,
+        })
+    }
+}
+
+pub struct CreateGameRequest {
+    pub room_id: u32,
+    /// The address the host claims to be reachable at, `None` if it isn't a valid ip.
+    pub ip: Option<IpAddr>,
+    pub access: SessionAccess,
+}
+
+impl TryFrom<&WormsPacket> for CreateGameRequest {
+    type Error = Error;
+
+    fn try_from(packet: &WormsPacket) -> Result<Self> {
+        match (
+            packet.value_1,
+            packet.value_2,
+            packet.value_4,
+            &packet.data,
+            &packet.name,
+            &packet.session,
+        ) {
+            (Some(0), Some(room_id), Some(0x800), Some(data), Some(_), Some(session)) => Ok(Self {
+                room_id,
+                ip: std::str::from_utf8(data)
+                    .ok()
+                    .and_then(|ip| ip.parse().ok()),
+                access: session.access,
+            }),
+            _ => bail!("Invalid Data!"),
+        }
+    }
+}
+
+pub struct ChatRoomRequest {
+    pub from_id: u32,
+    /// The room for `GRP:` messages, the user for `PRV:` messages.
+    pub target_id: u32,
+    /// Windows-1252 encoded message including its `GRP:[ name ]  ` style prefix.
+    pub message: Bytes,
+}
+
+impl TryFrom<&WormsPacket> for ChatRoomRequest {
+    type Error = Error;
+
+    fn try_from(packet: &WormsPacket) -> Result<Self> {
+        let from_id = packet.value_0.ok_or_eyre("From user invalid!")?;
+        let message = packet
+            .data
+            .clone()
+            .ok_or_eyre("No message included in chat packet!")?;
+        let target_id = packet
+            .value_3
+            .ok_or_eyre("No target id included in chat packet!")?;
+
+        Ok(Self {
+            from_id,
+            target_id,
+            message,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ChatKind {
+    /// `GRP:[ name ]  message`, sent to everyone in a room.
+    Group,
+    /// `PRV:[ name ]  message`, sent to a single user.
+    Private,
+}
+
+impl ChatRoomRequest {
+    /// Splits the message into its kind and body, if it carries the `GRP:[ name ]  ` or
+    /// `PRV:[ name ]  ` prefix for `sender_name` (Windows-1252 encoded).
+    pub fn split_message(&self, sender_name: &[u8]) -> Option<(ChatKind, Bytes)> {
+        let kind = match self.message.get(..4)? {
+            b"GRP:" => ChatKind::Group,
+            b"PRV:" => ChatKind::Private,
+            _ => return None,
+        };
+
+        let rest = self.message[4..].strip_prefix(b"[ ")?;
+        let rest = rest.strip_prefix(sender_name)?;
+        let rest = rest.strip_prefix(b" ]  ")?;
+
+        let body_start = self.message.len() - rest.len();
+        Some((kind, self.message.slice(body_start..)))
+    }
+}
+
+pub struct ConnectGameRequest {
+    pub game_id: u32,
+}
+
+impl TryFrom<&WormsPacket> for ConnectGameRequest {
+    type Error = Error;
+
+    fn try_from(packet: &WormsPacket) -> Result<Self> {
+        let game_id = packet.value_0.ok_or_eyre("no game id included!")?;
+        Ok(Self { game_id })
+    }
+}
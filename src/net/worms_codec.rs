@@ -1,8 +1,6 @@
 use crate::net::session_info::SessionInfo;
-use crate::net::worms_packet::{PacketFlags, WormsPacket};
-use encoding_rs::WINDOWS_1252;
+use crate::net::worms_packet::{PacketFlags, WormsPacket, MAX_DATA_LENGTH, MAX_NAME_LENGTH};
 use eyre::{bail, eyre, Error, Result};
-use log::error;
 use std::sync::Arc;
 use tokio_util::bytes::{Buf, Bytes, BytesMut};
 use tokio_util::codec::{Decoder, Encoder};
@@ -15,8 +13,9 @@ pub const EMPTY_BUFFER: [u8; 35] = [0u8; 35];
 pub const CRC_FIRST: u32 = 0x1717_1717;
 pub const CRC_SECOND: u32 = u32::from_be_bytes([0x02, 0x01, 0x01, 0x01]);
 
-const MAX_DATA_LENGTH: usize = 0x200;
 const ZEROES_EXPECTED: usize = 35;
+const HEADER_LENGTH: usize = 8;
+const SESSION_LENGTH: usize = 50;
 
 impl Encoder<Arc<Bytes>> for WormCodec {
     type Error = eyre::Error;
@@ -26,147 +25,155 @@ impl Encoder<Arc<Bytes>> for WormCodec {
     }
 }
 
+impl WormCodec {
+    /// Peeks at the start of `src` and works out how many bytes the whole frame spans, without
+    /// consuming anything. Returns `Ok(None)` while there aren't enough bytes to tell yet.
+    pub fn frame_length(src: &[u8]) -> Result<Option<usize>> {
+        if src.len() < HEADER_LENGTH {
+            return Ok(None);
+        }
+
+        let code = u32::from_le_bytes([src[0], src[1], src[2], src[3]]);
+        if let Err(e) = PacketCode::try_from(code) {
+            bail!("Invalid Packet Header! {}", e);
+        }
+        let flags =
+            PacketFlags::from_bits_truncate(u32::from_le_bytes([src[4], src[5], src[6], src[7]]));
+
+        let mut length = HEADER_LENGTH;
+
+        for value_flag in [
+            PacketFlags::VALUE0,
+            PacketFlags::VALUE1,
+            PacketFlags::VALUE2,
+            PacketFlags::VALUE3,
+            PacketFlags::VALUE4,
+            PacketFlags::VALUE10,
+        ] {
+            if flags.contains(value_flag) {
+                length += 4;
+            }
+        }
+
+        if flags.contains(PacketFlags::DATALENGTH) {
+            if src.len() < length + 4 {
+                return Ok(None);
+            }
+            let data_length = u32::from_le_bytes([
+                src[length],
+                src[length + 1],
+                src[length + 2],
+                src[length + 3],
+            ]) as usize;
+            if data_length > MAX_DATA_LENGTH {
+                bail!("Data Length too long! {}", data_length);
+            }
+            length += 4;
+
+            if flags.contains(PacketFlags::DATA) {
+                length += data_length;
+            }
+        }
+
+        if flags.contains(PacketFlags::ERRORCODE) {
+            length += 4;
+        }
+
+        if flags.contains(PacketFlags::NAME) {
+            length += MAX_NAME_LENGTH;
+        }
+
+        if flags.contains(PacketFlags::SESSION) {
+            length += SESSION_LENGTH;
+        }
+
+        Ok(Some(length))
+    }
+}
+
 impl Decoder for WormCodec {
-    type Item = Arc<WormsPacket>;
+    type Item = WormsPacket;
     type Error = eyre::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         use std::result::Result::Ok;
 
-        if src.len() < 8 {
-            // tell the frame we need more
+        // Nothing gets consumed until the whole frame has arrived, so a frame split across
+        // several reads is picked up again on the next call.
+        let Some(frame_length) = WormCodec::frame_length(src)? else {
+            return Ok(None);
+        };
+        if src.len() < frame_length {
+            src.reserve(frame_length - src.len());
             return Ok(None);
         }
 
+        let mut src = src.split_to(frame_length).freeze();
         let mut packet = WormsPacket::default();
 
-        match PacketCode::try_from(src.get_u32_le()) {
-            Ok(code) => {
-                packet.header_code = code;
-            }
-            Err(e) => {
-                bail!("Invalid Packet Header! {}", e);
-            }
-        }
+        packet.header_code = PacketCode::try_from(src.get_u32_le())?;
         packet.flags = PacketFlags::from_bits_truncate(src.get_u32_le());
 
         if packet.flags.contains(PacketFlags::VALUE0) {
-            if src.remaining() < 4 {
-                return Ok(None);
-            }
-            packet.value_0 = Option::from(src.get_u32_le());
+            packet.value_0 = Some(src.get_u32_le());
         }
 
         if packet.flags.contains(PacketFlags::VALUE1) {
-            if src.remaining() < 4 {
-                return Ok(None);
-            }
-            packet.value_1 = Option::from(src.get_u32_le());
+            packet.value_1 = Some(src.get_u32_le());
         }
 
         if packet.flags.contains(PacketFlags::VALUE2) {
-            if src.remaining() < 4 {
-                return Ok(None);
-            }
-            packet.value_2 = Option::from(src.get_u32_le());
+            packet.value_2 = Some(src.get_u32_le());
         }
 
         if packet.flags.contains(PacketFlags::VALUE3) {
-            if src.remaining() < 4 {
-                return Ok(None);
-            }
-            packet.value_3 = Option::from(src.get_u32_le());
+            packet.value_3 = Some(src.get_u32_le());
         }
 
         if packet.flags.contains(PacketFlags::VALUE4) {
-            if src.remaining() < 4 {
-                return Ok(None);
-            }
-            packet.value_4 = Option::from(src.get_u32_le());
+            packet.value_4 = Some(src.get_u32_le());
         }
 
         if packet.flags.contains(PacketFlags::VALUE10) {
-            if src.remaining() < 4 {
-                return Ok(None);
-            }
-            packet.value_10 = Option::from(src.get_u32_le());
+            packet.value_10 = Some(src.get_u32_le());
         }
 
         if packet.flags.contains(PacketFlags::DATALENGTH) {
-            if src.remaining() < 4 {
-                return Ok(None);
-            }
             let length = src.get_u32_le() as usize;
-            if length > MAX_DATA_LENGTH {
-                bail!("Data Length too long! {}", length);
-            }
 
             if packet.flags.contains(PacketFlags::DATA) {
-                if src.remaining() < length {
-                    return Ok(None);
-                }
-
-                let data_bytes = src.split_to(length);
-                let filtered_bytes: Vec<u8> = data_bytes
-                    .iter()
-                    .copied()
-                    .filter(|&byte| byte != b'\0')
-                    .collect();
-                let (decoded, _, had_error) = WINDOWS_1252.decode(&filtered_bytes);
-                if had_error {
-                    error!("Packet Data: Windows-1252 decode error");
-                    bail!("Windows-1252 decode error");
-                }
-
-                packet.data = Some(decoded.to_string());
+                packet.data = Some(take_text(&mut src, length));
             }
         }
 
         if packet.flags.contains(PacketFlags::ERRORCODE) {
-            if src.remaining() < 4 {
-                return Ok(None);
-            }
             packet.error_code = Some(src.get_u32_le());
         }
 
         if packet.flags.contains(PacketFlags::NAME) {
-            if src.remaining() < super::worms_packet::MAX_NAME_LENGTH {
-                return Ok(None);
-            }
-
-            decode_name(src, &mut packet)?;
+            packet.name = Some(take_text(&mut src, MAX_NAME_LENGTH));
         }
 
         if packet.flags.contains(PacketFlags::SESSION) {
-            packet.session = SessionInfo::decode_session(src)?;
+            packet.session = Some(SessionInfo::decode_session(&mut src)?);
         }
 
-        Ok(Some(Arc::new(packet)))
+        Ok(Some(packet))
     }
 }
 
-fn decode_name(src: &mut BytesMut, packet: &mut WormsPacket) -> Result<(), Error> {
-    let data_bytes = src.split_to(super::worms_packet::MAX_NAME_LENGTH);
-    let filtered_bytes: Vec<u8> = data_bytes
-        .iter()
-        .copied()
-        .filter(|&byte| byte != b'\0')
-        .collect();
-    let (decoded, _, had_error) = WINDOWS_1252.decode(&filtered_bytes);
-    if had_error {
-        log::error!("Packet Name: Windows-1252 decode error");
-        bail!("Windows-1252 decode error");
+/// Splits off a NUL terminated or padded text field, keeping everything up to the first NUL.
+fn take_text(src: &mut Bytes, length: usize) -> Bytes {
+    let mut text = src.split_to(length);
+    if let Some(end) = text.iter().position(|&byte| byte == b'\0') {
+        text.truncate(end);
     }
-    packet.name = Some(decoded.to_string());
-
-    Ok(())
+    text
 }
 
 impl SessionInfo {
-    pub fn decode_session(src: &mut BytesMut) -> Result<Option<Arc<Self>>, Error> {
-        if src.remaining() < 50 {
-            return Ok(None);
-        }
+    /// Decodes the fixed size session block. The caller has to make sure all 50 bytes are there.
+    pub fn decode_session(src: &mut Bytes) -> Result<Arc<Self>, Error> {
         let mut session_info = SessionInfo::default();
 
         // endianness doesn't matter on first. same no matter which order
@@ -208,6 +215,186 @@ impl SessionInfo {
 
         src.advance(ZEROES_EXPECTED);
 
-        Ok(Some(Arc::new(session_info)))
+        Ok(Arc::new(session_info))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::nation::Nation;
+    use crate::net::session_access::SessionAccess;
+    use crate::net::session_type::SessionType;
+
+    fn valid_packets() -> Vec<WormsPacket> {
+        let user_session = SessionInfo::new(Nation::UK, SessionType::User);
+        let room_session = SessionInfo::new(Nation::DE, SessionType::Room);
+        let game_session = SessionInfo::new_with_access(
+            Nation::Team17,
+            SessionType::Game,
+            SessionAccess::Protected,
+        );
+
+        vec![
+            WormsPacket::create(PacketCode::ListRooms).with_value_4(0),
+            WormsPacket::create(PacketCode::ListItem)
+                .with_value_1(0x1001)
+                .with_data("")
+                .with_name("Room")
+                .with_session(&room_session),
+            WormsPacket::create(PacketCode::ListItem)
+                .with_value_1(0x1002)
+                .with_data("127.0.0.1")
+                .with_name("Host")
+                .with_session(&game_session),
+            WormsPacket::create(PacketCode::ListEnd),
+            WormsPacket::create(PacketCode::ListUsers)
+                .with_value_2(0x1001)
+                .with_value_4(0),
+            WormsPacket::create(PacketCode::ListGames)
+                .with_value_2(0x1001)
+                .with_value_4(0),
+            WormsPacket::create(PacketCode::Login)
+                .with_value_1(0)
+                .with_value_4(0)
+                .with_name("Worm")
+                .with_session(&user_session),
+            WormsPacket::create(PacketCode::LoginReply)
+                .with_value_1(0x1000)
+                .with_error_code(0),
+            WormsPacket::create(PacketCode::CreateRoom)
+                .with_value_1(0)
+                .with_value_4(0)
+                .with_data("x")
+                .with_name("A new room")
+                .with_session(&room_session),
+            WormsPacket::create(PacketCode::CreateRoomReply)
+                .with_value_1(0x1001)
+                .with_error_code(0),
+            WormsPacket::create(PacketCode::Join)
+                .with_value_2(0x1001)
+                .with_value_10(0x1000),
+            WormsPacket::create(PacketCode::JoinReply).with_error_code(0),
+            WormsPacket::create(PacketCode::Leave)
+                .with_value_2(0x1001)
+                .with_value_10(0x1000),
+            WormsPacket::create(PacketCode::LeaveReply).with_error_code(1),
+            WormsPacket::create(PacketCode::DisconnectUser).with_value_10(0x1000),
+            WormsPacket::create(PacketCode::Close).with_value_10(0x1002),
+            WormsPacket::create(PacketCode::CloseReply).with_error_code(0),
+            WormsPacket::create(PacketCode::CreateGame)
+                .with_value_1(0)
+                .with_value_2(0x1001)
+                .with_value_4(0x800)
+                .with_data("192.168.0.1")
+                .with_name("Worm")
+                .with_session(&game_session),
+            WormsPacket::create(PacketCode::CreateGameReply)
+                .with_value_1(0x1002)
+                .with_error_code(0),
+            WormsPacket::create(PacketCode::ChatRoom)
+                .with_value_0(0x1000)
+                .with_value_3(0x1001)
+                .with_data("GRP:[ Worm ]  Hällo wörld!"),
+            WormsPacket::create(PacketCode::ChatRoomReply).with_error_code(0),
+            WormsPacket::create(PacketCode::ConnectGame).with_value_0(0x1002),
+            WormsPacket::create(PacketCode::ConnectGameReply)
+                .with_data("192.168.0.1")
+                .with_error_code(0),
+        ]
+    }
+
+    fn encode(packet: WormsPacket) -> Bytes {
+        Bytes::clone(&packet.build().expect("valid packet should build"))
+    }
+
+    #[test]
+    fn decodes_every_packet_fed_one_byte_at_a_time() {
+        let expected = valid_packets();
+        let stream: Vec<u8> = valid_packets()
+            .into_iter()
+            .flat_map(|p| encode(p).to_vec())
+            .collect();
+
+        let mut codec = WormCodec;
+        let mut buffer = BytesMut::new();
+        let mut decoded = Vec::new();
+
+        for byte in stream {
+            buffer.extend_from_slice(&[byte]);
+            if let Some(packet) = codec.decode(&mut buffer).expect("valid stream") {
+                assert!(
+                    buffer.is_empty(),
+                    "frame should consume exactly its own bytes"
+                );
+                decoded.push(packet);
+            }
+        }
+
+        assert!(buffer.is_empty());
+        assert_eq!(decoded.len(), expected.len());
+        for (decoded, expected) in decoded.iter().zip(&expected) {
+            assert_eq!(*decoded, *expected);
+        }
+    }
+
+    #[test]
+    fn partial_frame_is_not_consumed() {
+        for packet in valid_packets() {
+            let bytes = encode(packet);
+
+            for cut in 0..bytes.len() {
+                let mut buffer = BytesMut::from(&bytes[..cut]);
+                let result = WormCodec
+                    .decode(&mut buffer)
+                    .expect("prefix of a valid frame");
+
+                assert!(result.is_none());
+                assert_eq!(&buffer[..], &bytes[..cut]);
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_back_to_back_frames_in_one_read() {
+        let expected = valid_packets();
+        let mut buffer: BytesMut = valid_packets()
+            .into_iter()
+            .flat_map(|p| encode(p).to_vec())
+            .collect::<Vec<u8>>()
+            .as_slice()
+            .into();
+
+        for expected in &expected {
+            let packet = WormCodec
+                .decode(&mut buffer)
+                .expect("valid stream")
+                .expect("complete frame");
+            assert_eq!(packet, *expected);
+        }
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_code_before_frame_completes() {
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(&1234u32.to_le_bytes());
+        buffer.extend_from_slice(&PacketFlags::NAME.bits().to_le_bytes());
+
+        assert!(WormCodec.decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn rejects_oversized_data_length_before_frame_completes() {
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(&u32::from(PacketCode::ChatRoom).to_le_bytes());
+        buffer.extend_from_slice(
+            &(PacketFlags::DATALENGTH | PacketFlags::DATA)
+                .bits()
+                .to_le_bytes(),
+        );
+        buffer.extend_from_slice(&(MAX_DATA_LENGTH as u32 + 1).to_le_bytes());
+
+        assert!(WormCodec.decode(&mut buffer).is_err());
     }
 }
@@ -3,13 +3,17 @@ use crate::net::session_info::SessionInfo;
 use encoding_rs::WINDOWS_1252;
 use eyre::{bail, Result};
 use log::error;
-use std::char::REPLACEMENT_CHARACTER;
+use std::borrow::Cow;
 use std::fmt::{Debug, Formatter};
 use std::sync::Arc;
 use tokio_util::bytes::{BufMut, Bytes, BytesMut};
 
-pub(crate) const MAX_NAME_LENGTH: usize = 20;
+pub const MAX_NAME_LENGTH: usize = 20;
+pub const MAX_DATA_LENGTH: usize = 0x200;
 
+/// A single frame of the lobby protocol. Text fields are kept as the raw Windows-1252 bytes, so
+/// decoded frames only hold slices of the receive buffer and can be forwarded without re-encoding.
+#[derive(PartialEq)]
 pub struct WormsPacket {
     pub header_code: PacketCode,
     pub(super) flags: PacketFlags,
@@ -19,10 +23,13 @@ pub struct WormsPacket {
     pub value_3: Option<u32>,
     pub value_4: Option<u32>,
     pub value_10: Option<u32>,
-    pub data: Option<String>,
+    /// Windows-1252 encoded, without the NUL terminator.
+    pub data: Option<Bytes>,
     pub error_code: Option<u32>,
-    pub name: Option<String>,
+    /// Windows-1252 encoded, without the NUL padding.
+    pub name: Option<Bytes>,
     pub session: Option<Arc<SessionInfo>>,
+    encoding_failed: bool,
 }
 
 bitflags::bitflags! {
@@ -57,10 +64,22 @@ impl Default for WormsPacket {
             error_code: None,
             name: None,
             session: None,
+            encoding_failed: false,
         }
     }
 }
 
+/// Decodes Windows-1252 bytes, only allocating when there are non-ASCII characters.
+pub fn decode_text(bytes: &[u8]) -> Cow<'_, str> {
+    WINDOWS_1252.decode_without_bom_handling(bytes).0
+}
+
+/// Encodes `text` as Windows-1252, returning `None` if a character can't be represented.
+pub fn encode_text(text: &str) -> Option<Cow<'_, [u8]>> {
+    let (encoded, _, had_error) = WINDOWS_1252.encode(text);
+    (!had_error).then_some(encoded)
+}
+
 impl WormsPacket {
     pub fn create(header: PacketCode) -> Self {
         WormsPacket {
@@ -101,9 +120,20 @@ impl WormsPacket {
         self.flags.set(PacketFlags::VALUE10, true);
         self
     }
-    pub fn with_data(mut self, value: &str) -> Self {
+    pub fn with_data(self, value: &str) -> Self {
+        match encode_text(value) {
+            Some(encoded) => self.with_raw_data(Bytes::copy_from_slice(&encoded)),
+            None => {
+                error!("Packet Data: Windows-1252 encode error");
+                self.with_encoding_failed()
+            }
+        }
+    }
+
+    /// Sets already Windows-1252 encoded data, e.g. when forwarding a received message as is.
+    pub fn with_raw_data(mut self, value: Bytes) -> Self {
         if !value.is_empty() {
-            self.data = Some(value.to_string());
+            self.data = Some(value);
             // Length then Data
             self.flags.set(PacketFlags::DATALENGTH, true);
             self.flags.set(PacketFlags::DATA, true);
@@ -118,21 +148,20 @@ impl WormsPacket {
         self
     }
 
-    pub fn with_name(mut self, value: &str) -> Self {
-        if value.len() <= MAX_NAME_LENGTH {
-            self.name = Some(value.to_string());
-        } else {
-            // Truncate the string to MAX_NAME_LENGTH
-            let truncated_bytes = &value.as_bytes()[..MAX_NAME_LENGTH];
-
-            self.name = Some(
-                String::from_utf8_lossy(truncated_bytes)
-                    .to_string()
-                    .chars()
-                    .filter(|c| *c != REPLACEMENT_CHARACTER)
-                    .collect(),
-            );
+    pub fn with_name(self, value: &str) -> Self {
+        match encode_text(value) {
+            Some(encoded) => self.with_raw_name(Bytes::copy_from_slice(&encoded)),
+            None => {
+                error!("Packet Name: Windows-1252 encode error");
+                self.with_encoding_failed()
+            }
         }
+    }
+
+    /// Sets an already Windows-1252 encoded name, truncated to `MAX_NAME_LENGTH`.
+    pub fn with_raw_name(mut self, mut value: Bytes) -> Self {
+        value.truncate(MAX_NAME_LENGTH);
+        self.name = Some(value);
         self.flags.set(PacketFlags::NAME, true);
         self
     }
@@ -143,7 +172,26 @@ impl WormsPacket {
         self
     }
 
+    fn with_encoding_failed(mut self) -> Self {
+        self.encoding_failed = true;
+        self
+    }
+
+    /// The data decoded from Windows-1252.
+    pub fn data_text(&self) -> Option<Cow<'_, str>> {
+        self.data.as_deref().map(decode_text)
+    }
+
+    /// The name decoded from Windows-1252.
+    pub fn name_text(&self) -> Option<Cow<'_, str>> {
+        self.name.as_deref().map(decode_text)
+    }
+
     pub fn build(self) -> Result<Arc<Bytes>> {
+        if self.encoding_failed {
+            bail!("Windows-1252 encode error");
+        }
+
         let mut dst = BytesMut::new();
         dst.put_u32_le(self.header_code.into());
         dst.put_u32_le(self.flags.bits());
@@ -167,14 +215,14 @@ impl WormsPacket {
             dst.put_u32_le(value);
         }
         if let Some(value) = &self.data {
-            let (encoded, _, had_error) = WINDOWS_1252.encode(value);
-            if had_error {
-                error!("Packet Data: Windows-1252 encode error");
-                bail!("Windows-1252 encode error");
+            // Account for the trailing NUL
+            let length = value.len() + 1;
+            if length > MAX_DATA_LENGTH {
+                bail!("Data Length too long! {}", length);
             }
 
-            dst.put_u32_le(u32::try_from(encoded.len() + 1)?);
-            dst.extend_from_slice(&encoded);
+            dst.put_u32_le(u32::try_from(length)?);
+            dst.extend_from_slice(value);
             dst.put_u8(b'\0');
         }
         if let Some(value) = self.error_code {
@@ -182,15 +230,8 @@ impl WormsPacket {
         }
         if let Some(value) = &self.name {
             let mut buffer = [b'\0'; MAX_NAME_LENGTH];
-            let (encoded, _, had_error) = WINDOWS_1252.encode(value);
-
-            if had_error {
-                error!("Packet Name: Windows-1252 encode error");
-                bail!("Windows-1252 encode error");
-            }
-
-            let length = encoded.len().min(MAX_NAME_LENGTH);
-            buffer[..length].copy_from_slice(&encoded[..length]);
+            let length = value.len().min(MAX_NAME_LENGTH);
+            buffer[..length].copy_from_slice(&value[..length]);
 
             dst.extend_from_slice(&buffer);
         }
@@ -237,13 +278,13 @@ impl Debug for WormsPacket {
         if let Some(value) = self.value_10 {
             write!(f, "Value 10: {value} ")?;
         }
-        if let Some(value) = &self.data {
+        if let Some(value) = self.data_text() {
             write!(f, "Data: {value} ")?;
         }
         if let Some(value) = self.error_code {
             write!(f, "Error Code: {value} ")?;
         }
-        if let Some(value) = &self.name {
+        if let Some(value) = self.name_text() {
             write!(f, "Name: {value} ")?;
         }
         if let Some(value) = &self.session {
@@ -253,3 +294,357 @@ impl Debug for WormsPacket {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::nation::Nation;
+    use crate::net::session_access::SessionAccess;
+    use crate::net::session_type::SessionType;
+    use crate::net::worms_codec::WormCodec;
+    use proptest::prelude::*;
+    use tokio_util::codec::Decoder;
+
+    const PACKET_CODES: [PacketCode; 22] = [
+        PacketCode::ListRooms,
+        PacketCode::ListItem,
+        PacketCode::ListEnd,
+        PacketCode::ListUsers,
+        PacketCode::ListGames,
+        PacketCode::Login,
+        PacketCode::LoginReply,
+        PacketCode::CreateRoom,
+        PacketCode::CreateRoomReply,
+        PacketCode::Join,
+        PacketCode::JoinReply,
+        PacketCode::Leave,
+        PacketCode::LeaveReply,
+        PacketCode::DisconnectUser,
+        PacketCode::Close,
+        PacketCode::CloseReply,
+        PacketCode::CreateGame,
+        PacketCode::CreateGameReply,
+        PacketCode::ChatRoom,
+        PacketCode::ChatRoomReply,
+        PacketCode::ConnectGame,
+        PacketCode::ConnectGameReply,
+    ];
+
+    /// Any character Windows-1252 can represent, except NUL which is used as padding.
+    fn windows_1252_char() -> impl Strategy<Value = char> {
+        (1u8..=255).prop_map(|byte| {
+            let bytes = [byte];
+            let (decoded, _) = WINDOWS_1252.decode_without_bom_handling(&bytes);
+            decoded.chars().next().unwrap()
+        })
+    }
+
+    fn windows_1252_string(max_length: usize) -> impl Strategy<Value = String> {
+        prop::collection::vec(windows_1252_char(), 0..=max_length)
+            .prop_map(|chars| chars.into_iter().collect())
+    }
+
+    fn session_info() -> impl Strategy<Value = Arc<SessionInfo>> {
+        (
+            0u8..=51,
+            prop::sample::select(vec![
+                SessionType::Room,
+                SessionType::Game,
+                SessionType::User,
+            ]),
+            prop::sample::select(vec![SessionAccess::Public, SessionAccess::Protected]),
+        )
+            .prop_map(|(nation, session_type, access)| {
+                SessionInfo::new_with_access(Nation::from(nation), session_type, access)
+            })
+    }
+
+    prop_compose! {
+        fn packet()(
+            code in prop::sample::select(PACKET_CODES.to_vec()),
+            values in prop::array::uniform6(prop::option::of(any::<u32>())),
+            // The trailing NUL counts towards the data length
+            data in prop::option::of(windows_1252_string(MAX_DATA_LENGTH - 1)),
+            error_code in prop::option::of(any::<u32>()),
+            name in prop::option::of(windows_1252_string(MAX_NAME_LENGTH)),
+            session in prop::option::of(session_info()),
+        ) -> WormsPacket {
+            let mut packet = WormsPacket::create(code);
+            let [value_0, value_1, value_2, value_3, value_4, value_10] = values;
+
+            if let Some(value) = value_0 {
+                packet = packet.with_value_0(value);
+            }
+            if let Some(value) = value_1 {
+                packet = packet.with_value_1(value);
+            }
+            if let Some(value) = value_2 {
+                packet = packet.with_value_2(value);
+            }
+            if let Some(value) = value_3 {
+                packet = packet.with_value_3(value);
+            }
+            if let Some(value) = value_4 {
+                packet = packet.with_value_4(value);
+            }
+            if let Some(value) = value_10 {
+                packet = packet.with_value_10(value);
+            }
+            if let Some(value) = data {
+                packet = packet.with_data(&value);
+            }
+            if let Some(value) = error_code {
+                packet = packet.with_error_code(value);
+            }
+            if let Some(value) = name {
+                packet = packet.with_name(&value);
+            }
+            if let Some(value) = session {
+                packet = packet.with_session(&value);
+            }
+
+            packet
+        }
+    }
+
+    /// Builds a copy of `packet` so the original can be compared against the decoded result.
+    fn duplicate(packet: &WormsPacket) -> WormsPacket {
+        WormsPacket {
+            header_code: packet.header_code,
+            flags: packet.flags,
+            value_0: packet.value_0,
+            value_1: packet.value_1,
+            value_2: packet.value_2,
+            value_3: packet.value_3,
+            value_4: packet.value_4,
+            value_10: packet.value_10,
+            data: packet.data.clone(),
+            error_code: packet.error_code,
+            name: packet.name.clone(),
+            session: packet.session.clone(),
+            encoding_failed: packet.encoding_failed,
+        }
+    }
+
+    fn decode_all(bytes: &[u8]) -> Result<Vec<WormsPacket>> {
+        let mut buffer = BytesMut::from(bytes);
+        let mut packets = Vec::new();
+        while let Some(packet) = WormCodec.decode(&mut buffer)? {
+            packets.push(packet);
+        }
+        Ok(packets)
+    }
+
+    proptest! {
+        #[test]
+        fn decode_inverts_build(packet in packet()) {
+            let bytes = duplicate(&packet).build().unwrap();
+            let decoded = decode_all(&bytes).unwrap();
+
+            prop_assert_eq!(decoded.len(), 1);
+            prop_assert_eq!(&decoded[0], &packet);
+        }
+
+        #[test]
+        fn decode_inverts_build_for_streams(packets in prop::collection::vec(packet(), 1..8)) {
+            let bytes: Vec<u8> = packets
+                .iter()
+                .flat_map(|p| duplicate(p).build().unwrap().to_vec())
+                .collect();
+            let decoded = decode_all(&bytes).unwrap();
+
+            prop_assert_eq!(decoded.len(), packets.len());
+            for (decoded, packet) in decoded.iter().zip(&packets) {
+                prop_assert_eq!(decoded, packet);
+            }
+        }
+
+        #[test]
+        fn arbitrary_bytes_do_not_panic(bytes in prop::collection::vec(any::<u8>(), 0..1024)) {
+            let _ = decode_all(&bytes);
+        }
+
+        #[test]
+        fn arbitrary_frames_do_not_panic(
+            code in prop::sample::select(PACKET_CODES.to_vec()),
+            flags in any::<u32>(),
+            body in prop::collection::vec(any::<u8>(), 0..1024),
+        ) {
+            let mut bytes = Vec::with_capacity(body.len() + 8);
+            bytes.extend_from_slice(&u32::from(code).to_le_bytes());
+            bytes.extend_from_slice(&flags.to_le_bytes());
+            bytes.extend_from_slice(&body);
+
+            let _ = decode_all(&bytes);
+        }
+
+        #[test]
+        fn corrupted_session_is_an_error(
+            session in session_info(),
+            offset in prop::sample::select(vec![0usize, 4, 11, 12, 13, 14, 15, 49]),
+            corruption in 1u8..=255,
+        ) {
+            let bytes = WormsPacket::create(PacketCode::ListItem)
+                .with_session(&session)
+                .build()
+                .unwrap();
+            let mut bytes = bytes.to_vec();
+            let offset = 8 + offset;
+            bytes[offset] = bytes[offset].wrapping_add(corruption);
+
+            // Session type and access only fail when the result is out of range
+            let session_type_valid =
+                offset == 8 + 11 && SessionType::try_from(bytes[offset]).is_ok();
+            let access_valid = offset == 8 + 12 && SessionAccess::try_from(bytes[offset]).is_ok();
+            prop_assume!(!session_type_valid && !access_valid);
+
+            prop_assert!(decode_all(&bytes).is_err());
+        }
+    }
+
+    #[test]
+    fn every_flag_combination_decodes_to_its_own_length() {
+        for bits in 0..=PacketFlags::all().bits() {
+            let flags = PacketFlags::from_bits_truncate(bits);
+            if flags.bits() != bits {
+                continue;
+            }
+
+            let mut bytes = Vec::new();
+            bytes.extend_from_slice(&u32::from(PacketCode::ChatRoom).to_le_bytes());
+            bytes.extend_from_slice(&flags.bits().to_le_bytes());
+            for (flag, value) in [
+                (PacketFlags::VALUE0, 10u32),
+                (PacketFlags::VALUE1, 11),
+                (PacketFlags::VALUE2, 12),
+                (PacketFlags::VALUE3, 13),
+                (PacketFlags::VALUE4, 14),
+                (PacketFlags::VALUE10, 20),
+            ] {
+                if flags.contains(flag) {
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            if flags.contains(PacketFlags::DATALENGTH) {
+                bytes.extend_from_slice(&4u32.to_le_bytes());
+                if flags.contains(PacketFlags::DATA) {
+                    bytes.extend_from_slice(b"abc\0");
+                }
+            }
+            if flags.contains(PacketFlags::ERRORCODE) {
+                bytes.extend_from_slice(&7u32.to_le_bytes());
+            }
+            if flags.contains(PacketFlags::NAME) {
+                let mut name = [0u8; MAX_NAME_LENGTH];
+                name[..4].copy_from_slice(b"Worm");
+                bytes.extend_from_slice(&name);
+            }
+            if flags.contains(PacketFlags::SESSION) {
+                let session = SessionInfo::new(Nation::FI, SessionType::User);
+                let session_bytes = WormsPacket::create(PacketCode::ListItem)
+                    .with_session(&session)
+                    .build()
+                    .unwrap();
+                bytes.extend_from_slice(&session_bytes[8..]);
+            }
+
+            let mut buffer = BytesMut::from(&bytes[..]);
+            let packet = WormCodec
+                .decode(&mut buffer)
+                .unwrap_or_else(|e| panic!("flags {flags:?} failed: {e}"))
+                .unwrap_or_else(|| panic!("flags {flags:?} incomplete"));
+
+            assert!(buffer.is_empty(), "flags {flags:?} left bytes behind");
+            assert_eq!(packet.flags, flags);
+            assert_eq!(
+                packet.value_0.is_some(),
+                flags.contains(PacketFlags::VALUE0)
+            );
+            assert_eq!(
+                packet.value_10,
+                flags.contains(PacketFlags::VALUE10).then_some(20)
+            );
+            assert_eq!(
+                packet.data.as_deref(),
+                flags
+                    .contains(PacketFlags::DATALENGTH | PacketFlags::DATA)
+                    .then_some(&b"abc"[..])
+            );
+            assert_eq!(
+                packet.error_code,
+                flags.contains(PacketFlags::ERRORCODE).then_some(7)
+            );
+            assert_eq!(
+                packet.name.as_deref(),
+                flags.contains(PacketFlags::NAME).then_some(&b"Worm"[..])
+            );
+            assert_eq!(
+                packet.session.is_some(),
+                flags.contains(PacketFlags::SESSION)
+            );
+        }
+    }
+
+    #[test]
+    fn name_at_max_length_keeps_every_character() {
+        let name = "ÄÖÜäöüßéèêëàáâãåæçñø";
+        assert_eq!(name.chars().count(), MAX_NAME_LENGTH);
+
+        let bytes = WormsPacket::create(PacketCode::Login)
+            .with_name(name)
+            .build()
+            .unwrap();
+        let decoded = decode_all(&bytes).unwrap();
+        assert_eq!(decoded[0].name_text().as_deref(), Some(name));
+    }
+
+    #[test]
+    fn name_over_max_length_is_truncated() {
+        let name = "ÄÖÜäöüßéèêëàáâãåæçñø€‰";
+        let bytes = WormsPacket::create(PacketCode::Login)
+            .with_name(name)
+            .build()
+            .unwrap();
+        let decoded = decode_all(&bytes).unwrap();
+        assert_eq!(
+            decoded[0].name_text().as_deref(),
+            Some("ÄÖÜäöüßéèêëàáâãåæçñø")
+        );
+    }
+
+    #[test]
+    fn data_at_max_length_round_trips() {
+        let data = "€".repeat(MAX_DATA_LENGTH - 1);
+        let bytes = WormsPacket::create(PacketCode::ChatRoom)
+            .with_data(&data)
+            .build()
+            .unwrap();
+        let decoded = decode_all(&bytes).unwrap();
+        assert_eq!(decoded[0].data_text().as_deref(), Some(data.as_str()));
+    }
+
+    #[test]
+    fn data_over_max_length_does_not_build() {
+        let data = "x".repeat(MAX_DATA_LENGTH);
+        assert!(WormsPacket::create(PacketCode::ChatRoom)
+            .with_data(&data)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn unencodable_data_does_not_build() {
+        assert!(WormsPacket::create(PacketCode::ChatRoom)
+            .with_data("日本語")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn unknown_packet_code_is_an_error() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        assert!(decode_all(&bytes).is_err());
+    }
+}
@@ -0,0 +1,471 @@
+use crate::attempts::Attempts;
+use crate::bans::timestamp;
+use crate::bot;
+use crate::config::{Config, NicknamesConfig};
+use crate::database::DATABASE;
+use crate::ip_limits::IP_LIMITS;
+use crate::metrics::{LoginFailure, METRICS};
+use crate::moderation::{self, format_duration, split_word};
+use crate::names;
+use crate::server::Server;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use eyre::{bail, eyre, Result, WrapErr};
+use log::{error, info, warn};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::{Duration, SystemTime};
+use tokio::time;
+
+pub static NICKNAMES: LazyLock<NicknameList> = LazyLock::new(NicknameList::default);
+
+const MIN_PASSWORD_LENGTH: usize = 6;
+
+/// A registered name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Nickname {
+    pub name: String,
+    /// Argon2 hash of the password as a PHC string
+    hash: String,
+    #[serde(with = "timestamp", default = "SystemTime::now")]
+    pub registered: SystemTime,
+}
+
+/// The nicknames file, a `[[nickname]]` table for each registered name.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NicknameFile {
+    #[serde(default, rename = "nickname")]
+    nicknames: Vec<Nickname>,
+}
+
+/// Registered names, saved to a file on every change once one is loaded.
+#[derive(Default)]
+pub struct NicknameList {
+    /// By `names::comparable` name, so a lookalike can't pass as a registered name
+    nicknames: RwLock<HashMap<String, Nickname>>,
+    path: RwLock<Option<PathBuf>>,
+    /// Wrong `identify` passwords by comparable name, kept across logins
+    failures: Attempts,
+}
+
+impl NicknameList {
+    /// Reads the names registered in `path`, where changes are saved from then on. A missing file
+    /// has none registered. Returns how many are.
+    pub fn load(&self, path: &Path) -> Result<usize> {
+        let nicknames = if path.exists() {
+            let text = std::fs::read_to_string(path)
+                .wrap_err_with(|| format!("Unable to read nicknames file {}", path.display()))?;
+            toml::from_str::<NicknameFile>(&text)
+                .wrap_err_with(|| format!("Invalid nicknames file {}", path.display()))?
+                .nicknames
+        } else {
+            Vec::new()
+        };
+
+        *self.path.write() = Some(path.to_path_buf());
+        let mut current = self.nicknames.write();
+        *current = nicknames
+            .into_iter()
+            .map(|nickname| (names::comparable(&nickname.name), nickname))
+            .collect();
+        Ok(current.len())
+    }
+
+    /// Reads the nicknames file again, for changes made by hand.
+    pub fn reload(&self) -> Result<Option<usize>> {
+        let path = self.path.read().clone();
+        path.map(|path| self.load(&path)).transpose()
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.nicknames.read().contains_key(&names::comparable(name))
+    }
+
+    /// How a name that's the same as `name`, or looks like it, was registered.
+    pub fn registered_name(&self, name: &str) -> Option<String> {
+        self.nicknames
+            .read()
+            .get(&names::comparable(name))
+            .map(|nickname| nickname.name.clone())
+    }
+
+    /// The registered names, sorted.
+    pub fn list(&self) -> Vec<Nickname> {
+        let mut nicknames: Vec<Nickname> = self.nicknames.read().values().cloned().collect();
+        nicknames.sort_unstable_by_key(|nickname| nickname.name.to_lowercase());
+        nicknames
+    }
+
+    pub fn len(&self) -> usize {
+        self.nicknames.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nicknames.read().is_empty()
+    }
+
+    /// How long until a name can try a password again, if it's had too many wrong ones.
+    pub fn locked_out_for(&self, name: &str) -> Option<Duration> {
+        self.failures.locked_out_for(&names::comparable(name))
+    }
+
+    /// Counts a wrong password for a name, locking it out once it's had `max_identify_attempts`.
+    /// Returns whether it's locked out now.
+    fn identify_failed(&self, name: &str, config: &NicknamesConfig) -> bool {
+        self.failures.failed(
+            &names::comparable(name),
+            config.max_identify_attempts,
+            Duration::from_secs(config.identify_lockout_secs),
+        )
+    }
+
+    fn identify_succeeded(&self, name: &str) {
+        self.failures.succeeded(&names::comparable(name));
+    }
+
+    fn hash(&self, name: &str) -> Option<String> {
+        self.nicknames
+            .read()
+            .get(&names::comparable(name))
+            .map(|nickname| nickname.hash.clone())
+    }
+
+    /// Registers a name, or changes its password if `replace` is set. Returns false if it was
+    /// registered already, whether or not it was replaced.
+    fn insert(&self, name: &str, hash: String, replace: bool) -> Result<bool> {
+        let mut nicknames = self.nicknames.write();
+        let key = names::comparable(name);
+        if let Some(nickname) = nicknames.get_mut(&key) {
+            if !replace {
+                return Ok(false);
+            }
+            nickname.hash = hash;
+            info!("Changed the password of {}", nickname.name);
+            self.save(&nicknames)?;
+            return Ok(false);
+        }
+
+        nicknames.insert(
+            key,
+            Nickname {
+                name: name.to_string(),
+                hash,
+                registered: SystemTime::now(),
+            },
+        );
+        info!("Registered {}", name);
+        self.save(&nicknames)?;
+        Ok(true)
+    }
+
+    /// Returns false if the name wasn't registered.
+    pub fn remove(&self, name: &str) -> Result<bool> {
+        let mut nicknames = self.nicknames.write();
+        if nicknames.remove(&names::comparable(name)).is_none() {
+            return Ok(false);
+        }
+
+        info!("Unregistered {}", name);
+        self.save(&nicknames)?;
+        Ok(true)
+    }
+
+    fn save(&self, nicknames: &HashMap<String, Nickname>) -> Result<()> {
+        let Some(path) = self.path.read().clone() else {
+            return Ok(());
+        };
+
+        let mut file = NicknameFile {
+            nicknames: nicknames.values().cloned().collect(),
+        };
+        file.nicknames
+            .sort_unstable_by_key(|nickname| nickname.name.to_lowercase());
+        // Written next to the file and moved over it so a crash can't leave half of it
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, toml::to_string_pretty(&file)?)
+            .and_then(|()| std::fs::rename(&temporary, &path))
+            .wrap_err_with(|| format!("Unable to save nicknames to {}", path.display()))
+    }
+}
+
+/// Registers a name with a password, or with `replace` changes the password of one that is.
+/// Returns whether the name is newly registered.
+pub async fn set_password(name: &str, password: &str, replace: bool) -> Result<bool> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        bail!("Passwords need at least {MIN_PASSWORD_LENGTH} characters");
+    }
+    if !replace && NICKNAMES.is_registered(name) {
+        bail!("{name} is already registered");
+    }
+
+    // Hashing takes a while on purpose, keep it off the runtime's threads
+    let password = password.to_string();
+    let hash = tokio::task::spawn_blocking(move || hash_password(&password)).await??;
+    let created = NICKNAMES.insert(name, hash, replace)?;
+    if !created && !replace {
+        bail!("{name} is already registered");
+    }
+    Ok(created)
+}
+
+pub(crate) fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| eyre!("Unable to hash the password: {e}"))
+}
+
+pub(crate) fn verify_password(hash: &str, password: &str) -> bool {
+    PasswordHash::new(hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+/// Whether chat is a command meant for the bot, which would give away a password if it were sent
+/// to the room instead.
+pub fn is_password_command(body: &str) -> bool {
+    let (command, _) = split_word(body.trim_start_matches('/'));
+    ["identify", "register", "password", "unlock", "lock"]
+        .iter()
+        .any(|c| c.eq_ignore_ascii_case(command))
+}
+
+/// Whether a user is using a registered name they haven't proved is theirs.
+pub fn needs_identifying(user_id: u32) -> bool {
+    DATABASE
+        .users
+        .get(&user_id)
+        .is_some_and(|user| !user.identified && NICKNAMES.is_registered(&user.name))
+}
+
+/// Tells a user on a registered name to identify, disconnecting them if they haven't once the
+/// grace period is over.
+pub async fn demand_identification(user_id: u32) -> Result<()> {
+    let config = Config::current();
+    let grace = config.nicknames.grace();
+    let Some((name, connected_at)) = DATABASE
+        .users
+        .get(&user_id)
+        .map(|u| (u.name.clone(), u.connected_at))
+    else {
+        return Ok(());
+    };
+
+    let notice = format!(
+        "{name} is registered, send /identify <password> privately to {} within {} or you'll be \
+         disconnected",
+        config.bot.name,
+        format_duration(grace)
+    );
+    if let Some(user) = DATABASE.users.get(&user_id) {
+        user.send_packet(bot::private_packet(user_id, &notice)?)
+            .await?;
+    }
+
+    tokio::spawn(async move {
+        time::sleep(grace).await;
+
+        // Ids are reused, make sure it's still the same login
+        let Some(address) = DATABASE
+            .users
+            .get(&user_id)
+            .filter(|u| u.connected_at == connected_at && !u.identified)
+            .filter(|u| NICKNAMES.is_registered(&u.name))
+            .map(|u| u.address)
+        else {
+            return;
+        };
+
+        info!(
+            "Disconnecting {} from {}, they didn't identify",
+            name, address
+        );
+        METRICS.login_failed(LoginFailure::Unidentified);
+        IP_LIMITS.login_failed(&Config::current().limits, address.ip());
+
+        let kick = async {
+            if let Some(user) = DATABASE.users.get(&user_id) {
+                let notice = "You didn't identify in time";
+                user.send_packet(bot::private_packet(user_id, notice)?)
+                    .await?;
+            }
+            Server::kick_user(user_id).await
+        };
+        if let Err(e) = kick.await {
+            error!("Unable to disconnect {}: {:?}", name, e);
+        }
+    });
+
+    Ok(())
+}
+
+/// Answers the commands about registered names sent to the bot, returning the lines to reply with.
+pub async fn handle_message(user_id: u32, message: &str) -> Result<Vec<String>> {
+    let (command, argument) = split_word(message.trim().trim_start_matches('/'));
+    let Some((name, identified, address)) = DATABASE
+        .users
+        .get(&user_id)
+        .map(|u| (u.name.clone(), u.identified, u.address))
+    else {
+        return Ok(Vec::new());
+    };
+
+    let reply = match command.to_lowercase().as_str() {
+        "identify" => {
+            let Some(hash) = NICKNAMES.hash(&name) else {
+                bail!("{name} isn't registered");
+            };
+            if identified {
+                bail!("You're already identified");
+            }
+            if argument.is_empty() {
+                bail!("Usage: identify <password>");
+            }
+            // Not even hashed, guesses cost the server as much as a wrong one
+            if let Some(remaining) = NICKNAMES.locked_out_for(&name) {
+                bail!(
+                    "Too many wrong passwords, try again in {}",
+                    format_duration(remaining)
+                );
+            }
+
+            let password = argument.to_string();
+            let correct =
+                tokio::task::spawn_blocking(move || verify_password(&hash, &password)).await?;
+            if !correct {
+                warn!("Wrong password for {} from {}", name, address);
+                let config = Config::current();
+                IP_LIMITS.login_failed(&config.limits, address.ip());
+                if NICKNAMES.identify_failed(&name, &config.nicknames) {
+                    info!(
+                        "Disconnecting {} from {}, too many wrong passwords",
+                        name, address
+                    );
+                    METRICS.login_failed(LoginFailure::Unidentified);
+                    if let Some(user) = DATABASE.users.get(&user_id) {
+                        let notice = "Too many wrong passwords";
+                        user.send_packet(bot::private_packet(user_id, notice)?)
+                            .await?;
+                    }
+                    Server::kick_user(user_id).await?;
+                    return Ok(Vec::new());
+                }
+                bail!("Wrong password");
+            }
+
+            NICKNAMES.identify_succeeded(&name);
+            identify(user_id);
+            info!("{} identified", name);
+            vec![format!("You're identified as {name}")]
+        }
+        "register" => {
+            // Or anyone logging in first with a moderator's name could take their commands
+            if !Config::current().nicknames.self_register || moderation::is_moderator(&name) {
+                bail!("Ask an admin to register your name");
+            }
+            if argument.is_empty() {
+                bail!("Usage: register <password>");
+            }
+
+            set_password(&name, argument, false).await?;
+            identify(user_id);
+            vec![format!(
+                "Registered {name}, send identify <password> whenever you log in"
+            )]
+        }
+        "password" => {
+            if !identified {
+                bail!("Identify first");
+            }
+            if argument.is_empty() {
+                bail!("Usage: password <new password>");
+            }
+
+            set_password(&name, argument, true).await?;
+            vec!["Password changed".to_string()]
+        }
+        _ => bail!("Unknown command '{command}'"),
+    };
+
+    Ok(reply)
+}
+
+fn identify(user_id: u32) {
+    if let Some(mut user) = DATABASE.users.get_mut(&user_id) {
+        user.identified = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrong_passwords_lock_a_name_out() {
+        let config = NicknamesConfig {
+            max_identify_attempts: 2,
+            ..Default::default()
+        };
+        let nicknames = NicknameList::default();
+        assert!(!nicknames.identify_failed("Bob", &config));
+        assert_eq!(nicknames.locked_out_for("bob"), None);
+        assert!(nicknames.identify_failed("BOB", &config));
+        assert!(nicknames.locked_out_for("bob").is_some());
+        assert_eq!(nicknames.locked_out_for("Alice"), None);
+
+        nicknames.identify_succeeded("Bob");
+        assert_eq!(nicknames.locked_out_for("bob"), None);
+    }
+
+    #[test]
+    fn lookalikes_find_the_registered_name() {
+        let nicknames = NicknameList::default();
+        assert!(nicknames.insert("Bob", "hash".to_string(), false).unwrap());
+        assert!(nicknames.is_registered("B0B"));
+        assert!(nicknames.is_registered("Воb"));
+        assert_eq!(nicknames.registered_name("b0b"), Some("Bob".to_string()));
+        assert_eq!(nicknames.hash("8ob"), None);
+        assert!(!nicknames.insert("B0b", "other".to_string(), false).unwrap());
+    }
+
+    #[test]
+    fn passwords_verify_against_their_hash() {
+        let hash = hash_password("hunter22").unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password(&hash, "hunter22"));
+        assert!(!verify_password(&hash, "hunter23"));
+        assert!(!verify_password("not a hash", "hunter22"));
+    }
+
+    #[test]
+    fn password_commands_are_recognized() {
+        assert!(is_password_command("/identify secret"));
+        assert!(is_password_command("REGISTER secret"));
+        assert!(is_password_command("/lock secret"));
+        assert!(!is_password_command("/kick bob"));
+        assert!(!is_password_command("identifying the problem"));
+    }
+
+    #[test]
+    fn nicknames_survive_the_file() {
+        let file = NicknameFile {
+            nicknames: vec![Nickname {
+                name: "Alice".to_string(),
+                hash: hash_password("hunter22").unwrap(),
+                registered: SystemTime::UNIX_EPOCH,
+            }],
+        };
+
+        let text = toml::to_string_pretty(&file).unwrap();
+        let read: NicknameFile = toml::from_str(&text).unwrap();
+        assert_eq!(read.nicknames[0].name, "Alice");
+        assert_eq!(read.nicknames[0].registered, SystemTime::UNIX_EPOCH);
+        assert!(verify_password(&read.nicknames[0].hash, "hunter22"));
+    }
+}
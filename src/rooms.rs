@@ -0,0 +1,791 @@
+use crate::attempts::Attempts;
+use crate::audit::{self, Actor, Event};
+use crate::bot;
+use crate::config::{check_chat_message, Config};
+use crate::database::room::Room;
+use crate::database::{Database, DATABASE};
+use crate::greetings::{check_template, ROOM_VARIABLES};
+use crate::ip_limits::IpLimits;
+use crate::moderation::format_duration;
+use crate::net::nation::Nation;
+use crate::net::packet_code::PacketCode;
+use crate::net::worms_packet::{encode_text, WormsPacket, MAX_NAME_LENGTH};
+use crate::server::Server;
+use crate::{names, nicknames};
+use argon2::PasswordHash;
+use eyre::{bail, eyre, Result, WrapErr};
+use log::{info, warn};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+// Rooms set up by the operator. They stay open while empty and are saved to a file, so they come
+// back with the same ids after a restart.
+
+pub static PERMANENT_ROOMS: LazyLock<PermanentRooms> = LazyLock::new(PermanentRooms::default);
+
+/// Wrong room passwords by the comparable name of the user giving them.
+static UNLOCKS: LazyLock<Attempts> = LazyLock::new(Attempts::default);
+
+/// A permanent room as it's saved in the rooms file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PermanentRoom {
+    /// Given when the room is made and kept from then on, left out for a new room
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub id: u32,
+    pub name: String,
+    #[serde(default, with = "nation")]
+    pub nation: Nation,
+    /// Sent to whoever joins instead of `messages.room_greeting`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub greeting: String,
+    /// Needed to join, a single word. Saved as its Argon2 hash, one written in by hand is hashed
+    /// once it's read.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub password: String,
+    /// Most users in the room at once, 0 for `rooms.max_users`
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub capacity: u32,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+impl PermanentRoom {
+    fn of(room: &Room) -> Self {
+        Self {
+            id: room.id,
+            name: room.name.clone(),
+            nation: room.session.nation,
+            greeting: room.greeting.clone(),
+            password: room.password_hash.clone(),
+            capacity: room.capacity,
+        }
+    }
+
+    /// Checks the game could show the room and its greeting.
+    pub fn validate(&self) -> Result<()> {
+        let name = &self.name;
+        if name.trim().is_empty() {
+            bail!("Rooms need a name");
+        }
+        match encode_text(name) {
+            Some(encoded) if encoded.len() <= MAX_NAME_LENGTH => {}
+            Some(_) => bail!("{name}: names can be at most {MAX_NAME_LENGTH} characters long"),
+            None => bail!("{name}: names can only contain characters from Windows-1252"),
+        }
+        if self.id != 0 && self.id < Database::ID_START {
+            bail!("{name}: ids start at {}", Database::ID_START);
+        }
+        if self.password.contains(char::is_whitespace) {
+            bail!("{name}: the password must be a single word");
+        }
+        check_template(
+            &format!("{name}: greeting"),
+            &self.greeting,
+            &ROOM_VARIABLES,
+        )
+    }
+}
+
+/// The rooms file, a `[[room]]` table for each permanent room.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoomFile {
+    #[serde(default, rename = "room")]
+    rooms: Vec<PermanentRoom>,
+}
+
+/// Where the permanent rooms are saved. The rooms themselves are in `DATABASE.rooms`, or a
+/// test's own database.
+pub struct PermanentRooms {
+    path: Mutex<Option<PathBuf>>,
+    database: &'static Database,
+}
+
+impl Default for PermanentRooms {
+    fn default() -> Self {
+        Self::in_database(&DATABASE)
+    }
+}
+
+impl PermanentRooms {
+    /// Keeps the rooms in another database than the server's.
+    fn in_database(database: &'static Database) -> Self {
+        Self {
+            path: Mutex::default(),
+            database,
+        }
+    }
+
+    /// Opens the rooms in `path`, where changes are saved from then on. Permanent rooms no longer
+    /// in it aren't kept open anymore. A missing file has none. Returns how many there are.
+    pub async fn load(&self, path: &Path) -> Result<usize> {
+        let rooms = if path.exists() {
+            let text = std::fs::read_to_string(path)
+                .wrap_err_with(|| format!("Unable to read rooms file {}", path.display()))?;
+            toml::from_str::<RoomFile>(&text)
+                .wrap_err_with(|| format!("Invalid rooms file {}", path.display()))?
+                .rooms
+        } else {
+            Vec::new()
+        };
+
+        let mut names = HashSet::new();
+        let mut ids = HashSet::new();
+        for room in &rooms {
+            room.validate()
+                .wrap_err_with(|| format!("Invalid rooms file {}", path.display()))?;
+            if !names.insert(room.name.to_lowercase()) {
+                bail!(
+                    "{} is in the rooms file {} twice",
+                    room.name,
+                    path.display()
+                );
+            }
+            if room.id != 0 && !ids.insert(room.id) {
+                bail!(
+                    "Id {} is in the rooms file {} twice",
+                    room.id,
+                    path.display()
+                );
+            }
+        }
+        for room in &rooms {
+            // The room with the id now being another one in the file means the file swapped them
+            let holder = self
+                .database
+                .rooms
+                .get(&room.id)
+                .filter(|held| held.permanent && !held.name.eq_ignore_ascii_case(&room.name))
+                .map(|held| held.name.clone());
+            if let Some(holder) = holder.filter(|holder| names.contains(&holder.to_lowercase())) {
+                bail!(
+                    "{}: id {} belongs to {} in the rooms file {}",
+                    room.name,
+                    room.id,
+                    holder,
+                    path.display()
+                );
+            }
+        }
+        // Saved ids first, so new rooms can't be given one of them
+        for &id in &ids {
+            self.database.reserve_id(id);
+        }
+
+        *self.path.lock() = Some(path.to_path_buf());
+        let mut kept = Vec::new();
+        let mut changed = false;
+        for settings in rooms {
+            let asked_for = settings.id;
+            changed |= !is_hashed(&settings.password);
+            let (id, _) = self.keep_room(settings).await?;
+            changed |= id != asked_for;
+            kept.push(id);
+        }
+
+        let dropped: Vec<u32> = self
+            .database
+            .rooms
+            .iter()
+            .filter(|room| room.permanent && !kept.contains(&room.id))
+            .map(|room| room.id)
+            .collect();
+        for id in dropped {
+            self.release_room(id).await?;
+        }
+
+        // New rooms have their ids written down, and passwords are only kept hashed
+        if changed {
+            self.save()?;
+        }
+        Ok(kept.len())
+    }
+
+    /// Reads the rooms file again, for changes made by hand.
+    pub async fn reload(&self) -> Result<Option<usize>> {
+        let path = self.path.lock().clone();
+        match path {
+            Some(path) => Ok(Some(self.load(&path).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Makes a room permanent, or changes one that is, matching it by id and then by name. Returns
+    /// whether the room had to be made.
+    pub async fn keep(&self, settings: PermanentRoom) -> Result<bool> {
+        settings.validate()?;
+        let (_, created) = self.keep_room(settings).await?;
+        self.save()?;
+        Ok(created)
+    }
+
+    /// Stops keeping a room open, it's closed now if it's empty. Returns false if it wasn't
+    /// permanent.
+    pub async fn release(&self, room_id: u32) -> Result<bool> {
+        if !self.release_room(room_id).await? {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// The permanent rooms, by id.
+    pub fn list(&self) -> Vec<PermanentRoom> {
+        let mut rooms: Vec<PermanentRoom> = self
+            .database
+            .rooms
+            .iter()
+            .filter(|room| room.permanent)
+            .map(|room| PermanentRoom::of(&room))
+            .collect();
+        rooms.sort_unstable_by_key(|room| room.id);
+        rooms
+    }
+
+    /// Writes the permanent rooms to the file, if one was loaded.
+    pub(crate) fn save(&self) -> Result<()> {
+        let path = self.path.lock();
+        let Some(path) = path.as_ref() else {
+            return Ok(());
+        };
+
+        let file = RoomFile { rooms: self.list() };
+        // Written next to the file and moved over it so a crash can't leave half of it
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, toml::to_string_pretty(&file)?)
+            .and_then(|()| std::fs::rename(&temporary, path))
+            .wrap_err_with(|| format!("Unable to save rooms to {}", path.display()))
+    }
+
+    /// Applies the settings to the room they're for, making it if there's none. Returns its id and
+    /// whether it was made.
+    async fn keep_room(&self, settings: PermanentRoom) -> Result<(u32, bool)> {
+        let password_hash = if is_hashed(&settings.password) {
+            settings.password.clone()
+        } else {
+            hash_password(&settings.password).await?
+        };
+        let existing = Some(settings.id)
+            .filter(|id| self.database.rooms.contains_key(id))
+            .or_else(|| self.find(&settings.name));
+        if self
+            .database
+            .rooms
+            .iter()
+            .any(|room| Some(room.id) != existing && room.name.eq_ignore_ascii_case(&settings.name))
+        {
+            bail!("There's another room called {}", settings.name);
+        }
+
+        if let Some(id) = existing {
+            if let Some(mut room) = self.database.rooms.get_mut(&id) {
+                if !room.permanent && !room.name.eq_ignore_ascii_case(&settings.name) {
+                    bail!("{}: id {id} is taken by room {}", settings.name, room.name);
+                }
+                // Clients already showing the room see a new name or flag once they list them again
+                room.name = settings.name;
+                room.set_access(settings.nation, !password_hash.is_empty(), &password_hash);
+                room.greeting = settings.greeting;
+                room.capacity = settings.capacity;
+                room.permanent = true;
+                // The operator's now, the settings only change from the admin tools
+                room.owner = 0;
+                return Ok((id, false));
+            }
+        }
+
+        let id = match settings.id {
+            0 => self.database.next_id(),
+            id if self.database.users.contains_key(&id)
+                || self.database.games.contains_key(&id)
+                || self.database.rooms.contains_key(&id) =>
+            {
+                bail!("{}: id {id} is taken", settings.name)
+            }
+            id => {
+                self.database.reserve_id(id);
+                id
+            }
+        };
+        let mut room = Room::new(id, &settings.name, settings.nation);
+        room.set_access(settings.nation, !password_hash.is_empty(), &password_hash);
+        room.greeting = settings.greeting;
+        room.capacity = settings.capacity;
+        room.permanent = true;
+
+        let packet = WormsPacket::create(PacketCode::CreateRoom)
+            .with_value_1(id)
+            .with_value_4(0)
+            .with_data("")
+            .with_name(&room.name)
+            .with_session(&room.session)
+            .build()?;
+        info!("Opened permanent room '{}'", room.name);
+        self.database.rooms.insert(id, room);
+        Server::broadcast_all(packet).await?;
+
+        Ok((id, true))
+    }
+
+    async fn release_room(&self, room_id: u32) -> Result<bool> {
+        match self.database.rooms.get_mut(&room_id) {
+            Some(mut room) if room.permanent => room.permanent = false,
+            _ => return Ok(false),
+        }
+
+        // From now on it's closed like any other once nobody's left in it
+        let empty = !self.database.users.iter().any(|u| u.room_id == room_id)
+            && !self.database.games.iter().any(|g| g.room_id == room_id);
+        if empty && Config::current().rooms.remove_when_empty {
+            Server::close_room(room_id).await?;
+        }
+        Ok(true)
+    }
+
+    /// The id of the room with a name, ignoring case.
+    fn find(&self, name: &str) -> Option<u32> {
+        self.database
+            .rooms
+            .iter()
+            .find(|room| room.name.eq_ignore_ascii_case(name))
+            .map(|room| room.id)
+    }
+}
+
+/// A room's settings as they'd be saved, whether or not it's permanent.
+pub fn settings(room_id: u32) -> Option<PermanentRoom> {
+    DATABASE
+        .rooms
+        .get(&room_id)
+        .map(|room| PermanentRoom::of(&room))
+}
+
+/// The id of the room with a name, ignoring case.
+pub fn find_room(name: &str) -> Option<u32> {
+    PERMANENT_ROOMS.find(name)
+}
+
+/// Whether a room password is empty or already hashed, and can be kept as it is.
+fn is_hashed(password: &str) -> bool {
+    password.is_empty() || PasswordHash::new(password).is_ok()
+}
+
+/// Hashes a room password off the runtime's threads, it takes a while on purpose.
+pub(crate) async fn hash_password(password: &str) -> Result<String> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || nicknames::hash_password(&password)).await?
+}
+
+/// Lets a user into a room with a password once they've given it, `unlock <room> <password>`.
+pub async fn unlock(user_id: u32, argument: &str) -> Result<String> {
+    let (room, password) = argument
+        .trim()
+        .rsplit_once(' ')
+        .ok_or_else(|| eyre!("Usage: unlock <room> <password>"))?;
+    let room = room.trim();
+    let room_id = room
+        .parse::<u32>()
+        .ok()
+        .filter(|id| DATABASE.rooms.contains_key(id))
+        .or_else(|| find_room(room))
+        .ok_or_else(|| eyre!("No room called '{room}'"))?;
+
+    let (room_name, hash) = {
+        let room = DATABASE
+            .rooms
+            .get(&room_id)
+            .ok_or_else(|| eyre!("No room called '{room}'"))?;
+        if !room.is_protected() {
+            bail!("{} doesn't need a password", room.name);
+        }
+        if room.password_hash.is_empty() {
+            bail!(
+                "{} has no password yet, whoever made it sets one",
+                room.name
+            );
+        }
+        (room.name.clone(), room.password_hash.clone())
+    };
+
+    let user_name = DATABASE
+        .users
+        .get(&user_id)
+        .map(|user| user.name.clone())
+        .ok_or_else(|| eyre!("User '{user_id}' not found!"))?;
+    // Counted apart from failed logins, a typo shouldn't keep anyone behind the address out
+    let key = names::comparable(&user_name);
+    if let Some(remaining) = UNLOCKS.locked_out_for(&key) {
+        bail!(
+            "Too many wrong room passwords, try again in {}",
+            format_duration(remaining)
+        );
+    }
+
+    let password = password.to_string();
+    let correct =
+        tokio::task::spawn_blocking(move || nicknames::verify_password(&hash, &password)).await?;
+    if !correct {
+        warn!("Wrong password for room '{}' from {}", room_name, user_name);
+        let config = Config::current();
+        let lockout = Duration::from_secs(config.rooms.unlock_lockout_secs);
+        if UNLOCKS.failed(&key, config.rooms.max_unlock_attempts, lockout) {
+            bail!(
+                "Wrong password for {room_name}, wait {} before trying again",
+                format_duration(lockout)
+            );
+        }
+        bail!("Wrong password for {room_name}");
+    }
+    UNLOCKS.succeeded(&key);
+
+    let mut user = DATABASE
+        .users
+        .get_mut(&user_id)
+        .ok_or_else(|| eyre!("User '{user_id}' not found!"))?;
+    if !user.unlocked_rooms.contains(&room_id) {
+        user.unlocked_rooms.push(room_id);
+    }
+    Ok(format!("You can join {room_name} now"))
+}
+
+/// Whether a user may join a room, for a protected one they must own it or have given its
+/// password.
+pub fn is_unlocked(user_id: u32, room: &Room) -> bool {
+    !room.is_protected()
+        || room.owner == user_id
+        || DATABASE
+            .users
+            .get(&user_id)
+            .is_some_and(|user| user.unlocked_rooms.contains(&room.id))
+}
+
+/// Sets a password on the room a user is in and owns, `lock <password>`. Whoever gave the old
+/// one has to give the new one.
+pub async fn lock(user_id: u32, password: &str) -> Result<String> {
+    let password = password.trim();
+    if password.is_empty() || password.contains(' ') {
+        bail!("Usage: lock <password>");
+    }
+
+    owned_room(user_id)?;
+    let password_hash = hash_password(password).await?;
+    // Checked again, the room could have changed hands while hashing
+    let room_id = owned_room(user_id)?;
+    let room_name = {
+        let mut room = DATABASE
+            .rooms
+            .get_mut(&room_id)
+            .ok_or_else(|| eyre!("You aren't in a room"))?;
+        let nation = room.session.nation;
+        room.set_access(nation, true, &password_hash);
+        room.name.clone()
+    };
+    for mut user in DATABASE.users.iter_mut() {
+        user.unlocked_rooms.retain(|id| *id != room_id);
+    }
+
+    info!("Room '{}' was given a password", room_name);
+    Ok(format!("{room_name} needs the password to join now"))
+}
+
+/// Lets anyone into the room a user is in and owns again, `open`.
+pub fn open(user_id: u32) -> Result<String> {
+    let room_id = owned_room(user_id)?;
+    let mut room = DATABASE
+        .rooms
+        .get_mut(&room_id)
+        .ok_or_else(|| eyre!("You aren't in a room"))?;
+    let nation = room.session.nation;
+    room.set_access(nation, false, "");
+    Ok(format!("Anyone can join {} now", room.name))
+}
+
+/// Sets the topic of the room a user owns, `topic [text]`, clearing it without any.
+pub async fn set_topic(user_id: u32, topic: &str) -> Result<String> {
+    let topic = topic.trim();
+    check_chat_message("The topic", &format!("Topic: {topic}"))?;
+    let room_id = owned_room(user_id)?;
+    let room_name = {
+        let mut room = DATABASE
+            .rooms
+            .get_mut(&room_id)
+            .ok_or_else(|| eyre!("You aren't in a room"))?;
+        room.topic = topic.to_string();
+        room.name.clone()
+    };
+
+    if topic.is_empty() {
+        return Ok(format!("Cleared the topic of {room_name}"));
+    }
+    Server::broadcast_notice(Some(room_id), &format!("Topic: {topic}")).await?;
+    Ok(format!("Set the topic of {room_name}"))
+}
+
+/// Sends a user out of the room the owner is in, for good, `kick <name>`.
+pub async fn kick(user_id: u32, name: &str) -> Result<String> {
+    let room_id = owned_room(user_id)?;
+    let (target_id, target_name) = user_in_room(room_id, name)?;
+    if target_id == user_id {
+        bail!("You can't kick yourself out, leave instead");
+    }
+    let target_range = DATABASE
+        .users
+        .get(&target_id)
+        .and_then(|u| IpLimits::key(&Config::current().limits, u.address.ip()));
+
+    let room_name = {
+        let mut room = DATABASE
+            .rooms
+            .get_mut(&room_id)
+            .ok_or_else(|| eyre!("You aren't in a room"))?;
+        room.kick(&target_name, target_range);
+        room.name.clone()
+    };
+    Server::remove_from_room(room_id, target_id).await?;
+    if let Some(target) = DATABASE.users.get(&target_id) {
+        let message = format!("You were kicked out of {room_name}");
+        target
+            .send_packet(bot::private_packet(target_id, &message)?)
+            .await?;
+    }
+
+    info!("{} was kicked out of room '{}'", target_name, room_name);
+    let event = Event::Moderation {
+        by: "room owner",
+        action: format!("kick {target_name} out of {room_name}"),
+    };
+    audit::record(Actor::user(user_id), event);
+    Server::broadcast_notice(
+        Some(room_id),
+        &format!("{target_name} was kicked out of the room"),
+    )
+    .await?;
+    Ok(format!("Kicked {target_name} out of {room_name}"))
+}
+
+/// Gives the room a user owns to someone else in it, `owner <name>`.
+pub async fn transfer(user_id: u32, name: &str) -> Result<String> {
+    let room_id = owned_room(user_id)?;
+    let (target_id, target_name) = user_in_room(room_id, name)?;
+    let room_name = {
+        let mut room = DATABASE
+            .rooms
+            .get_mut(&room_id)
+            .ok_or_else(|| eyre!("You aren't in a room"))?;
+        room.owner = target_id;
+        room.name.clone()
+    };
+
+    Server::broadcast_notice(
+        Some(room_id),
+        &format!("{target_name} owns {room_name} now"),
+    )
+    .await?;
+    Ok(format!("Gave {room_name} to {target_name}"))
+}
+
+/// Passes a room on when its owner leaves, to whoever in it has been online longest. Nobody owns
+/// it once it's empty.
+pub async fn pass_ownership(room_id: u32, left_id: u32) -> Result<()> {
+    let owner = match DATABASE.rooms.get(&room_id) {
+        Some(room) if room.owner != 0 => room.owner,
+        _ => return Ok(()),
+    };
+    // Disconnected owners are gone from the users before their room is left
+    if owner != left_id && DATABASE.users.contains_key(&owner) {
+        return Ok(());
+    }
+
+    let heir = DATABASE
+        .users
+        .iter()
+        .filter(|u| u.id != left_id && u.room_id == room_id && !bot::is_bot(u.id))
+        .min_by_key(|u| u.connected_at)
+        .map(|u| (u.id, u.name.clone()));
+    let room_name = match DATABASE.rooms.get_mut(&room_id) {
+        Some(mut room) => {
+            room.owner = heir.as_ref().map_or(0, |(id, _)| *id);
+            room.name.clone()
+        }
+        None => return Ok(()),
+    };
+
+    if let Some((_, name)) = heir {
+        Server::broadcast_notice(Some(room_id), &format!("{name} owns {room_name} now")).await?;
+    }
+    Ok(())
+}
+
+/// How many rooms a user owns, not counting permanent ones.
+pub fn owned_count(user_id: u32) -> usize {
+    DATABASE
+        .rooms
+        .iter()
+        .filter(|room| room.owner == user_id && !room.permanent)
+        .count()
+}
+
+/// A user in a room by name, ignoring case.
+fn user_in_room(room_id: u32, name: &str) -> Result<(u32, String)> {
+    let name = name.trim();
+    if name.is_empty() {
+        bail!("No name given");
+    }
+    DATABASE
+        .users
+        .iter()
+        .find(|u| u.room_id == room_id && !bot::is_bot(u.id) && u.name.eq_ignore_ascii_case(name))
+        .map(|u| (u.id, u.name.clone()))
+        .ok_or_else(|| eyre!("{name} isn't in your room"))
+}
+
+/// The room a user is in, if they own it.
+fn owned_room(user_id: u32) -> Result<u32> {
+    let room_id = DATABASE
+        .users
+        .get(&user_id)
+        .map(|user| user.room_id)
+        .ok_or_else(|| eyre!("User '{user_id}' not found!"))?;
+    let room = DATABASE
+        .rooms
+        .get(&room_id)
+        .ok_or_else(|| eyre!("You aren't in a room"))?;
+    if room.owner != user_id {
+        bail!("Only the owner of {} can do that", room.name);
+    }
+    Ok(room_id)
+}
+
+/// Saves the flag by its name, like `UK` or `Skull`.
+mod nation {
+    use crate::net::nation::Nation;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(nation: &Nation, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{nation:?}"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Nation, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|e: eyre::Report| D::Error::custom(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::address_range::AddressRange;
+
+    #[test]
+    fn rooms_survive_the_file() {
+        let file = RoomFile {
+            rooms: vec![
+                PermanentRoom {
+                    id: 0x1000,
+                    name: "Lobby".to_string(),
+                    nation: Nation::UK,
+                    greeting: "Welcome to {room}, {name}".to_string(),
+                    ..Default::default()
+                },
+                PermanentRoom {
+                    id: 0x1001,
+                    name: "Clan".to_string(),
+                    password: "secret".to_string(),
+                    capacity: 8,
+                    ..Default::default()
+                },
+            ],
+        };
+
+        let text = toml::to_string_pretty(&file).unwrap();
+        let read: RoomFile = toml::from_str(&text).unwrap();
+        assert_eq!(read.rooms, file.rooms);
+    }
+
+    #[test]
+    fn rooms_without_a_capacity_use_the_server_limit() {
+        let mut room = Room::new(0, "Lobby", Nation::None);
+        assert!(!room.is_full(100, 0));
+        assert!(room.is_full(10, 10));
+        room.capacity = 2;
+        assert!(room.is_full(2, 10));
+        assert!(!room.is_full(1, 10));
+    }
+
+    #[test]
+    fn kicks_outlast_logging_in_again() {
+        let address = "203.0.113.7".parse().unwrap();
+        let mut room = Room::new(0, "Lobby", Nation::None);
+        room.kick("Bob", Some(AddressRange::containing(address, 32)));
+
+        // A new id under the same name, or from the same address
+        assert!(room.is_kicked("bob", "198.51.100.1".parse().unwrap()));
+        assert!(room.is_kicked("B0b", "198.51.100.1".parse().unwrap()));
+        assert!(room.is_kicked("Robert", address));
+        // Whoever gets Bob's old id isn't kept out
+        assert!(!room.is_kicked("Alice", "198.51.100.1".parse().unwrap()));
+
+        let mut lan_room = Room::new(0, "LAN", Nation::None);
+        lan_room.kick("Bob", None);
+        assert!(!lan_room.is_kicked("Alice", address));
+    }
+
+    #[test]
+    fn settings_are_checked() {
+        let room = |name: &str, password: &str| PermanentRoom {
+            name: name.to_string(),
+            password: password.to_string(),
+            ..Default::default()
+        };
+        assert!(room("Lobby", "").validate().is_ok());
+        assert!(room(" ", "").validate().is_err());
+        assert!(room("A name far too long for the game", "")
+            .validate()
+            .is_err());
+        assert!(room("Clan", "two words").validate().is_err());
+        assert!(toml::from_str::<PermanentRoom>("name = \"Lobby\"\nnation = \"Narnia\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn room_passwords_are_kept_hashed() {
+        let hash = hash_password("secret").await.unwrap();
+        assert!(is_hashed(&hash));
+        assert!(is_hashed(""));
+        assert!(!is_hashed("secret"));
+        assert!(nicknames::verify_password(&hash, "secret"));
+        assert!(!nicknames::verify_password(&hash, "Secret"));
+    }
+
+    #[tokio::test]
+    async fn new_rooms_dont_take_saved_ids() {
+        // Handed out next unless the saved room holds on to it first
+        let database = Box::leak(Box::new(Database::with_capacity(4)));
+        let saved = 0x00F0_0000;
+        database.recycle(saved);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rooms.toml");
+        let file =
+            format!("[[room]]\nname = \"New\"\n\n[[room]]\nid = {saved}\nname = \"Saved\"\n");
+        std::fs::write(&path, file).unwrap();
+
+        let rooms = PermanentRooms::in_database(database);
+        assert_eq!(rooms.load(&path).await.unwrap(), 2);
+
+        assert_eq!(database.rooms.get(&saved).unwrap().name, "Saved");
+        let new_id = rooms.find("New").unwrap();
+        assert_ne!(new_id, saved);
+        let saved_file = std::fs::read_to_string(&path).unwrap();
+        assert!(saved_file.contains(&format!("id = {new_id}")));
+        assert!(DATABASE.rooms.get(&saved).is_none());
+    }
+}
@@ -1,39 +1,74 @@
+use crate::audit::{self, Actor, Event};
+use crate::bans::BANS;
+use crate::bot;
+use crate::chat_filter;
+use crate::config::Config;
 use crate::database::user::User;
 use crate::database::{Database, DATABASE, SHUTDOWN_TOKEN};
+use crate::greetings;
+use crate::ip_limits::{Refusal, Session, IP_LIMITS};
+use crate::metrics::{LoginFailure, RateLimit, METRICS};
+use crate::names::{self, NameProblem};
+use crate::net::capture::{Recorder, RecordingCodec};
 use crate::net::packet_code::PacketCode;
 use crate::net::packet_handler;
-use crate::net::worms_codec::WormCodec;
+use crate::net::requests::LoginRequest;
 use crate::net::worms_packet::WormsPacket;
+use crate::nicknames::{self, NICKNAMES};
+use crate::rooms::{self, PERMANENT_ROOMS};
 use eyre::{bail, eyre, Result, WrapErr};
 use futures_util::StreamExt;
-use futures_util::{FutureExt, SinkExt};
+use futures_util::{FutureExt, Sink, SinkExt};
 use governor::{Quota, RateLimiter};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 
 use futures_util::future::join_all;
-use std::num::NonZeroU32;
-use std::sync::Arc;
-use std::time::Duration;
+use std::fmt::Display;
+use std::net::SocketAddr;
+use std::sync::{Arc, LazyLock, OnceLock};
+use std::time::{Duration, Instant};
 use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
-use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::{Receiver, Sender};
 use tokio::time;
 use tokio_util::bytes::Bytes;
 use tokio_util::codec::Framed;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
 
-pub(crate) struct Server;
+/// Every connection task, so a shutdown can wait for them to send what's left.
+static CONNECTIONS: LazyLock<TaskTracker> = LazyLock::new(TaskTracker::new);
+/// Tells connections to flush their queue and close, the last step of a shutdown.
+static DISCONNECT_TOKEN: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+/// Grace period asked for by whoever started the shutdown, instead of the configured one.
+static SHUTDOWN_GRACE: OnceLock<Duration> = OnceLock::new();
+/// When the server started listening.
+static STARTED: OnceLock<Instant> = OnceLock::new();
+
+pub struct Server;
 
 impl Server {
-    const AUTHORIZED_TTL: Duration = Duration::from_secs(10 * 60);
-    const UNAUTHORIZED_TTL: Duration = Duration::from_secs(3);
+    /// How long connections get to send their last packets once everyone's being disconnected.
+    const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+    /// How often the limits per address forget about addresses that went quiet.
+    const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+    /// `LoginReply` error codes, the game only checks for a non-zero one
+    const LOGIN_REFUSED: u32 = 1;
+    const LOGIN_BANNED: u32 = 2;
+    const LOGIN_NAME_TAKEN: u32 = 3;
+    const LOGIN_NAME_LENGTH: u32 = 4;
+    const LOGIN_NAME_CHARACTERS: u32 = 5;
+    const LOGIN_NAME_RESERVED: u32 = 6;
 
     pub async fn start_server(address: impl ToSocketAddrs) -> Result<()> {
         let cancellation_token = SHUTDOWN_TOKEN.clone();
-        let rate_limiter = RateLimiter::dashmap(Quota::per_second(NonZeroU32::new(1).unwrap()));
+        let mut prune_interval = time::interval(Server::PRUNE_INTERVAL);
 
         let listener = TcpListener::bind(address).await?;
         let local_addr = listener
             .local_addr()
             .map_err(|e| eyre!("Unable to get local address: {}", e))?;
+        STARTED.get_or_init(Instant::now);
+        bot::start();
 
         println!("Server listening at {local_addr}");
         println!("Press Ctrl + C to shutdown!");
@@ -41,76 +76,228 @@ impl Server {
             tokio::select! {
                 listen_result = listener.accept() => {
                     if let Ok((stream, _)) = listen_result {
-                        // Limit login attempts to 1 per second
                         let addr = stream.peer_addr()?;
-                        if let Err(_) = rate_limiter.check_key(&addr) {
-                            error!("Rate limit exceeded for {}", addr);
+                        if BANS.find_address(addr.ip()).is_some() {
+                            info!("Refused banned address {}", addr);
+                            METRICS.login_failed(LoginFailure::Banned);
                             continue;
                         }
 
+                        // Limit connections per address
+                        let session = match IP_LIMITS.accept(&Config::current().limits, addr.ip()) {
+                            Ok(session) => session,
+                            Err(refusal) => {
+                                info!("Refused {}: {:?}", addr, refusal);
+                                METRICS.rate_limited(refusal.into());
+                                continue;
+                            }
+                        };
+
                         // Set TCP_NODELAY to true
                         stream.set_nodelay(true)?;
 
                         // Handle the connection in a separate task
-                        tokio::spawn(Server::handle_connection(stream));
+                        CONNECTIONS.spawn(Server::handle_connection(stream, session));
                     }
                 },
+                _ = prune_interval.tick() => IP_LIMITS.prune(),
                     () = cancellation_token.cancelled().fuse() => {
                     break 'server;
                 }
             }
         }
 
+        drop(listener);
+        Server::shutdown().await
+    }
+
+    /// How long the server's been listening for.
+    pub fn uptime() -> Duration {
+        STARTED.get().map_or(Duration::ZERO, Instant::elapsed)
+    }
+
+    /// Handles Ctrl + C, the first starts shutting down and a second exits without waiting for
+    /// the grace period or whatever's left to send.
+    pub fn interrupt() {
+        if SHUTDOWN_TOKEN.is_cancelled() {
+            warn!("Exiting without waiting for connections to close");
+            std::process::exit(1);
+        }
+
+        info!("Server shutting down, press Ctrl + C again to exit right away");
+        Server::begin_shutdown(None);
+    }
+
+    /// Starts shutting down, `grace` overrides the configured grace period.
+    pub fn begin_shutdown(grace: Option<Duration>) {
+        if let Some(grace) = grace {
+            let _ = SHUTDOWN_GRACE.set(grace);
+        }
+        SHUTDOWN_TOKEN.cancel();
+    }
+
+    /// Warns everyone, waits out the grace period and then disconnects every user, letting their
+    /// connections send what's still queued first.
+    async fn shutdown() -> Result<()> {
+        let config = Config::current();
+        let grace = SHUTDOWN_GRACE
+            .get()
+            .copied()
+            .unwrap_or_else(|| config.server.shutdown_grace());
+
+        if !grace.is_zero() {
+            info!("Disconnecting everyone in {} seconds", grace.as_secs());
+            let notice = config
+                .messages
+                .shutdown_notice
+                .replace("{seconds}", &grace.as_secs().to_string());
+
+            Server::broadcast_notice(None, &notice).await?;
+
+            time::sleep(grace).await;
+        }
+
+        info!("Disconnecting everyone");
+        let goodbye = async {
+            // Close everything before the users go, the same order as a single user leaving
+            let games: Vec<u32> = DATABASE.games.iter().map(|g| g.id).collect();
+            let rooms: Vec<u32> = DATABASE.rooms.iter().map(|r| r.id).collect();
+            for id in games.into_iter().chain(rooms) {
+                let packet = WormsPacket::create(PacketCode::Close)
+                    .with_value_10(id)
+                    .build()?;
+                Server::broadcast_all(packet).await?;
+            }
+
+            let users: Vec<u32> = DATABASE.users.iter().map(|u| u.id).collect();
+            for id in users {
+                let packet = WormsPacket::create(PacketCode::DisconnectUser)
+                    .with_value_10(id)
+                    .build()?;
+                Server::broadcast_all(packet).await?;
+            }
+
+            DISCONNECT_TOKEN.cancel();
+            CONNECTIONS.close();
+            CONNECTIONS.wait().await;
+            Ok::<(), eyre::Error>(())
+        };
+
+        if time::timeout(Server::FLUSH_TIMEOUT, goodbye).await.is_err() {
+            warn!("Some connections didn't close in time, they were dropped");
+        }
+
         Ok(())
     }
 
-    async fn handle_connection(stream: TcpStream) -> Result<()> {
+    async fn send_to_user(user_id: u32, packet: Arc<Bytes>) {
+        let Some(user) = DATABASE.users.get(&user_id) else {
+            return;
+        };
+        if let Err(e) = user.send_packet(packet).await {
+            error!("Error sending packet to user {}: {:?}", user.name, e);
+        }
+    }
+
+    /// Runs a connection until it closes, `_session` counts it against its address' limits until
+    /// then.
+    async fn handle_connection(stream: TcpStream, _session: Session<'static>) -> Result<()> {
         let user_id;
 
-        let cancellation_token = SHUTDOWN_TOKEN.clone();
-        let rate_limiter = RateLimiter::direct(Quota::per_second(NonZeroU32::new(5).unwrap()));
+        let disconnect_token = DISCONNECT_TOKEN.clone();
+        let mut config = Config::current();
+        let mut rate_limiter =
+            RateLimiter::direct(Quota::per_second(config.limits.packets_per_second));
         let mut limited_count = 0;
-        const MAX_LIMITED_COUNT: u32 = 10;
 
         let sender_addr = stream.peer_addr()?;
 
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<Arc<Bytes>>(100);
-        let framed = Framed::new(stream, WormCodec);
+        let (tx, mut rx) =
+            tokio::sync::mpsc::channel::<Arc<Bytes>>(config.limits.outbound_queue_size.get());
+        let capture = Recorder::session(sender_addr);
+        let framed = Framed::new(stream, RecordingCodec::new(capture.clone()));
         let (mut sink, mut stream) = framed.split();
 
         let mut packets_to_send = Vec::with_capacity(50);
 
         // authorize the client
-        let packet = time::timeout(Server::UNAUTHORIZED_TTL, stream.next()).await?;
+        let packet = match time::timeout(config.server.unauthorized_ttl(), stream.next()).await {
+            Ok(packet) => packet,
+            Err(e) => {
+                METRICS.login_failed(LoginFailure::Timeout);
+                IP_LIMITS.login_failed(&config.limits, sender_addr.ip());
+                return Err(e.into());
+            }
+        };
         if let Some(Ok(ref packet)) = packet {
+            METRICS.packet_received(packet.header_code);
             if packet.header_code != PacketCode::Login {
+                METRICS.login_failed(LoginFailure::BadFirstPacket);
+                IP_LIMITS.login_failed(&config.limits, sender_addr.ip());
                 bail!("First packet must be a login packet");
             }
 
-            let login_result = Server::login_client(packet, &tx).await;
+            if let Err(refusal) = IP_LIMITS.login_attempt(&config.limits, sender_addr.ip()) {
+                METRICS.rate_limited(refusal.into());
+                let reason = match refusal {
+                    Refusal::Blocked => "Too many failed logins, try again later",
+                    _ => "Too many logins, try again in a minute",
+                };
+                Server::refuse_login(&tx, Server::LOGIN_REFUSED, Some(reason)).await?;
+                Server::flush_remaining(&mut rx, &mut sink, sender_addr).await;
+                bail!("Refused a login from {}: {:?}", sender_addr, refusal);
+            }
+
+            let login_result = Server::login_client(packet, &tx, sender_addr).await;
             match login_result {
                 Ok(id) => {
                     user_id = id;
+                    IP_LIMITS.login_succeeded(&config.limits, sender_addr.ip());
+                    if let Some(capture) = &capture {
+                        capture.set_user_id(id);
+                    }
                 }
                 Err(e) => {
                     error!("Error logging in: {}", e);
+                    IP_LIMITS.login_failed(&config.limits, sender_addr.ip());
+                    // Let the client know why
+                    Server::flush_remaining(&mut rx, &mut sink, sender_addr).await;
                     return Ok(());
                 }
             }
         } else {
+            if let Some(Err(_)) = packet {
+                METRICS.decode_error();
+            }
+            METRICS.login_failed(LoginFailure::BadFirstPacket);
+            IP_LIMITS.login_failed(&config.limits, sender_addr.ip());
             bail!("First packet must be a login packet");
         }
 
+        let Some(kick_token) = DATABASE.users.get(&user_id).map(|u| u.kick_token.clone()) else {
+            return Ok(());
+        };
+
         // main loop for the client connection handling packets and sending them out
         'client: loop {
             tokio::select! {
-                frame_result = time::timeout(Server::AUTHORIZED_TTL, stream.next()) => {
-                    // Limit packets to 5 per second
+                frame_result = time::timeout(config.server.authorized_ttl(), stream.next()) => {
+                    // Follow config reloads, keeping the limiter's state unless its quota changed
+                    let latest = Config::current();
+                    if latest.limits.packets_per_second != config.limits.packets_per_second {
+                        rate_limiter = RateLimiter::direct(Quota::per_second(
+                            latest.limits.packets_per_second,
+                        ));
+                    }
+                    config = latest;
+
+                    // Limit packets per second
                     if rate_limiter.check().is_err() {
                         limited_count += 1;
+                        METRICS.rate_limited(RateLimit::Connection);
 
                         // If the user sends too many packets, disconnect them
-                        if limited_count > MAX_LIMITED_COUNT {
+                        if limited_count > config.limits.max_limited_count {
                             error!("Rate limit exceeded for {}", sender_addr);
                             break 'client;
                         }
@@ -123,15 +310,15 @@ impl Server {
                     match frame_result {
                         Ok(Some(Ok(packet))) => {
                             debug!("Received Packet: {:?}", packet);
+                            METRICS.packet_received(packet.header_code);
 
                             if user_id < Database::ID_START {
                                 break 'client; // Disconnect invalid users
                             }
 
                             if let Err(e) = packet_handler::dispatch(
-                                packet.header_code,
                                 tx.clone(),
-                                packet.clone(),
+                                &packet,
                                 user_id,
                                 sender_addr,
                             ).await
@@ -142,6 +329,7 @@ impl Server {
                         }
                         Ok(Some(Err(e))) => {
                             error!("Error receiving packet: {}", e);
+                            METRICS.decode_error();
                             break 'client;
                         }
                         Ok(None) => break 'client, // Stream ended
@@ -157,6 +345,7 @@ impl Server {
                     if packet_count == 0 {
                         break 'client;
                     }
+                    METRICS.outbound_queue_depth(packet_count + rx.len());
 
                     // Drain and send each packet in the batch
                     // Sadly since some packets depends on order we can't parallelize this
@@ -174,7 +363,13 @@ impl Server {
                         break 'client;
                     }
                 },
-                () = cancellation_token.cancelled().fuse() => {
+                // The user's already been removed, send what's still queued before closing
+                () = kick_token.cancelled().fuse() => {
+                    Server::flush_remaining(&mut rx, &mut sink, sender_addr).await;
+                    return Ok(());
+                }
+                () = disconnect_token.cancelled().fuse() => {
+                    Server::flush_remaining(&mut rx, &mut sink, sender_addr).await;
                     return Ok(());
                 }
             }
@@ -184,35 +379,87 @@ impl Server {
         Ok(())
     }
 
-    async fn login_client(packet: &Arc<WormsPacket>, tx: &Sender<Arc<Bytes>>) -> Result<u32> {
-        let name = packet.name.as_ref().ok_or(eyre!("No name specified!"))?;
-        let session_nation = packet
-            .session
-            .as_ref()
-            .map(|s| s.nation)
-            .ok_or(eyre!("No nation specified!"))?;
-
-        if Database::check_user_exists(name) {
-            let packet = WormsPacket::create(PacketCode::LoginReply)
-                .with_value_1(0)
-                .with_error_code(1)
-                .build()?;
-            tx.send(packet).await?;
+    /// Sends everything queued for a connection that's about to close.
+    async fn flush_remaining<S>(rx: &mut Receiver<Arc<Bytes>>, sink: &mut S, address: SocketAddr)
+    where
+        S: Sink<Arc<Bytes>> + Unpin,
+        S::Error: Display,
+    {
+        rx.close();
+        while let Some(packet) = rx.recv().await {
+            if let Err(e) = sink.feed(packet).await {
+                debug!("Error sending last packets to {}: {}", address, e);
+                return;
+            }
+        }
+
+        if let Err(e) = sink.flush().await {
+            debug!("Error flushing last packets to {}: {}", address, e);
+        }
+    }
+
+    pub async fn login_client(
+        packet: &WormsPacket,
+        tx: &Sender<Arc<Bytes>>,
+        address: SocketAddr,
+    ) -> Result<u32> {
+        let request = LoginRequest::try_from(packet)
+            .inspect_err(|_| METRICS.login_failed(LoginFailure::BadFirstPacket))?;
+        let name = request.name_text();
+
+        if SHUTDOWN_TOKEN.is_cancelled() {
+            METRICS.login_failed(LoginFailure::ShuttingDown);
+            Server::refuse_login(tx, Server::LOGIN_REFUSED, None).await?;
+            bail!("Failed to login: Server is shutting down")
+        }
+
+        if let Some(ban) = BANS.find(Some(&name), address.ip()) {
+            METRICS.login_failed(LoginFailure::Banned);
+            Server::refuse_login(tx, Server::LOGIN_BANNED, Some(&ban.explanation())).await?;
+            bail!("Failed to login: '{}' from {} is banned", name, address)
+        }
+
+        if let Err(problem) = names::check(&name) {
+            METRICS.login_failed(LoginFailure::BadName);
+            let code = match problem {
+                NameProblem::TooShort(_) | NameProblem::TooLong(_) => Server::LOGIN_NAME_LENGTH,
+                NameProblem::Character(_) | NameProblem::Spacing => Server::LOGIN_NAME_CHARACTERS,
+                NameProblem::Reserved
+                | NameProblem::Registered(_)
+                | NameProblem::ReservedPrefix(_)
+                | NameProblem::Banned => Server::LOGIN_NAME_RESERVED,
+            };
+            Server::refuse_login(tx, code, Some(&problem.to_string())).await?;
+            bail!("Failed to login: '{}' refused, {}", name, problem)
+        }
+
+        if Database::check_user_exists(&name) {
+            METRICS.login_failed(LoginFailure::DuplicateName);
+            let reason = "Someone with that name, or one that looks like it, is online";
+            Server::refuse_login(tx, Server::LOGIN_NAME_TAKEN, Some(reason)).await?;
             bail!("Failed to login: Name already exists")
         }
 
         let new_id = Database::get_next_id();
-        let new_user = User::new(tx.clone().downgrade(), new_id, name, session_nation);
+        let new_user = User::new(
+            tx.clone().downgrade(),
+            new_id,
+            &name,
+            request.nation,
+            address,
+        );
 
         info!("User '{}' {} joined!", name, new_id);
+        METRICS.login();
 
         let packet = WormsPacket::create(PacketCode::Login)
             .with_value_1(new_id)
             .with_value_4(0)
-            .with_name(name)
+            .with_raw_name(request.name.clone())
             .with_session(&new_user.session)
             .build()?;
 
+        audit::record(Actor::of(&new_user), Event::Login);
         DATABASE.users.insert(new_id, new_user);
         Server::broadcast_all(packet).await?;
 
@@ -222,9 +469,30 @@ impl Server {
             .build()?;
         tx.send(packet).await?;
 
+        greetings::send_motd(tx, new_id).await?;
+
+        if NICKNAMES.is_registered(&name) {
+            nicknames::demand_identification(new_id).await?;
+        }
+
         Ok(new_id)
     }
 
+    /// Answers a login with an error code, explaining why in the chat first if there's a reason
+    /// the user should know.
+    async fn refuse_login(tx: &Sender<Arc<Bytes>>, code: u32, reason: Option<&str>) -> Result<()> {
+        if let Some(reason) = reason {
+            tx.send(bot::private_packet(0, reason)?).await?;
+        }
+
+        let packet = WormsPacket::create(PacketCode::LoginReply)
+            .with_value_1(0)
+            .with_error_code(code)
+            .build()?;
+        tx.send(packet).await?;
+        Ok(())
+    }
+
     async fn broadcast_all_with_filter<F>(packet: Arc<Bytes>, filter: F) -> Result<(), eyre::Error>
     where
         F: Fn(&u32) -> bool,
@@ -259,22 +527,21 @@ impl Server {
     }
 
     pub async fn disconnect_user(client_id: u32) -> Result<()> {
-        if client_id < Database::ID_START {
+        if client_id < Database::ID_START || bot::is_bot(client_id) {
             return Ok(());
         }
 
-        info!("Disconnecting User: '{}'", {
-            DATABASE
-                .users
-                .get(&client_id)
-                .map_or(client_id.to_string(), |u| u.name.to_string())
-        });
+        // Already disconnected, by a kick for example
+        let Some((_, old_user)) = DATABASE.users.remove(&client_id) else {
+            return Ok(());
+        };
+        info!("Disconnecting User: '{}'", old_user.name);
+        audit::record(Actor::of(&old_user), Event::Disconnect);
+        chat_filter::forget(&old_user.name);
 
         let mut left_id = client_id;
-        let old_user = DATABASE.users.remove(&client_id);
-
-        let (mut room_id, client_name) =
-            old_user.map_or((0, String::new()), |(_, u)| (u.room_id, u.name.clone()));
+        let (mut room_id, client_name) = (old_user.room_id, old_user.name.clone());
+        drop(old_user);
 
         DATABASE.games.retain(|cur_id, cur_game| {
             if cur_game.name == client_name {
@@ -315,12 +582,146 @@ impl Server {
         Ok(())
     }
 
+    /// Disconnects a user and closes their connection once what's queued for them is sent.
+    /// Returns false if there's no such user.
+    pub async fn kick_user(client_id: u32) -> Result<bool> {
+        if bot::is_bot(client_id) {
+            return Ok(false);
+        }
+        let Some(kick_token) = DATABASE.users.get(&client_id).map(|u| u.kick_token.clone()) else {
+            return Ok(false);
+        };
+
+        Server::disconnect_user(client_id).await?;
+        kick_token.cancel();
+        Ok(true)
+    }
+
+    /// Removes a game the same way as when its host disconnects. Returns false if there's no such
+    /// game.
+    pub async fn close_game(game_id: u32) -> Result<bool> {
+        let Some((_, game)) = DATABASE.games.remove(&game_id) else {
+            return Ok(false);
+        };
+        debug!("Removing Game '{}'", game.name);
+
+        let host_id = DATABASE
+            .users
+            .iter()
+            .find(|u| u.name == game.name)
+            .map_or(0, |u| u.id);
+        let room_id = game.room_id;
+        drop(game);
+
+        let packet = WormsPacket::create(PacketCode::Leave)
+            .with_value_2(game_id)
+            .with_value_10(host_id)
+            .build()?;
+        Server::broadcast_all(packet).await?;
+        let packet = WormsPacket::create(PacketCode::Close)
+            .with_value_10(game_id)
+            .build()?;
+        Server::broadcast_all(packet).await?;
+
+        Server::leave_room(room_id, game_id).await?;
+        Ok(true)
+    }
+
+    /// Closes a room with its games, moving the users in it back out. A permanent room is taken out
+    /// of the rooms file as well. Returns false if there's no such room.
+    pub async fn close_room(room_id: u32) -> Result<bool> {
+        if !DATABASE.rooms.contains_key(&room_id) {
+            return Ok(false);
+        }
+
+        let games: Vec<u32> = DATABASE
+            .games
+            .iter()
+            .filter(|g| g.room_id == room_id)
+            .map(|g| g.id)
+            .collect();
+        for game_id in games {
+            Server::close_game(game_id).await?;
+        }
+
+        let users: Vec<u32> = DATABASE
+            .users
+            .iter()
+            .filter(|u| u.room_id == room_id)
+            .map(|u| u.id)
+            .collect();
+        for user_id in users {
+            Server::remove_from_room(room_id, user_id).await?;
+        }
+
+        if let Some((_, room)) = DATABASE.rooms.remove(&room_id) {
+            debug!("Removed room '{}'", room.name);
+            // Closed by the operator, it doesn't come back
+            if room.permanent {
+                PERMANENT_ROOMS.save()?;
+            }
+        }
+        let packet = WormsPacket::create(PacketCode::Close)
+            .with_value_10(room_id)
+            .build()?;
+        Server::broadcast_all(packet).await?;
+
+        Ok(true)
+    }
+
+    /// Announces a message from the bot to everyone in a room, or to every user without one.
+    /// Returns how many users it was sent to.
+    pub async fn broadcast_notice(room_id: Option<u32>, message: &str) -> Result<usize> {
+        let users: Vec<(u32, u32)> = DATABASE
+            .users
+            .iter()
+            .filter(|u| !bot::is_bot(u.id) && room_id.is_none_or(|room_id| u.room_id == room_id))
+            .map(|u| (u.id, u.room_id))
+            .collect();
+
+        for (user_id, room_id) in &users {
+            let packet = bot::chat_packet(*room_id, message)?;
+            Server::send_to_user(*user_id, packet).await;
+        }
+
+        Ok(users.len())
+    }
+
+    /// Moves a user out of a room they didn't choose to leave, telling them as well as everyone
+    /// else. Games they host there are closed, as when they disconnect.
+    pub async fn remove_from_room(room_id: u32, user_id: u32) -> Result<()> {
+        let Some(name) = DATABASE.users.get(&user_id).map(|u| u.name.clone()) else {
+            return Ok(());
+        };
+        let games: Vec<u32> = DATABASE
+            .games
+            .iter()
+            .filter(|g| g.room_id == room_id && g.name == name)
+            .map(|g| g.id)
+            .collect();
+        for game_id in games {
+            Server::close_game(game_id).await?;
+        }
+
+        if let Some(mut user) = DATABASE.users.get_mut(&user_id) {
+            user.room_id = 0;
+        }
+        let packet = WormsPacket::create(PacketCode::Leave)
+            .with_value_2(room_id)
+            .with_value_10(user_id)
+            .build()?;
+        Server::broadcast_all(packet).await
+    }
+
     pub async fn leave_room(room_id: u32, left_id: u32) -> Result<()> {
-        let room_exists = DATABASE.rooms.contains_key(&room_id);
+        let (room_exists, permanent) = DATABASE
+            .rooms
+            .get(&room_id)
+            .map_or((false, false), |room| (true, room.permanent));
 
         // Close an abandoned room.
         let room_abandoned = {
-            if room_exists {
+            if room_exists && !permanent && Config::current().rooms.remove_when_empty {
                 let any_users_connected = DATABASE
                     .users
                     .iter()
@@ -343,6 +744,10 @@ impl Server {
             }
         }
 
+        if room_exists && !room_abandoned {
+            rooms::pass_ownership(room_id, left_id).await?;
+        }
+
         // Notify users
         if room_exists {
             let packet = WormsPacket::create(PacketCode::Leave)